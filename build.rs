@@ -2,6 +2,9 @@ fn main() {
     use std::env;
     use std::path::PathBuf;
     println!("cargo:rerun-if-changed=src/ui/uifile.fl");
+    if env::var_os("CARGO_FEATURE_GUI").is_none() {
+        return;
+    }
     let g = fl2rust::Generator::default();
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
     g.in_out(
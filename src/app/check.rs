@@ -0,0 +1,55 @@
+use super::Application;
+use crate::{check::check_document, types::Message};
+use fltk::prelude::*;
+
+impl Application {
+    /// Connect widgets of the check panel
+    pub fn init_check_panel(&mut self) {
+        let (main_sender, _) = self.main_channel.unwrap();
+        let ui = &mut self.ui;
+        ui.check_btn.emit(main_sender, Message::CheckDocument);
+        ui.check_results.set_column_char('\t');
+        ui.check_results.set_column_widths(&[300]);
+        ui.check_results.emit(main_sender, Message::OpenProblem);
+    }
+
+    /// Check the current document and list its problems in the check panel
+    pub fn check_document(&mut self) {
+        _ = self.ui.main_tabs.set_value(&self.ui.check_group);
+        self.problems = check_document(&self.document);
+        let browser = &mut self.ui.check_results;
+        browser.clear();
+        // "@." stops interpreting the rest of the column as formatting
+        for problem in &self.problems {
+            browser.add(&format!("@.{}\t@.{}", problem.path, problem.description()));
+        }
+        if self.problems.is_empty() {
+            browser.add("@.No problems found");
+        }
+        browser.redraw();
+    }
+
+    /// Select the node of the clicked problem
+    pub fn open_problem(&mut self) {
+        let line = self.ui.check_results.value();
+        let Some(problem) = (line > 0)
+            .then(|| self.problems.get(line as usize - 1))
+            .flatten()
+            .cloned()
+        else {
+            return;
+        };
+        if self.is_node_editing || self.document.clone().get_node(problem.node_id).is_none() {
+            return;
+        }
+        _ = self.ui.main_tabs.set_value(&self.ui.document_group);
+        self.update_document_tree(Some(problem.node_id));
+    }
+
+    /// Remove problems of the check panel
+    pub fn clear_problems(&mut self) {
+        self.problems.clear();
+        self.ui.check_results.clear();
+        self.ui.check_results.redraw();
+    }
+}
@@ -0,0 +1,106 @@
+use fltk::{
+    app, button::ShortcutButton, dialog, enums::*, prelude::*, tree::TreeItem, window::Window,
+};
+use std::fs;
+
+/// Creates an array of paths of children nodes, '/' in labels is escaped as in
+/// `node_to_tree_vec`
+pub fn get_tree_item_path(tree_item: TreeItem, last_vec: Vec<String>) -> Vec<String> {
+    let mut result = last_vec.clone();
+    if !tree_item.is_root() {
+        result.push(tree_item.label().unwrap().replace('/', "\\/"));
+        if let Some(parent) = tree_item.parent() {
+            result.extend(get_tree_item_path(parent, last_vec.clone()));
+        }
+    }
+    result
+}
+
+pub enum SaveDialogAnswer {
+    Yes,
+    No,
+    Cancel,
+}
+
+/// Saves the file and additional files (e.g. images) next to it
+pub fn save_format_dialog(name: String, bytes: Vec<u8>, files: Vec<(String, Vec<u8>)>) {
+    let mut nfc = dialog::NativeFileChooser::new(dialog::FileDialogType::BrowseSaveFile);
+    nfc.set_filter("");
+    nfc.set_preset_file(&name);
+    nfc.show();
+    let filename = nfc.filename();
+    if !filename.to_string_lossy().is_empty() {
+        if let Err(e) = fs::write(&filename, bytes) {
+            dialog::alert_default(&e.to_string());
+            return;
+        }
+        if let Some(directory) = filename.parent() {
+            for (name, bytes) in files {
+                if let Err(e) = fs::write(directory.join(&name), bytes) {
+                    dialog::alert_default(&format!("Can't write {name}: {e}"));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+pub fn open_format_dialog(filter: &str) -> Option<Vec<u8>> {
+    let mut nfc = dialog::NativeFileChooser::new(dialog::FileDialogType::BrowseFile);
+    nfc.set_filter(filter);
+    nfc.show();
+    let filename = nfc.filename();
+    if !filename.to_string_lossy().is_empty() {
+        match fs::read(filename) {
+            Ok(bytes) => return Some(bytes),
+            Err(e) => dialog::alert_default(&e.to_string()),
+        }
+    }
+    None
+}
+
+pub fn ask_save_dialog(text: &str) -> SaveDialogAnswer {
+    if let Some(answer) = dialog::choice2_default(text, "Yes", "No", "Cancel") {
+        if answer == 2 {
+            SaveDialogAnswer::Cancel
+        } else if answer == 0 {
+            SaveDialogAnswer::Yes
+        } else {
+            SaveDialogAnswer::No
+        }
+    } else {
+        SaveDialogAnswer::Cancel
+    }
+}
+
+pub fn ask_shortcut(start_shortcut: Shortcut) -> Shortcut {
+    let (s, r) = app::channel::<bool>();
+    let mut clicks = 0;
+    let mut win = Window::new(100, 100, 300, 200, "Shortcut");
+    let mut btn = ShortcutButton::default().with_size(win.w(), win.h());
+    win.make_modal(true);
+    btn.set_label_size(30);
+    btn.set_down_frame(fltk::enums::FrameType::NoBox);
+    btn.set_label("Click to start");
+    win.end();
+    win.show();
+    btn.handle(move |e, ev| {
+        if ev == Event::Push {
+            clicks += 1;
+            if clicks == 2 {
+                s.send(true);
+            } else {
+                e.window().unwrap().set_label("Click to end");
+                e.set_value(start_shortcut);
+            }
+        }
+        false
+    });
+    while app::wait() && win.visible() {
+        if r.recv().is_some() {
+            win.hide();
+            return btn.value();
+        }
+    }
+    start_shortcut
+}
@@ -1,18 +1,28 @@
-use std::{fs, path::PathBuf};
+use std::{collections::BTreeMap, fs, path::PathBuf};
 
-use super::Application;
-use crate::{formats, types::*, utils::*};
+use super::{dialogs::*, Application, DocumentTab};
+use crate::{
+    cli, formats,
+    text_processor::{
+        extract_document, rewrite_pasted_references, rewrite_references, with_rewritten_references,
+    },
+    types::*,
+    utils::*,
+};
 use fltk::{prelude::*, *};
 
 impl Application {
     /// Update document tree in UI
     pub fn update_document_tree(&mut self, node_to_select: Option<u32>) {
+        let collapsed = self.collapsed_tree_items();
         let tree = &mut self.ui.main_tree;
         tree.clear();
         let tree_items = node_to_tree_vec(self.document.root_node.clone(), Vec::new(), None);
         for (k, __) in tree_items.clone() {
             tree.add(k.as_str());
         }
+        self.collapse_tree_items(&collapsed);
+        let tree = &mut self.ui.main_tree;
         tree.redraw();
         let cloned_tree_items = tree_items.clone();
         let (main_sender, _) = self.main_channel.unwrap();
@@ -41,12 +51,93 @@ impl Application {
         }
     }
 
+    /// Paths of tree items with hidden children
+    pub fn collapsed_tree_items(&self) -> Vec<String> {
+        let tree = &self.ui.main_tree;
+        let mut paths = Vec::new();
+        let mut next = tree.first();
+        while let Some(item) = next {
+            if item.has_children() && item.is_close() && !item.is_root() {
+                let mut path = get_tree_item_path(item.clone(), Vec::new());
+                path.reverse();
+                paths.push(path.join("/"));
+            }
+            next = tree.next(&item);
+        }
+        paths
+    }
+
+    /// Hide children of tree items by their paths
+    pub fn collapse_tree_items(&mut self, paths: &[String]) {
+        for path in paths {
+            if let Some(mut item) = self.ui.main_tree.find_item(path) {
+                item.close();
+            }
+        }
+        self.ui.main_tree.redraw();
+    }
+
     /// Set document file
     pub fn set_document_file(&mut self, path: PathBuf) {
+        self.main_settings.add_recent_document(path.clone());
+        self.update_recent_documents_menu();
         self.document_file_path = Some(path);
         self.update_window_title();
     }
 
+    /// Update File/Recent menu, documents that don't exist anymore are removed
+    pub fn update_recent_documents_menu(&mut self) {
+        self.main_settings.prune_recent_documents();
+        let (main_sender, _) = self.main_channel.unwrap();
+        let menubar = &mut self.ui.main_menubar;
+        let index = menubar.find_index("&File/Recent");
+        if index < 0 || menubar.clear_submenu(index).is_err() {
+            return;
+        }
+        let recent_documents = &self.main_settings.recent_documents;
+        for (i, path) in recent_documents.iter().enumerate() {
+            let flag = if i + 1 == recent_documents.len() {
+                menu::MenuFlag::MenuDivider
+            } else {
+                menu::MenuFlag::Normal
+            };
+            let item_index = menubar.add_emit(
+                &format!("&File/Recent/{i}"),
+                enums::Shortcut::None,
+                flag,
+                main_sender,
+                Message::OpenRecentDocument(i),
+            );
+            // Paths are set as labels after adding because '/' separates submenus
+            if let Some(mut item) = menubar.at(item_index) {
+                item.set_label(&path.to_string_lossy().replace('&', "&&").replace('@', "@@"));
+            }
+        }
+        let clear_index = menubar.add_emit(
+            "&File/Recent/Clear recent",
+            enums::Shortcut::None,
+            menu::MenuFlag::Normal,
+            main_sender,
+            Message::ClearRecentDocuments,
+        );
+        if recent_documents.is_empty() {
+            if let Some(mut item) = menubar.at(clear_index) {
+                item.deactivate();
+            }
+        }
+    }
+
+    /// Open the recent document by its index in File/Recent
+    pub fn open_recent_document(&mut self, index: usize) {
+        let Some(path) = self.main_settings.recent_documents.get(index).cloned() else {
+            return;
+        };
+        if self.is_node_editing && self.save_editing_node() && self.node_started_editing {
+        } else if !self.open_document_file(path) {
+            self.update_recent_documents_menu();
+        }
+    }
+
     /// Save node
     pub fn save_editing_node(&mut self) -> bool {
         let need_to_save = self.node_started_editing;
@@ -67,6 +158,7 @@ impl Application {
                             name: name.clone(),
                             content: self.ui.main_text_editor.buffer().unwrap().text(),
                             children: current_editing_node.clone().children,
+                            metadata: current_editing_node.clone().metadata,
                         };
                         if find_node_with_same_name_in_same_parent_node(
                             self.clone().document.root_node,
@@ -75,10 +167,13 @@ impl Application {
                         )
                         .is_some()
                         {
-                            self.document = self.history_manager.register_document_modification(
-                                self.document.clone(),
-                                DocumentModification::EditNode(id, new_node),
-                            );
+                            let mut modification = DocumentModification::EditNode(id, new_node);
+                            // References to the node and its children keep pointing to them
+                            if name != current_editing_node.name {
+                                modification =
+                                    with_rewritten_references(&self.document, modification);
+                            }
+                            self.modify_document(modification);
                             self.update_document_tree(Some(current_editing_node.clone().id));
                         } else {
                             dialog::alert_default(&format!("There is already a node named {name}"));
@@ -112,30 +207,112 @@ impl Application {
         true
     }
 
-    /// Open document
+    /// Open document in a new tab
     pub fn open_document(&mut self) {
         if self.is_node_editing && self.save_editing_node() && self.node_started_editing {
-        } else if self.save_document_dialog() {
+        } else {
             let mut nfc = dialog::NativeFileChooser::new(dialog::FileDialogType::BrowseFile);
             nfc.set_filter(RTD_FILTER);
             nfc.show();
             let filename = nfc.filename();
             if !filename.to_string_lossy().is_empty() {
-                if let Ok(bytes) = fs::read(&filename) {
-                    match formats::rtd::deserealize(bytes) {
-                        Ok(doc) => {
-                            self.document = formats::rtd::rtd_document_to_document(doc);
-                            self.set_document_file(filename);
-                            self.is_saved = true;
-                            self.update_document_tree(None);
-                            self.update_resources(None);
-                            self.update_window_title();
+                self.open_document_file(filename);
+            }
+        }
+    }
+
+    /// Open document file in a new tab, or select its tab if it is already open.
+    /// Documents in other formats (.json, .opml, .org) are opened as new documents
+    pub fn open_document_file(&mut self, path: PathBuf) -> bool {
+        if let Some(index) = self.find_tab(&path) {
+            self.select_tab(index);
+            return true;
+        }
+        match cli::load_document(&path) {
+            Ok(document) => {
+                let is_rtd = path
+                    .extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("rtd"));
+                if !self.open_tab(DocumentTab::new(document, None)) {
+                    return false;
+                }
+                if is_rtd {
+                    self.set_document_file(path);
+                    self.is_saved = true;
+                    self.update_window_title();
+                }
+                true
+            }
+            Err(e) => {
+                dialog::alert_default(&e);
+                false
+            }
+        }
+    }
+
+    /// Open the document and select the node given by the command-line arguments
+    pub fn open_arguments(&mut self, args: &[String]) {
+        let arguments = match cli::open_arguments(args) {
+            Ok(arguments) => arguments,
+            Err(e) => return dialog::alert_default(&e),
+        };
+        let Some(path) = arguments.document else {
+            return;
+        };
+        if !self.open_document_file(path) {
+            return;
+        }
+        if let Some(node) = arguments.node {
+            match cli::find_node(&self.document, &node) {
+                Ok(id) => self.update_document_tree(Some(id)),
+                Err(e) => dialog::alert_default(&e),
+            }
+        }
+    }
+
+    /// Import node from another format as a new document or into the selected node
+    pub fn import_node(&mut self, import_type: TreeImportType) {
+        let (is_document, filter) = match import_type {
+            TreeImportType::OpmlDoc => (true, OPML_FILTER),
+            TreeImportType::OpmlNode => (false, OPML_FILTER),
+            TreeImportType::JsonDoc => (true, JSON_FILTER),
+            TreeImportType::JsonNode => (false, JSON_FILTER),
+            TreeImportType::OrgDoc => (true, ORG_FILTER),
+            TreeImportType::OrgNode => (false, ORG_FILTER),
+        };
+        if let Some(bytes) = open_format_dialog(filter) {
+            let text = String::from_utf8_lossy(&bytes).to_string();
+            let result = match import_type {
+                TreeImportType::OpmlDoc | TreeImportType::OpmlNode => {
+                    formats::opml::opml_to_node(text).map(|root_node| Document {
+                        root_node,
+                        resources: BTreeMap::new(),
+                    })
+                }
+                TreeImportType::JsonDoc | TreeImportType::JsonNode => {
+                    formats::json::json_to_document(text)
+                }
+                TreeImportType::OrgDoc | TreeImportType::OrgNode => formats::org::org_to_node(text)
+                    .map(|root_node| Document {
+                        root_node,
+                        resources: BTreeMap::new(),
+                    }),
+            };
+            match result {
+                Ok(document) => {
+                    if is_document {
+                        if !self.open_tab(DocumentTab::new(document, None)) {
+                            return;
                         }
-                        Err(e) => dialog::alert_default(&e.to_string()),
+                    } else {
+                        let id = self.get_selected_node_id().unwrap_or(self.current_node_id);
+                        self.paste_document(document, id);
+                        self.update_document_tree(Some(id));
                     }
-                } else {
-                    dialog::alert_default("Can't read file");
+                    self.update_resources(None);
+                    self.set_unsaved();
                 }
+                Err(e) => dialog::alert_default(&e),
             }
         }
     }
@@ -214,10 +391,7 @@ impl Application {
     /// Creating new node
     pub fn create_new_node(&mut self) {
         if let Some(id) = self.get_selected_node_id() {
-            self.document = self.history_manager.register_document_modification(
-                self.document.clone(),
-                DocumentModification::CreateNode(id, Node::generate_id()),
-            );
+            self.modify_document(DocumentModification::CreateNode(id, Node::generate_id()));
             let tree = &mut self.ui.main_tree;
             _ = tree.deselect_all(&tree.first_selected_item().unwrap(), true);
             self.update_document_tree(Some(id));
@@ -227,10 +401,7 @@ impl Application {
     /// Deleting selected node
     pub fn delete_node(&mut self) {
         if let Some(id) = self.get_selected_node_id() {
-            self.document = self.history_manager.register_document_modification(
-                self.document.clone(),
-                DocumentModification::DeleteNode(id),
-            );
+            self.modify_document(DocumentModification::DeleteNode(id));
             let tree = &mut self.ui.main_tree;
             _ = tree.deselect_all(&tree.first_selected_item().unwrap(), true);
             self.update_document_tree(None);
@@ -240,10 +411,7 @@ impl Application {
     /// Move node up
     pub fn move_node_up(&mut self) {
         if let Some(id) = self.get_selected_node_id() {
-            self.document = self.history_manager.register_document_modification(
-                self.document.clone(),
-                DocumentModification::MoveNode(id, MoveDirection::Up),
-            );
+            self.modify_document(DocumentModification::MoveNode(id, MoveDirection::Up));
             let tree = &mut self.ui.main_tree;
             _ = tree.deselect_all(&tree.first_selected_item().unwrap(), true);
             self.update_document_tree(Some(id));
@@ -253,10 +421,7 @@ impl Application {
     /// Move node down
     pub fn move_node_down(&mut self) {
         if let Some(id) = self.get_selected_node_id() {
-            self.document = self.history_manager.register_document_modification(
-                self.document.clone(),
-                DocumentModification::MoveNode(id, MoveDirection::Down),
-            );
+            self.modify_document(DocumentModification::MoveNode(id, MoveDirection::Down));
             let tree = &mut self.ui.main_tree;
             _ = tree.deselect_all(&tree.first_selected_item().unwrap(), true);
             self.update_document_tree(Some(id));
@@ -266,52 +431,122 @@ impl Application {
     /// Cut node
     pub fn cut_node(&mut self) {
         if let Some(id) = self.get_selected_node_id() {
-            if let Some(node) = &self.document.clone().get_node(id) {
-                self.current_copied_node = Some(randomize_node(node));
-                self.document = self.history_manager.register_document_modification(
-                    self.document.clone(),
-                    DocumentModification::DeleteNode(id),
-                );
+            if let Some(node) = self.document.clone().get_node(id) {
+                self.copied_document = extract_document(&self.document, id);
+                self.cut_from = get_node_parent(self.document.root_node.clone(), id)
+                    .map(|parent| (self.document.root_node.id, parent, node));
+                self.modify_document(DocumentModification::DeleteNode(id));
                 self.update_document_tree(None);
             }
         }
     }
 
-    /// Copy node
+    /// Copy node with the resources it uses
     pub fn copy_node(&mut self) {
         if let Some(id) = self.get_selected_node_id() {
-            if let Some(node) = &self.document.clone().get_node(id) {
-                self.current_copied_node = Some(randomize_node(node));
-            }
+            self.copied_document = extract_document(&self.document, id);
+            self.cut_from = None;
         }
     }
 
-    /// Paste node
+    /// Paste node, resources it uses are added to the document (renamed if there are
+    /// different resources with the same names). Pasting a cut node in its document moves
+    /// it, so references to it are rewritten in the same action
     pub fn paste_node(&mut self) {
-        if let Some(copied) = self.clone().current_copied_node {
+        if let Some(copied) = self.copied_document.clone() {
             if let Some(id) = self.get_selected_node_id() {
-                self.document = self.history_manager.register_document_modification(
-                    self.document.clone(),
-                    DocumentModification::PasteNode(copied.clone(), id),
-                );
-                self.update_document_tree(Some(copied.id));
+                let cut = self.cut_from.take().filter(|(root, parent, _)| {
+                    *root == self.document.root_node.id
+                        && find_node_ref(&self.document.root_node, *parent).is_some()
+                });
+                let node_id = match cut {
+                    Some((_, parent, cut_node)) => {
+                        // The node is pasted with its references as they were in the document
+                        let original = Document {
+                            root_node: cut_node.clone(),
+                            ..copied
+                        };
+                        let (mut modifications, node) = self.paste_modifications(original, id);
+                        let before = modify_document(
+                            self.document.clone(),
+                            DocumentModification::PasteNode(cut_node.clone(), parent),
+                        );
+                        let after = modify_document(
+                            self.document.clone(),
+                            DocumentModification::Batch(modifications.clone()),
+                        );
+                        let ids = map_node_ids(&cut_node, &node);
+                        modifications.extend(rewrite_references(&before, &after, &ids));
+                        self.modify_document(DocumentModification::Batch(modifications));
+                        node.id
+                    }
+                    None => self.paste_document(copied, id),
+                };
+                self.update_document_tree(Some(node_id));
+                self.update_resources(None);
             }
         }
     }
 
+    /// Paste the root node of another document into the node with id, with the resources
+    /// it uses, as one action. References between the pasted nodes point to them with
+    /// their new ids and paths. Returns the new id of the pasted node
+    pub fn paste_document(&mut self, document: Document, id: u32) -> u32 {
+        let (mut modifications, node) = self.paste_modifications(document.clone(), id);
+        let after = modify_document(
+            self.document.clone(),
+            DocumentModification::Batch(modifications.clone()),
+        );
+        let ids = map_node_ids(&document.root_node, &node);
+        modifications.extend(rewrite_pasted_references(&document, &after, &ids, node.id));
+        self.modify_document(DocumentModification::Batch(modifications));
+        node.id
+    }
+
+    /// Modifications that paste the root node of another document into the node with id
+    /// and add the resources it uses, and the pasted node with new ids
+    fn paste_modifications(
+        &self,
+        document: Document,
+        id: u32,
+    ) -> (Vec<DocumentModification>, Node) {
+        let mut merged = self.document.clone();
+        let node = randomize_node(&merge_resources(&mut merged, document));
+        let mut modifications = merged
+            .resources
+            .into_iter()
+            .filter(|(name, _)| !self.document.resources.contains_key(name))
+            .map(|(name, res)| DocumentModification::AddResource(name, res))
+            .collect::<Vec<_>>();
+        modifications.push(DocumentModification::PasteNode(node.clone(), id));
+        (modifications, node)
+    }
+
+    /// Apply the modification as an undoable action and update the index
+    pub fn modify_document(&mut self, modification: DocumentModification) {
+        self.document = self
+            .history_manager
+            .register_document_modification(self.document.clone(), modification.clone());
+        self.index.apply(&self.document, &modification);
+    }
+
     /// Undo action
     pub fn undo_action(&mut self) {
-        if let Some(doc) = self.history_manager.undo_action(self.document.clone()) {
+        if let Some((doc, modification)) = self.history_manager.undo_action(self.document.clone()) {
             self.document = doc;
+            self.index.apply(&self.document, &modification);
             self.update_document_tree(Some(self.current_node_id));
+            self.update_resources(None);
         }
     }
 
     /// Redo action
     pub fn redo_action(&mut self) {
-        if let Some(doc) = self.history_manager.redo_action(self.document.clone()) {
+        if let Some((doc, modification)) = self.history_manager.redo_action(self.document.clone()) {
             self.document = doc;
+            self.index.apply(&self.document, &modification);
             self.update_document_tree(Some(self.current_node_id));
+            self.update_resources(None);
         }
     }
 }
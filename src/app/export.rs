@@ -0,0 +1,155 @@
+use super::{dialogs::save_format_dialog, Application};
+use crate::{
+    formats::{self, ExportFormat, ExportOptions},
+    types::TreeExportType,
+};
+use fltk::{
+    app,
+    button::{Button, CheckButton, ReturnButton},
+    dialog,
+    input::{Input, IntInput, MultilineInput},
+    menu::Choice,
+    prelude::*,
+    window::Window,
+};
+
+impl Application {
+    /// Asks export options and exports the selected node or the whole document.
+    /// The options are remembered for the format
+    pub fn export_node(&mut self, export_type: TreeExportType) {
+        let format = export_type.format();
+        let node = if export_type.is_document() {
+            self.document.root_node.clone()
+        } else {
+            self.document
+                .clone()
+                .get_node(self.current_node_id)
+                .unwrap_or(self.document.root_node.clone())
+        };
+        let options = self
+            .main_settings
+            .export_options
+            .get(&format)
+            .cloned()
+            .unwrap_or_default();
+        let Some(options) = ask_export_options(format, options) else {
+            return;
+        };
+        self.main_settings
+            .export_options
+            .insert(format, options.clone());
+        match formats::export_node(&self.document, node.id, format, &options) {
+            Ok(exported) => save_format_dialog(
+                node.name + format.extension(),
+                exported.bytes,
+                exported.files,
+            ),
+            Err(e) => dialog::alert_default(&e),
+        }
+    }
+}
+
+/// Asks options of exporting to the format, `None` if canceled.
+/// Options the format doesn't support are disabled
+fn ask_export_options(format: ExportFormat, options: ExportOptions) -> Option<ExportOptions> {
+    let mut win = Window::new(100, 100, 400, 410, None);
+    win.set_label(&format!("Export to {}", format.name()));
+    win.make_modal(true);
+
+    let mut table_of_contents = CheckButton::new(150, 10, 240, 25, "Table of contents");
+    table_of_contents.set_checked(options.table_of_contents);
+    if !format.has_table_of_contents() {
+        table_of_contents.deactivate();
+    }
+    let mut numbering = CheckButton::new(150, 40, 240, 25, "Number headings");
+    numbering.set_checked(options.numbering);
+    if !format.has_numbering() {
+        numbering.deactivate();
+    }
+    let mut max_depth = IntInput::new(150, 70, 240, 25, "Depth limit");
+    max_depth.set_value(&options.max_depth.unwrap_or(0).to_string());
+    max_depth.set_tooltip("Levels of children to export, 0 exports all of them");
+    let mut excluded_paths = MultilineInput::new(150, 100, 240, 75, "Excluded nodes");
+    excluded_paths.set_value(&options.excluded_paths.join("\n"));
+    excluded_paths.set_tooltip("Paths of nodes (Root/Node/Child), one per line");
+    let mut excluded_tags = Input::new(150, 185, 240, 25, "Excluded tags");
+    excluded_tags.set_value(&options.excluded_tags.join(" "));
+    excluded_tags.set_tooltip("Nodes with these tags are not exported, separated by spaces");
+
+    let handlings = format.resource_handlings();
+    let mut resources = Choice::new(150, 220, 240, 25, "Resources");
+    for handling in handlings {
+        resources.add_choice(handling.name());
+    }
+    if let Some(handling) = options.resource_handling(format) {
+        resources.set_value(handlings.iter().position(|h| *h == handling).unwrap_or(0) as i32);
+    } else {
+        resources.deactivate();
+    }
+
+    let mut wrap_width = IntInput::new(150, 255, 240, 25, "Wrap width");
+    wrap_width.set_value(&options.wrap_width.to_string());
+    wrap_width.set_tooltip("Maximum line length in characters, 0 disables wrapping");
+    let mut convert_to_markdown = CheckButton::new(150, 290, 240, 25, "Convert HTML to Markdown");
+    convert_to_markdown.set_checked(options.convert_to_markdown);
+    convert_to_markdown.set_tooltip("Otherwise the content is HTML (that works in GFM)");
+    let mut language = Input::new(150, 320, 240, 25, "Language");
+    language.set_value(&options.language);
+    language.set_tooltip("Language of the text as a tag (en, pt-BR)");
+    if format != ExportFormat::Text {
+        wrap_width.deactivate();
+    }
+    if format != ExportFormat::Markdown {
+        convert_to_markdown.deactivate();
+    }
+    if format != ExportFormat::Epub {
+        language.deactivate();
+    }
+
+    let mut export_button = ReturnButton::new(190, 370, 100, 30, "Export");
+    let mut cancel_button = Button::new(295, 370, 95, 30, "Cancel");
+    win.end();
+    win.show();
+
+    let (s, r) = app::channel::<bool>();
+    export_button.emit(s, true);
+    cancel_button.emit(s, false);
+    while app::wait() && win.visible() {
+        if let Some(export) = r.recv() {
+            win.hide();
+            if !export {
+                return None;
+            }
+            return Some(ExportOptions {
+                table_of_contents: table_of_contents.is_checked(),
+                max_depth: max_depth
+                    .value()
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|depth| *depth > 0),
+                excluded_paths: excluded_paths
+                    .value()
+                    .lines()
+                    .map(|path| path.trim().to_string())
+                    .filter(|path| !path.is_empty())
+                    .collect(),
+                excluded_tags: excluded_tags
+                    .value()
+                    .split(|c: char| c.is_whitespace() || c == ':' || c == ',')
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                numbering: numbering.is_checked(),
+                resources: handlings
+                    .get(resources.value().max(0) as usize)
+                    .copied()
+                    .unwrap_or(options.resources),
+                wrap_width: wrap_width.value().trim().parse().unwrap_or(0),
+                convert_to_markdown: convert_to_markdown.is_checked(),
+                language: language.value().trim().to_string(),
+            });
+        }
+    }
+    None
+}
@@ -1,6 +1,10 @@
 use crate::{
+    check::Problem,
     history_manager::HistoryManager,
+    index::DocumentIndex,
+    search::{highlight_html, SearchMatch},
     settings::{MainSettings, UIElement, UIElementType},
+    text_processor::{self, Backlink},
     types::*,
     ui,
 };
@@ -12,11 +16,25 @@ use fltk::{
     *,
 };
 use fltk_theme::{ThemeType, WidgetTheme};
-use std::{collections::BTreeMap, path::PathBuf};
+use regex::Regex;
+use std::path::PathBuf;
 
+mod check;
+pub mod dialogs;
 mod document;
+mod export;
+mod quick_open;
+mod references;
+mod replace;
 mod resources;
-pub mod text_processor;
+mod search;
+mod session;
+mod tabs;
+
+pub use tabs::DocumentTab;
+
+/// Name of the anchor before the highlighted search match in the node view
+const SEARCH_HIT_ANCHOR: &str = "rustree-search-hit";
 
 #[derive(Clone)]
 pub struct Application {
@@ -31,38 +49,71 @@ pub struct Application {
     pub is_saved: bool,
     pub document_file_path: Option<PathBuf>,
     pub resources_manager: ResourcesManager,
-    pub current_copied_node: Option<Node>,
+    /// Copied node with the resources it uses, it can be pasted in any tab
+    pub copied_document: Option<Document>,
+    /// Root of the document and parent the copied node was cut from, and the node before
+    /// it was copied. Pasting it there again is a move that keeps references to it
+    pub cut_from: Option<(u32, u32, Node)>,
     pub main_settings: MainSettings,
+    /// Open documents, the current one is stored when another tab is selected
+    pub tabs: Vec<DocumentTab>,
+    pub current_tab: usize,
+    /// Results of the search panel and the pattern they were found with
+    pub search_results: Vec<SearchMatch>,
+    pub search_pattern: Option<Regex>,
+    /// Node and index of the match highlighted in the node view
+    pub search_highlight: Option<(u32, usize)>,
+    /// Visited nodes of the current document, most recent first
+    pub recent_nodes: Vec<u32>,
+    /// Index of names and text of the current document, updated with its modifications
+    pub index: DocumentIndex,
+    /// Nodes that reference the current node, listed in the backlinks panel
+    pub backlinks: Vec<Backlink>,
+    /// Problems of the document listed in the check panel
+    pub problems: Vec<Problem>,
+}
+
+impl Default for Application {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Application {
-    /// Run Rustree
-    pub fn run(&mut self) {
-        init_ui(self);
+    /// Run Rustree with the document and node given by the command-line arguments
+    pub fn run(&mut self, args: &[String]) {
+        init_ui(self, args);
         self.app.run().unwrap();
     }
 
     /// Create application from configuration
     pub fn new() -> Self {
         let app = App::default();
-        let document = Document {
-            root_node: Node::new("New document".to_string(), String::new(), Vec::new()),
-            resources: BTreeMap::new(),
-        };
+        let tab = DocumentTab::new_document();
         let mut application = Self {
             app,
             ui: ui::UserInterface::make_window(),
             is_node_editing: false,
-            document: document.clone(),
-            current_node_id: document.root_node.id,
+            document: tab.document.clone(),
+            current_node_id: tab.current_node_id,
             main_channel: None,
             history_manager: HistoryManager::new(),
             is_saved: false,
             document_file_path: None,
             node_started_editing: false,
             resources_manager: ResourcesManager::new(),
-            current_copied_node: None,
+            copied_document: None,
+            cut_from: None,
             main_settings: MainSettings::new(),
+            current_tab: 0,
+            search_results: Vec::new(),
+            search_pattern: None,
+            search_highlight: None,
+            recent_nodes: Vec::new(),
+            index: tab.index.clone(),
+            backlinks: Vec::new(),
+            problems: Vec::new(),
+            tabs: vec![tab],
         };
         application.update_window_title();
         application
@@ -114,6 +165,7 @@ impl Application {
                 "Untitled"
             }
         ));
+        self.update_current_tab_label();
     }
 
     /// Set node view value
@@ -145,7 +197,23 @@ impl Application {
             },
         );
 
-        self.ui.main_text_view.set_value(&processed_text);
+        match (&self.search_highlight, &self.search_pattern) {
+            (Some((id, occurrence)), Some(pattern)) if *id == self.current_node_id => {
+                self.ui.main_text_view.set_value(&highlight_html(
+                    &processed_text,
+                    pattern,
+                    *occurrence,
+                    SEARCH_HIT_ANCHOR,
+                ));
+                self.ui
+                    .main_text_view
+                    .set_top_line_string(SEARCH_HIT_ANCHOR);
+            }
+            _ => {
+                self.search_highlight = None;
+                self.ui.main_text_view.set_value(&processed_text);
+            }
+        }
     }
 }
 
@@ -162,10 +230,17 @@ pub fn init_menubar(app: &mut Application) {
         mut quit,
         mut save_as,
         mut save,
+        mut new,
         mut open,
+        mut close_tab,
         mut settings_btn,
         mut undo,
         mut redo,
+        mut find,
+        mut replace,
+        mut quick_open,
+        mut complete_reference,
+        mut check_document,
     ) = (
         menubar.find_item("&Edit/Move node up").unwrap(),
         menubar.find_item("&Edit/Move node down").unwrap(),
@@ -174,10 +249,17 @@ pub fn init_menubar(app: &mut Application) {
         menubar.find_item("&File/Quit").unwrap(),
         menubar.find_item("&File/Save as").unwrap(),
         menubar.find_item("&File/Save").unwrap(),
+        menubar.find_item("&File/New").unwrap(),
         menubar.find_item("&File/Open").unwrap(),
+        menubar.find_item("&File/Close tab").unwrap(),
         menubar.find_item("&File/Settings").unwrap(),
         menubar.find_item("&Edit/Undo").unwrap(),
         menubar.find_item("&Edit/Redo").unwrap(),
+        menubar.find_item("&Edit/Find").unwrap(),
+        menubar.find_item("&Edit/Replace").unwrap(),
+        menubar.find_item("&Edit/Quick open").unwrap(),
+        menubar.find_item("&Edit/Complete reference").unwrap(),
+        menubar.find_item("&Edit/Check document").unwrap(),
     );
 
     move_node_up.emit(main_sender, Message::MoveNodeUp);
@@ -185,11 +267,18 @@ pub fn init_menubar(app: &mut Application) {
     quit.emit(main_sender, Message::Quit);
     save_as.emit(main_sender, Message::SaveAs);
     save.emit(main_sender, Message::SaveDocument);
+    new.emit(main_sender, Message::NewDocument);
     open.emit(main_sender, Message::OpenDocument);
+    close_tab.emit(main_sender, Message::CloseTab);
     create_node.emit(main_sender, Message::CreateNode);
     delete_node.emit(main_sender, Message::DeleteNode);
     undo.emit(main_sender, Message::Undo);
     redo.emit(main_sender, Message::Redo);
+    find.emit(main_sender, Message::ShowSearch);
+    replace.emit(main_sender, Message::ShowReplace);
+    quick_open.emit(main_sender, Message::QuickOpen);
+    complete_reference.emit(main_sender, Message::CompleteReference);
+    check_document.emit(main_sender, Message::CheckDocument);
 
     let (
         mut export_doc_txt,
@@ -201,6 +290,18 @@ pub fn init_menubar(app: &mut Application) {
         mut export_node_rtd,
         mut export_doc_json,
         mut export_node_json,
+        mut export_doc_opml,
+        mut export_node_opml,
+        mut export_doc_epub,
+        mut export_node_epub,
+        mut export_doc_latex,
+        mut export_node_latex,
+        mut export_doc_docx,
+        mut export_node_docx,
+        mut export_doc_pdf,
+        mut export_node_pdf,
+        mut export_doc_org,
+        mut export_node_org,
     ) = (
         menubar
             .find_item("&File/Export/Document/Text file")
@@ -213,6 +314,18 @@ pub fn init_menubar(app: &mut Application) {
         menubar.find_item("&File/Export/Node/Rtd").unwrap(),
         menubar.find_item("&File/Export/Document/JSON").unwrap(),
         menubar.find_item("&File/Export/Node/JSON").unwrap(),
+        menubar.find_item("&File/Export/Document/OPML").unwrap(),
+        menubar.find_item("&File/Export/Node/OPML").unwrap(),
+        menubar.find_item("&File/Export/Document/EPUB").unwrap(),
+        menubar.find_item("&File/Export/Node/EPUB").unwrap(),
+        menubar.find_item("&File/Export/Document/LaTeX").unwrap(),
+        menubar.find_item("&File/Export/Node/LaTeX").unwrap(),
+        menubar.find_item("&File/Export/Document/DOCX").unwrap(),
+        menubar.find_item("&File/Export/Node/DOCX").unwrap(),
+        menubar.find_item("&File/Export/Document/PDF").unwrap(),
+        menubar.find_item("&File/Export/Node/PDF").unwrap(),
+        menubar.find_item("&File/Export/Document/Org").unwrap(),
+        menubar.find_item("&File/Export/Node/Org").unwrap(),
     );
 
     export_doc_txt.emit(main_sender, Message::ExportNode(TreeExportType::TextDoc));
@@ -224,18 +337,63 @@ pub fn init_menubar(app: &mut Application) {
     export_node_rtd.emit(main_sender, Message::ExportNode(TreeExportType::RtdNode));
     export_node_json.emit(main_sender, Message::ExportNode(TreeExportType::JsonNode));
     export_doc_json.emit(main_sender, Message::ExportNode(TreeExportType::JsonDoc));
+    export_node_opml.emit(main_sender, Message::ExportNode(TreeExportType::OpmlNode));
+    export_doc_opml.emit(main_sender, Message::ExportNode(TreeExportType::OpmlDoc));
+    export_node_epub.emit(main_sender, Message::ExportNode(TreeExportType::EpubNode));
+    export_doc_epub.emit(main_sender, Message::ExportNode(TreeExportType::EpubDoc));
+    export_node_latex.emit(main_sender, Message::ExportNode(TreeExportType::LatexNode));
+    export_doc_latex.emit(main_sender, Message::ExportNode(TreeExportType::LatexDoc));
+    export_node_docx.emit(main_sender, Message::ExportNode(TreeExportType::DocxNode));
+    export_doc_docx.emit(main_sender, Message::ExportNode(TreeExportType::DocxDoc));
+    export_node_pdf.emit(main_sender, Message::ExportNode(TreeExportType::PdfNode));
+    export_doc_pdf.emit(main_sender, Message::ExportNode(TreeExportType::PdfDoc));
+    export_node_org.emit(main_sender, Message::ExportNode(TreeExportType::OrgNode));
+    export_doc_org.emit(main_sender, Message::ExportNode(TreeExportType::OrgDoc));
+
+    let (
+        mut import_doc_opml,
+        mut import_node_opml,
+        mut import_doc_json,
+        mut import_node_json,
+        mut import_doc_org,
+        mut import_node_org,
+    ) = (
+        menubar.find_item("&File/Import/Document/OPML").unwrap(),
+        menubar.find_item("&File/Import/Node/OPML").unwrap(),
+        menubar.find_item("&File/Import/Document/JSON").unwrap(),
+        menubar.find_item("&File/Import/Node/JSON").unwrap(),
+        menubar.find_item("&File/Import/Document/Org").unwrap(),
+        menubar.find_item("&File/Import/Node/Org").unwrap(),
+    );
+
+    import_doc_opml.emit(main_sender, Message::ImportNode(TreeImportType::OpmlDoc));
+    import_node_opml.emit(main_sender, Message::ImportNode(TreeImportType::OpmlNode));
+    import_doc_json.emit(main_sender, Message::ImportNode(TreeImportType::JsonDoc));
+    import_node_json.emit(main_sender, Message::ImportNode(TreeImportType::JsonNode));
+    import_doc_org.emit(main_sender, Message::ImportNode(TreeImportType::OrgDoc));
+    import_node_org.emit(main_sender, Message::ImportNode(TreeImportType::OrgNode));
 
     settings_btn.emit(main_sender, Message::OpenSettings);
 
     let shortcuts_manager = &mut app.main_settings.shortcuts_manager;
 
+    shortcuts_manager.register_ui_element(&mut UIElement::MenuItem(new), UIElementType::NewFile);
     shortcuts_manager.register_ui_element(&mut UIElement::MenuItem(open), UIElementType::OpenFile);
+    shortcuts_manager
+        .register_ui_element(&mut UIElement::MenuItem(close_tab), UIElementType::CloseTab);
     shortcuts_manager.register_ui_element(&mut UIElement::MenuItem(save), UIElementType::SaveFile);
     shortcuts_manager
         .register_ui_element(&mut UIElement::MenuItem(save_as), UIElementType::SaveAsFile);
     shortcuts_manager.register_ui_element(&mut UIElement::MenuItem(quit), UIElementType::QuitApp);
     shortcuts_manager.register_ui_element(&mut UIElement::MenuItem(undo), UIElementType::Undo);
     shortcuts_manager.register_ui_element(&mut UIElement::MenuItem(redo), UIElementType::Redo);
+    shortcuts_manager.register_ui_element(&mut UIElement::MenuItem(find), UIElementType::Find);
+    shortcuts_manager
+        .register_ui_element(&mut UIElement::MenuItem(replace), UIElementType::Replace);
+    shortcuts_manager.register_ui_element(
+        &mut UIElement::MenuItem(quick_open),
+        UIElementType::QuickOpen,
+    );
 
     shortcuts_manager.register_ui_element(
         &mut UIElement::MenuItem(create_node),
@@ -253,6 +411,8 @@ pub fn init_menubar(app: &mut Application) {
         &mut UIElement::MenuItem(move_node_down),
         UIElementType::DownNodeBtn,
     );
+
+    app.update_recent_documents_menu();
 }
 
 /// Initialize buttons
@@ -295,7 +455,7 @@ fn init_buttons(app: &mut Application) {
 }
 
 /// Initialize user interface
-fn init_ui(main_app: &mut Application) {
+fn init_ui(main_app: &mut Application, args: &[String]) {
     let ch = app::channel::<Message>();
     let (main_sender, _main_receiver) = app::channel::<Message>();
 
@@ -311,6 +471,10 @@ fn init_ui(main_app: &mut Application) {
     init_menubar(main_app);
     init_buttons(main_app);
 
+    main_app.update_document_tabs();
+    main_app.init_search_panel();
+    main_app.init_backlinks_panel();
+    main_app.init_check_panel();
     main_app.update_document_tree(None);
     main_app.update_resources(None);
 
@@ -335,8 +499,11 @@ fn init_ui(main_app: &mut Application) {
     ui.node_name_input
         .emit(main_sender, Message::NodeStartedEditing);
 
+    let backlinks_browser = ui.backlinks_browser.clone();
     right_main_tile_group.handle(move |_, e| {
-        if e == Event::Push && app::event_clicks() {
+        // Double clicks in the backlinks panel open backlinks
+        if e == Event::Push && app::event_clicks() && !app::event_inside_widget(&backlinks_browser)
+        {
             main_sender.send(Message::ToggleEditMode);
         }
         false
@@ -344,5 +511,7 @@ fn init_ui(main_app: &mut Application) {
 
     ui.window.show();
     main_app.apply_settings();
+    main_app.restore_session();
+    main_app.open_arguments(args);
     main_app.event_loop();
 }
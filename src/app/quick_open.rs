@@ -0,0 +1,130 @@
+use super::Application;
+use crate::search::fuzzy_find_nodes;
+use fltk::{
+    app,
+    browser::HoldBrowser,
+    enums::{CallbackTrigger, Event, Key},
+    input::Input,
+    prelude::*,
+    window::Window,
+};
+
+/// Visited nodes remembered to rank them higher in quick open
+const RECENT_NODES_LENGTH: usize = 50;
+/// Matching nodes listed in quick open
+const QUICK_OPEN_RESULTS: usize = 200;
+
+#[derive(Clone, Copy)]
+enum QuickOpenAction {
+    Filter,
+    Open,
+}
+
+impl Application {
+    /// Remember the node as the most recently visited one
+    pub fn add_recent_node(&mut self, id: u32) {
+        self.recent_nodes.retain(|recent| *recent != id);
+        self.recent_nodes.insert(0, id);
+        self.recent_nodes.truncate(RECENT_NODES_LENGTH);
+    }
+
+    /// Asks for a node by a fuzzy match of its path and selects it
+    pub fn quick_open(&mut self) {
+        if !self.finish_node_editing() {
+            return;
+        }
+        let paths = self.index.paths();
+        // The current node is already open, so the previous one is offered first
+        let recent_nodes = self
+            .recent_nodes
+            .iter()
+            .copied()
+            .filter(|id| *id != self.current_node_id)
+            .collect::<Vec<_>>();
+
+        let main_window = &self.ui.window;
+        let mut win = Window::new(
+            main_window.x() + (main_window.w() - 600) / 2,
+            main_window.y() + 60,
+            600,
+            400,
+            "Quick open",
+        );
+        win.make_modal(true);
+        let mut input = Input::new(10, 10, 580, 25, None);
+        input.set_tooltip("Letters of the node path in order, Enter opens the selected node");
+        let mut browser = HoldBrowser::new(10, 45, 580, 345, None);
+        win.end();
+        win.show();
+        _ = input.take_focus();
+
+        let (s, r) = app::channel::<QuickOpenAction>();
+        input.set_trigger(CallbackTrigger::Changed);
+        input.emit(s, QuickOpenAction::Filter);
+        input.handle({
+            let mut browser = browser.clone();
+            move |_, event| {
+                if event != Event::KeyDown {
+                    return false;
+                }
+                let line = browser.value();
+                match app::event_key() {
+                    Key::Up if line > 1 => browser.select(line - 1),
+                    Key::Down if line < browser.size() => browser.select(line + 1),
+                    Key::Enter | Key::KPEnter => s.send(QuickOpenAction::Open),
+                    Key::Up | Key::Down => {}
+                    _ => return false,
+                }
+                true
+            }
+        });
+        browser.set_callback(move |_| {
+            if app::event_clicks() {
+                s.send(QuickOpenAction::Open);
+            }
+        });
+
+        let mut found = Vec::new();
+        s.send(QuickOpenAction::Filter);
+        while app::wait() && win.visible() {
+            match r.recv() {
+                Some(QuickOpenAction::Filter) => {
+                    found = fuzzy_find_nodes(&paths, &input.value(), &recent_nodes);
+                    found.truncate(QUICK_OPEN_RESULTS);
+                    browser.clear();
+                    // "@." stops interpreting the path as formatting
+                    for (path, _) in &found {
+                        browser.add(&format!("@.{path}"));
+                    }
+                    if !found.is_empty() {
+                        browser.select(1);
+                    }
+                    browser.redraw();
+                }
+                Some(QuickOpenAction::Open) => {
+                    let line = browser.value();
+                    if line < 1 {
+                        continue;
+                    }
+                    win.hide();
+                    let (path, id) = found[line as usize - 1].clone();
+                    _ = self.ui.main_tabs.set_value(&self.ui.document_group);
+                    self.update_document_tree(Some(id));
+                    // Deep nodes may be hidden by collapsed parents
+                    let tree = &mut self.ui.main_tree;
+                    if let Some(item) = tree.find_item(&path) {
+                        let mut parent = item.parent();
+                        while let Some(mut p) = parent {
+                            p.open();
+                            parent = p.parent();
+                        }
+                        tree.show_item_middle(&item);
+                        tree.redraw();
+                    }
+                    return;
+                }
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use super::Application;
+use crate::{text_processor::find_backlinks, types::Message};
+use fltk::{
+    enums::Shortcut,
+    menu::{MenuButton, MenuFlag},
+    prelude::*,
+};
+use regex::Regex;
+
+/// Completions shown in the menu, the rest are left out
+const COMPLETIONS_LENGTH: usize = 40;
+
+impl Application {
+    /// Connect the backlinks panel
+    pub fn init_backlinks_panel(&mut self) {
+        let (main_sender, _) = self.main_channel.unwrap();
+        let browser = &mut self.ui.backlinks_browser;
+        browser.set_column_char('\t');
+        browser.set_column_widths(&[200]);
+        browser.emit(main_sender, Message::OpenBacklink);
+    }
+
+    /// List nodes that reference the current node in the backlinks panel
+    pub fn update_backlinks(&mut self) {
+        self.backlinks = find_backlinks(&self.document, self.current_node_id);
+        let browser = &mut self.ui.backlinks_browser;
+        browser.clear();
+        // "@." stops interpreting the rest of the column as formatting
+        for backlink in &self.backlinks {
+            browser.add(&format!("@.{}\t@.{}", backlink.path, backlink.snippet));
+        }
+        if self.backlinks.is_empty() {
+            browser.add("@.No nodes reference this node");
+        }
+        browser.redraw();
+    }
+
+    /// Select the node of the clicked backlink
+    pub fn open_backlink(&mut self) {
+        let line = self.ui.backlinks_browser.value();
+        let Some(backlink) = (line > 0)
+            .then(|| self.backlinks.get(line as usize - 1))
+            .flatten()
+            .cloned()
+        else {
+            return;
+        };
+        if self.is_node_editing || self.document.clone().get_node(backlink.node_id).is_none() {
+            return;
+        }
+        self.update_document_tree(Some(backlink.node_id));
+    }
+
+    /// Complete the path of the reference at the cursor of the editor from paths of the
+    /// document. Outside of a reference a new one is inserted
+    pub fn complete_reference(&mut self) {
+        if !self.is_node_editing {
+            return;
+        }
+        let mut editor = self.ui.main_text_editor.clone();
+        let Some(mut buffer) = editor.buffer() else {
+            return;
+        };
+        let cursor = editor.insert_position();
+        let text = buffer.text_range(0, cursor).unwrap_or_default();
+        // The argument of the unfinished reference before the cursor
+        let reference = Regex::new(r#"reference!\("([^"]*)$"#)
+            .unwrap()
+            .captures(&text)
+            .map(|captures| captures.get(1).unwrap());
+        let (start, prefix) = match reference {
+            Some(argument) => match argument.as_str().strip_prefix("path:") {
+                Some(path) => (argument.start(), path.trim_start().to_string()),
+                None if argument.as_str().trim().is_empty() => (argument.start(), String::new()),
+                // Other kinds of references are not completed
+                None => return,
+            },
+            None => (cursor as usize, String::new()),
+        };
+        let completions = self.index.complete_path(&prefix);
+        if completions.is_empty() {
+            return;
+        }
+
+        let (x, y) = editor.position_to_xy(cursor);
+        let mut menu = MenuButton::new(x, y, 0, editor.text_size() + 4, None);
+        for completion in completions.iter().take(COMPLETIONS_LENGTH) {
+            let index = menu.add("item", Shortcut::None, MenuFlag::Normal, |_| {});
+            // Paths are set as labels after adding because '/' separates submenus
+            if let Some(mut item) = menu.at(index) {
+                item.set_label(&completion.replace('&', "&&").replace('@', "@@"));
+            }
+        }
+        if completions.len() > COMPLETIONS_LENGTH {
+            let index = menu.add("…", Shortcut::None, MenuFlag::Inactive, |_| {});
+            if let Some(mut item) = menu.at(index) {
+                item.set_label(&format!("{} more…", completions.len() - COMPLETIONS_LENGTH));
+            }
+        }
+        self.ui.window.add(&menu);
+        let chosen = menu
+            .popup()
+            .and_then(|_| completions.get(menu.value() as usize));
+        self.ui.window.remove(&menu);
+        let Some(path) = chosen else {
+            MenuButton::delete(menu);
+            return;
+        };
+        MenuButton::delete(menu);
+
+        let rest = buffer
+            .text_range(cursor, buffer.length())
+            .unwrap_or_default();
+        // A '"' would end the reference, so such nodes are referenced by their ids
+        let argument = match self.index.paths().into_iter().find(|(p, _)| p == path) {
+            Some((_, id)) if path.contains('"') => format!("id:{id}"),
+            _ => format!("path:{path}"),
+        };
+        let mut completed = match reference {
+            Some(_) => argument,
+            None => format!("reference!(\"{argument}"),
+        };
+        if !rest.starts_with("\")") {
+            completed.push_str("\")");
+        }
+        buffer.replace(start as i32, cursor, &completed);
+        // The cursor goes after the closing `")`
+        editor.set_insert_position(start as i32 + completed.len() as i32);
+        if rest.starts_with("\")") {
+            editor.set_insert_position(editor.insert_position() + 2);
+        }
+        editor.show_insert_position();
+        self.node_started_editing = true;
+        self.set_unsaved();
+    }
+}
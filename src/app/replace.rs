@@ -0,0 +1,208 @@
+use super::Application;
+use crate::{
+    search::{
+        find_replacements, replace_modification, search_pattern, Replacement, SearchField,
+        SearchOptions,
+    },
+    text_processor::with_rewritten_references,
+    types::{Document, Node},
+    utils::{find_node_with_same_name_in_same_parent_node, modify_document, DocumentModification},
+};
+use fltk::{
+    app,
+    browser::CheckBrowser,
+    button::{Button, CheckButton, ReturnButton},
+    dialog,
+    enums::Align,
+    frame::Frame,
+    input::Input,
+    prelude::*,
+    window::Window,
+};
+
+#[derive(Clone, Copy)]
+enum ReplaceAction {
+    Preview,
+    AcceptAll,
+    RejectAll,
+    Replace,
+    Cancel,
+}
+
+impl Application {
+    /// Asks what to find and replace in the document, previews the changes and applies
+    /// the accepted ones as a single action
+    pub fn show_replace(&mut self) {
+        if !self.finish_node_editing() {
+            return;
+        }
+        let query = self.ui.search_input.value();
+        let Some(replacements) = ask_replacements(&self.document, &query) else {
+            return;
+        };
+        let Some(modification) = replace_modification(&self.document, &replacements) else {
+            return;
+        };
+        if let Err(e) = check_replaced_names(&self.document, &modification) {
+            return dialog::alert_default(&e);
+        }
+        // Renamed nodes keep their references
+        self.modify_document(with_rewritten_references(&self.document, modification));
+        // Results and highlighting of the search panel point to the old text
+        self.clear_search_results();
+        self.update_document_tree(Some(self.current_node_id));
+        self.set_unsaved();
+    }
+}
+
+/// Checks that names of the nodes stay non-empty and unique among their siblings
+fn check_replaced_names(
+    document: &Document,
+    modification: &DocumentModification,
+) -> Result<(), String> {
+    let DocumentModification::Batch(modifications) = modification else {
+        return Ok(());
+    };
+    let replaced = modify_document(document.clone(), modification.clone());
+    for modification in modifications {
+        let DocumentModification::EditNode(id, Node { name, .. }) = modification else {
+            continue;
+        };
+        if name.trim().is_empty() {
+            return Err("Node name can't be empty".to_string());
+        }
+        if find_node_with_same_name_in_same_parent_node(
+            replaced.root_node.clone(),
+            *id,
+            name.clone(),
+        )
+        .is_none()
+        {
+            return Err(format!("There is already a node named {name}"));
+        }
+    }
+    Ok(())
+}
+
+/// Asks what to find and replace in the document and which of the changes to accept,
+/// `None` if canceled
+fn ask_replacements(document: &Document, query: &str) -> Option<Vec<Replacement>> {
+    let mut win = Window::new(100, 100, 640, 520, "Replace");
+    win.make_modal(true);
+
+    let mut find_input = Input::new(110, 10, 520, 25, "Find");
+    find_input.set_value(query);
+    let mut replace_input = Input::new(110, 40, 520, 25, "Replace with");
+    replace_input.set_tooltip("$1 or $name are groups of a regular expression, $$ is $");
+    let case_sensitive = CheckButton::new(110, 70, 130, 25, "Case sensitive");
+    let whole_word = CheckButton::new(245, 70, 110, 25, "Whole word");
+    let regex = CheckButton::new(360, 70, 170, 25, "Regular expression");
+    let names = CheckButton::new(110, 100, 130, 25, "Node names");
+    names.set_checked(true);
+    let content = CheckButton::new(245, 100, 130, 25, "Node content");
+    content.set_checked(true);
+    let mut preview_button = Button::new(530, 100, 100, 25, "Preview");
+
+    let mut changes = CheckBrowser::new(10, 135, 620, 305, None);
+    changes.set_tooltip("Unchecked changes are not made");
+    let mut status = Frame::new(10, 445, 620, 25, None);
+    status.set_align(Align::Left | Align::Inside);
+
+    let mut accept_all_button = Button::new(10, 480, 100, 30, "Accept all");
+    let mut reject_all_button = Button::new(115, 480, 100, 30, "Reject all");
+    let mut replace_button = ReturnButton::new(430, 480, 100, 30, "Replace");
+    let mut cancel_button = Button::new(535, 480, 95, 30, "Cancel");
+    win.end();
+    win.show();
+
+    let (s, r) = app::channel::<ReplaceAction>();
+    preview_button.emit(s, ReplaceAction::Preview);
+    accept_all_button.emit(s, ReplaceAction::AcceptAll);
+    reject_all_button.emit(s, ReplaceAction::RejectAll);
+    replace_button.emit(s, ReplaceAction::Replace);
+    cancel_button.emit(s, ReplaceAction::Cancel);
+
+    // Inputs of the listed replacements, Replace previews again if they are changed
+    let mut previewed = None;
+    let mut replacements = Vec::new();
+    while app::wait() && win.visible() {
+        let Some(action) = r.recv() else {
+            continue;
+        };
+        let inputs = (
+            find_input.value(),
+            replace_input.value(),
+            case_sensitive.is_checked(),
+            whole_word.is_checked(),
+            regex.is_checked(),
+            names.is_checked(),
+            content.is_checked(),
+        );
+        match action {
+            ReplaceAction::Replace if previewed.as_ref() == Some(&inputs) => {
+                win.hide();
+                return Some(
+                    replacements
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(index, _)| changes.checked(*index as i32 + 1))
+                        .map(|(_, replacement)| replacement)
+                        .collect(),
+                );
+            }
+            ReplaceAction::Preview | ReplaceAction::Replace => {
+                let options = SearchOptions {
+                    case_sensitive: inputs.2,
+                    whole_word: inputs.3,
+                    regex: inputs.4,
+                };
+                let pattern = match search_pattern(&inputs.0, options) {
+                    Ok(pattern) => pattern,
+                    Err(e) => {
+                        dialog::alert_default(&e);
+                        continue;
+                    }
+                };
+                let mut fields = Vec::new();
+                if inputs.5 {
+                    fields.push(SearchField::Name);
+                }
+                if inputs.6 {
+                    fields.push(SearchField::Content);
+                }
+                replacements =
+                    find_replacements(&document.root_node, &pattern, &inputs.1, inputs.4, &fields);
+                changes.clear();
+                for replacement in &replacements {
+                    let field = match replacement.field {
+                        SearchField::Name => "name",
+                        SearchField::Content => "content",
+                    };
+                    changes.add(
+                        &format!("{} ({field}): {}", replacement.path, replacement.preview),
+                        true,
+                    );
+                }
+                status.set_label(&format!(
+                    "{} changes, uncheck the ones that shouldn't be made",
+                    replacements.len()
+                ));
+                changes.redraw();
+                previewed = Some(inputs);
+            }
+            ReplaceAction::AcceptAll => {
+                changes.check_all();
+                changes.redraw();
+            }
+            ReplaceAction::RejectAll => {
+                changes.check_none();
+                changes.redraw();
+            }
+            ReplaceAction::Cancel => {
+                win.hide();
+                return None;
+            }
+        }
+    }
+    None
+}
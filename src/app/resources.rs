@@ -1,7 +1,7 @@
 use super::Application;
-use crate::types::*;
+use crate::{types::*, utils::DocumentModification};
 use fltk::{prelude::*, *};
-use std::{collections::BTreeMap, fs};
+use std::fs;
 
 impl Application {
     /// Update resources
@@ -36,8 +36,10 @@ impl Application {
                     filename.file_name().unwrap().to_str().unwrap(),
                 ) {
                     if !self.document.clone().resources.contains_key(&res_name) {
-                        let res = Resource::new(bytes);
-                        self.document.resources.insert(res_name.clone(), res);
+                        self.modify_document(DocumentModification::AddResource(
+                            res_name.clone(),
+                            Resource::new(bytes),
+                        ));
                         self.update_resources(Some(res_name));
                     } else {
                         dialog::alert_default(&format!("Resource {} already exists", res_name));
@@ -50,13 +52,9 @@ impl Application {
     /// Delete selected resources
     pub fn delete_resources(&mut self) {
         if let Some(items) = self.ui.resources_tree.get_selected_items() {
-            self.document.resources = self
-                .document
-                .clone()
-                .resources
-                .into_iter()
-                .filter(|(s, _)| !items.iter().any(|i| i.label().unwrap() == *s))
-                .collect::<BTreeMap<_, _>>();
+            for item in items {
+                self.modify_document(DocumentModification::DeleteResource(item.label().unwrap()));
+            }
             self.update_resources(None);
         }
     }
@@ -93,13 +91,13 @@ impl Application {
                 let filename = nfc.filename();
                 if !filename.to_string_lossy().is_empty() {
                     if let Ok(bytes) = fs::read(filename.clone()) {
-                        self.document.resources.insert(
+                        self.modify_document(DocumentModification::AddResource(
                             label.clone(),
                             Resource {
                                 bytes,
                                 ..res.clone()
                             },
-                        );
+                        ));
                         self.update_resources(Some(label.to_string()));
                     }
                 }
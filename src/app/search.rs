@@ -0,0 +1,88 @@
+use super::Application;
+use crate::{
+    search::{search_pattern, SearchField, SearchOptions},
+    types::Message,
+};
+use fltk::{dialog, enums::CallbackTrigger, prelude::*};
+
+impl Application {
+    /// Connect widgets of the search panel
+    pub fn init_search_panel(&mut self) {
+        let (main_sender, _) = self.main_channel.unwrap();
+        let ui = &mut self.ui;
+        ui.search_input.set_trigger(CallbackTrigger::EnterKeyAlways);
+        ui.search_input.emit(main_sender, Message::Search);
+        ui.search_btn.emit(main_sender, Message::Search);
+        ui.search_results.set_column_char('\t');
+        ui.search_results.set_column_widths(&[300]);
+        ui.search_results
+            .emit(main_sender, Message::OpenSearchResult);
+    }
+
+    /// Show the search panel and focus the query
+    pub fn show_search(&mut self) {
+        _ = self.ui.main_tabs.set_value(&self.ui.search_group);
+        _ = self.ui.search_input.take_focus();
+    }
+
+    /// Search the current document by the query of the search panel
+    pub fn search(&mut self) {
+        let ui = &self.ui;
+        let options = SearchOptions {
+            case_sensitive: ui.search_case_sensitive.is_checked(),
+            whole_word: ui.search_whole_word.is_checked(),
+            regex: ui.search_regex.is_checked(),
+        };
+        let query = ui.search_input.value();
+        let pattern = match search_pattern(&query, options) {
+            Ok(pattern) => pattern,
+            Err(e) => return dialog::alert_default(&e),
+        };
+        self.search_results = self.index.search(&query, options, &pattern);
+        self.search_pattern = Some(pattern);
+        self.search_highlight = None;
+
+        let browser = &mut self.ui.search_results;
+        browser.clear();
+        // "@." stops interpreting the rest of the column as formatting
+        for result in &self.search_results {
+            let snippet = match result.field {
+                SearchField::Name => "(name)".to_string(),
+                SearchField::Content => result.snippet.clone(),
+            };
+            browser.add(&format!("@.{}\t@.{snippet}", result.path));
+        }
+        if self.search_results.is_empty() {
+            browser.add("@.No matches");
+        }
+        browser.redraw();
+    }
+
+    /// Select the node of the clicked search result and highlight the match
+    pub fn open_search_result(&mut self) {
+        let line = self.ui.search_results.value();
+        let Some(result) = (line > 0)
+            .then(|| self.search_results.get(line as usize - 1))
+            .flatten()
+            .cloned()
+        else {
+            return;
+        };
+        if self.is_node_editing || self.document.clone().get_node(result.node_id).is_none() {
+            return;
+        }
+        self.search_highlight =
+            (result.field == SearchField::Content).then_some((result.node_id, result.occurrence));
+        _ = self.ui.main_tabs.set_value(&self.ui.document_group);
+        self.update_document_tree(Some(result.node_id));
+    }
+
+    /// Remove results of the search panel
+    pub fn clear_search_results(&mut self) {
+        self.search_results.clear();
+        self.search_pattern = None;
+        self.search_highlight = None;
+        self.ui.search_results.clear();
+        self.ui.search_results.redraw();
+    }
+}
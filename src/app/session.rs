@@ -0,0 +1,105 @@
+use super::Application;
+use crate::{
+    settings::{Session, SessionDocument},
+    utils::node_to_tree_vec,
+};
+use fltk::prelude::*;
+
+impl Application {
+    /// Documents of tabs and the layout of the window
+    pub fn session(&self) -> Session {
+        let documents = (0..self.tabs.len())
+            .filter_map(|index| {
+                let tab = if index == self.current_tab {
+                    self.current_tab_state()
+                } else {
+                    self.tabs[index].clone()
+                };
+                let path = tab.document_file_path?;
+                let selected_node = node_to_tree_vec(tab.document.root_node, Vec::new(), None)
+                    .into_iter()
+                    .find(|(_, id)| *id == tab.current_node_id)
+                    .map(|(path, _)| path);
+                Some((
+                    index,
+                    SessionDocument {
+                        path,
+                        selected_node,
+                        collapsed_nodes: tab.collapsed_nodes,
+                    },
+                ))
+            })
+            .collect::<Vec<_>>();
+        let ui = &self.ui;
+        Session {
+            // Documents without files are left out, so the index can be different
+            current_document: documents
+                .iter()
+                .position(|(index, _)| *index >= self.current_tab)
+                .unwrap_or(0),
+            documents: documents.into_iter().map(|(_, doc)| doc).collect(),
+            main_tab: ui.main_tabs.value().map_or(0, |g| ui.main_tabs.find(&g)),
+            window: Some((ui.window.x(), ui.window.y(), ui.window.w(), ui.window.h())),
+            tree_width: Some(ui.tree_group.w()),
+        }
+    }
+
+    /// Restore the previous session if it is enabled in the settings.
+    /// Documents that don't exist anymore are skipped
+    pub fn restore_session(&mut self) {
+        if !self.main_settings.restore_session {
+            return;
+        }
+        let Some(session) = self.main_settings.session.clone() else {
+            return;
+        };
+        let ui = &mut self.ui;
+        if let Some((x, y, w, h)) = session.window {
+            ui.window.resize(x, y, w.max(200), h.max(200));
+        }
+        if let Some(width) = session.tree_width {
+            let tile = &mut ui.main_tile;
+            let width = width.clamp(50, (tile.w() - 50).max(50));
+            ui.tree_group
+                .resize(tile.x(), ui.tree_group.y(), width, ui.tree_group.h());
+            let right_group = &mut ui.right_main_tile_group;
+            right_group.resize(
+                tile.x() + width,
+                right_group.y(),
+                tile.w() - width,
+                right_group.h(),
+            );
+            tile.init_sizes();
+            tile.redraw();
+        }
+        if let Some(tab) = ui.main_tabs.child(session.main_tab) {
+            if let Some(group) = tab.as_group() {
+                _ = ui.main_tabs.set_value(&group);
+            }
+        }
+
+        let mut restored = Vec::new();
+        for (index, document) in session.documents.into_iter().enumerate() {
+            if !document.path.exists() || !self.open_document_file(document.path) {
+                continue;
+            }
+            self.collapse_tree_items(&document.collapsed_nodes);
+            if let Some(selected_node) = document.selected_node {
+                let tree_items =
+                    node_to_tree_vec(self.document.root_node.clone(), Vec::new(), None);
+                if let Some((_, id)) = tree_items.iter().find(|(path, _)| *path == selected_node) {
+                    // Set now, the tree sends the selection after the next tab is opened
+                    self.current_node_id = *id;
+                    self.update_document_tree(Some(*id));
+                }
+            }
+            restored.push(index);
+        }
+        if let Some(tab) = restored
+            .iter()
+            .position(|index| *index == session.current_document)
+        {
+            self.select_tab(tab);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use super::Application;
+use crate::{history_manager::HistoryManager, index::DocumentIndex, types::*};
+use fltk::{group::Group, prelude::*};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Document open in a tab. State of the current tab is kept in the fields of `Application`
+/// and is stored here when another tab is selected
+#[derive(Clone)]
+pub struct DocumentTab {
+    pub document: Document,
+    pub history_manager: HistoryManager,
+    pub is_saved: bool,
+    pub document_file_path: Option<PathBuf>,
+    pub current_node_id: u32,
+    /// Paths of tree items with hidden children
+    pub collapsed_nodes: Vec<String>,
+    /// Visited nodes, most recent first
+    pub recent_nodes: Vec<u32>,
+    pub index: DocumentIndex,
+}
+
+impl DocumentTab {
+    pub fn new(document: Document, document_file_path: Option<PathBuf>) -> Self {
+        Self {
+            current_node_id: document.root_node.id,
+            is_saved: document_file_path.is_some(),
+            index: DocumentIndex::new(&document),
+            document,
+            history_manager: HistoryManager::new(),
+            document_file_path,
+            collapsed_nodes: Vec::new(),
+            recent_nodes: Vec::new(),
+        }
+    }
+
+    /// Empty document that is opened at start and by File/New
+    pub fn new_document() -> Self {
+        Self::new(
+            Document {
+                root_node: Node::new("New document".to_string(), String::new(), Vec::new()),
+                resources: BTreeMap::new(),
+            },
+            None,
+        )
+    }
+}
+
+/// Label of a tab: the file name, marked with `*` if the document isn't saved
+fn tab_label(document_file_path: Option<&PathBuf>, is_saved: bool) -> String {
+    let name = document_file_path
+        .and_then(|path| path.file_name())
+        .map_or("Untitled".to_string(), |name| {
+            name.to_string_lossy().to_string()
+        });
+    format!(
+        "{}{}",
+        if is_saved { "" } else { "*" },
+        name.replace('@', "@@")
+    )
+}
+
+impl Application {
+    /// State of the current tab
+    pub fn current_tab_state(&self) -> DocumentTab {
+        DocumentTab {
+            document: self.document.clone(),
+            history_manager: self.history_manager.clone(),
+            is_saved: self.is_saved,
+            document_file_path: self.document_file_path.clone(),
+            current_node_id: self.current_node_id,
+            collapsed_nodes: self.collapsed_tree_items(),
+            recent_nodes: self.recent_nodes.clone(),
+            index: self.index.clone(),
+        }
+    }
+
+    /// Make the tab's document the current one
+    fn load_tab_state(&mut self, tab: DocumentTab) {
+        self.document = tab.document;
+        self.history_manager = tab.history_manager;
+        self.is_saved = tab.is_saved;
+        self.document_file_path = tab.document_file_path;
+        self.current_node_id = tab.current_node_id;
+        self.recent_nodes = tab.recent_nodes;
+        self.index = tab.index;
+        // Results belong to the previous document
+        self.clear_search_results();
+        self.clear_problems();
+        // Items of the previous document shouldn't stay collapsed
+        self.ui.main_tree.clear();
+        self.update_document_tree(Some(self.current_node_id));
+        self.collapse_tree_items(&tab.collapsed_nodes);
+        self.update_resources(None);
+        self.update_window_title();
+    }
+
+    /// Finish editing the node before leaving it, `false` if the node is still edited
+    pub fn finish_node_editing(&mut self) -> bool {
+        !(self.is_node_editing && self.save_editing_node() && self.node_started_editing)
+    }
+
+    /// Rebuild tabs of documents
+    pub fn update_document_tabs(&mut self) {
+        let (main_sender, _) = self.main_channel.unwrap();
+        let tabs_widget = &mut self.ui.document_tabs;
+        tabs_widget.clear();
+        let mut current_group = None;
+        for (index, tab) in self.tabs.iter().enumerate() {
+            let label = if index == self.current_tab {
+                tab_label(self.document_file_path.as_ref(), self.is_saved)
+            } else {
+                tab_label(tab.document_file_path.as_ref(), tab.is_saved)
+            };
+            // Tabs are drawn above their groups, so the groups are empty and have no height
+            let mut group = Group::new(
+                tabs_widget.x(),
+                tabs_widget.y() + tabs_widget.h(),
+                tabs_widget.w(),
+                0,
+                None,
+            );
+            group.end();
+            group.set_label(&label);
+            tabs_widget.add(&group);
+            if index == self.current_tab {
+                current_group = Some(group);
+            }
+        }
+        if let Some(group) = current_group {
+            _ = tabs_widget.set_value(&group);
+        }
+        tabs_widget.set_callback(move |t| {
+            if let Some(group) = t.value() {
+                main_sender.send(Message::SelectTab(t.find(&group) as usize));
+            }
+        });
+        tabs_widget.redraw();
+    }
+
+    /// Update label of the current tab
+    pub fn update_current_tab_label(&mut self) {
+        let label = tab_label(
+            self.document_file_path.as_ref(),
+            self.is_saved && !self.is_node_editing,
+        );
+        let tabs_widget = &mut self.ui.document_tabs;
+        if let Some(mut group) = tabs_widget.child(self.current_tab as i32) {
+            if group.label() != label {
+                group.set_label(&label);
+                tabs_widget.redraw();
+            }
+        }
+    }
+
+    /// Select the tab by index
+    pub fn select_tab(&mut self, index: usize) {
+        if index == self.current_tab || index >= self.tabs.len() || !self.finish_node_editing() {
+            // Keep the current tab selected in the tab bar
+            self.update_document_tabs();
+            return;
+        }
+        self.tabs[self.current_tab] = self.current_tab_state();
+        self.current_tab = index;
+        self.load_tab_state(self.tabs[index].clone());
+        self.update_document_tabs();
+    }
+
+    /// Open the document in a new tab, or in the current one if it is an empty new document.
+    /// `false` if the current node is still edited
+    pub fn open_tab(&mut self, tab: DocumentTab) -> bool {
+        if !self.finish_node_editing() {
+            return false;
+        }
+        let is_empty_new_document = self.document_file_path.is_none()
+            && self.history_manager.is_empty()
+            && self.document.root_node.children.is_empty()
+            && self.document.root_node.content.is_empty()
+            && self.document.resources.is_empty();
+        if !is_empty_new_document {
+            self.tabs[self.current_tab] = self.current_tab_state();
+            self.tabs.push(tab.clone());
+            self.current_tab = self.tabs.len() - 1;
+        }
+        self.load_tab_state(tab);
+        self.update_document_tabs();
+        true
+    }
+
+    /// Index of the tab with the document file
+    pub fn find_tab(&self, path: &Path) -> Option<usize> {
+        let path = path.canonicalize().ok()?;
+        self.tabs.iter().enumerate().position(|(index, tab)| {
+            let tab_path = if index == self.current_tab {
+                self.document_file_path.as_ref()
+            } else {
+                tab.document_file_path.as_ref()
+            };
+            tab_path.and_then(|p| p.canonicalize().ok()).as_ref() == Some(&path)
+        })
+    }
+
+    /// Close the current tab after asking to save its document
+    pub fn close_tab(&mut self) {
+        if !self.finish_node_editing() || !self.save_document_dialog() {
+            return;
+        }
+        self.tabs.remove(self.current_tab);
+        if self.tabs.is_empty() {
+            self.tabs.push(DocumentTab::new_document());
+        }
+        self.current_tab = self.current_tab.min(self.tabs.len() - 1);
+        self.load_tab_state(self.tabs[self.current_tab].clone());
+        self.update_document_tabs();
+    }
+
+    /// Ask to save documents of all tabs, `false` if canceled
+    pub fn save_all_tabs_dialog(&mut self) -> bool {
+        for index in 0..self.tabs.len() {
+            let (is_saved, has_file) = if index == self.current_tab {
+                (self.is_saved, self.document_file_path.is_some())
+            } else {
+                let tab = &self.tabs[index];
+                (tab.is_saved, tab.document_file_path.is_some())
+            };
+            if is_saved && has_file {
+                continue;
+            }
+            self.select_tab(index);
+            if !self.save_document_dialog() {
+                return false;
+            }
+        }
+        true
+    }
+}
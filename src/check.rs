@@ -0,0 +1,180 @@
+//! Checks of a document: references that don't resolve, missing resources, duplicate
+//! names of siblings and empty nodes
+
+use crate::{
+    formats::html::remove_html_tags,
+    text_processor::{find_references, find_resources, resolve_node_path},
+    types::{Document, Node},
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProblemKind {
+    BrokenReference,
+    MissingResource,
+    DuplicateName,
+    EmptyNode,
+}
+
+/// Problem found in a node
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    #[serde(rename = "id")]
+    pub node_id: u32,
+    /// Path of the node as in `node_to_tree_vec`
+    pub path: String,
+    pub kind: ProblemKind,
+    /// The reference, the resource name or the name used by several siblings
+    pub detail: String,
+}
+
+impl Problem {
+    pub fn description(&self) -> String {
+        match self.kind {
+            ProblemKind::BrokenReference => {
+                format!("reference!(\"{}\") doesn't point to a node", self.detail)
+            }
+            ProblemKind::MissingResource => {
+                format!("resource!(\"{}\") is not in the resources", self.detail)
+            }
+            ProblemKind::DuplicateName => {
+                format!("A sibling has the same name ({})", self.detail)
+            }
+            ProblemKind::EmptyNode => "The node has no text, images or children".to_string(),
+        }
+    }
+}
+
+/// Finds problems of the nodes in the order of the tree
+pub fn check_document(document: &Document) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let root = &document.root_node;
+    let mut nodes = vec![(root, root.name.replace('/', "\\/"))];
+    while let Some((node, path)) = nodes.pop() {
+        problems.extend(check_node(document, node, &path));
+        // Children are pushed in reverse to be visited in the order of the tree
+        for child in node.children.iter().rev() {
+            nodes.push((child, format!("{path}/{}", child.name.replace('/', "\\/"))));
+        }
+    }
+    problems
+}
+
+fn check_node(document: &Document, node: &Node, path: &str) -> Vec<Problem> {
+    let problem = |kind, detail: &str| Problem {
+        node_id: node.id,
+        path: path.to_string(),
+        kind,
+        detail: detail.to_string(),
+    };
+    let mut problems = Vec::new();
+    for (_, reference) in find_references(&node.content) {
+        if resolve_node_path(&reference, document, node.id) == 0 {
+            problems.push(problem(ProblemKind::BrokenReference, &reference));
+        }
+    }
+    let resources = find_resources(&node.content);
+    for name in &resources {
+        if !document.resources.contains_key(name) {
+            problems.push(problem(ProblemKind::MissingResource, name));
+        }
+    }
+    let is_empty = node.children.is_empty()
+        && resources.is_empty()
+        && remove_html_tags(&node.content).trim().is_empty();
+    // An empty root is a new document
+    if is_empty && node.id != document.root_node.id {
+        problems.push(problem(ProblemKind::EmptyNode, ""));
+    }
+    // Each of the siblings is reported, a path points to the first one of them
+    let mut names = BTreeMap::<&str, usize>::new();
+    for child in &node.children {
+        *names.entry(child.name.as_str()).or_default() += 1;
+    }
+    for child in &node.children {
+        if names[child.name.as_str()] > 1 {
+            problems.push(Problem {
+                node_id: child.id,
+                path: format!("{path}/{}", child.name.replace('/', "\\/")),
+                kind: ProblemKind::DuplicateName,
+                detail: child.name.clone(),
+            });
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    #[test]
+    fn finds_problems_in_the_order_of_the_tree() {
+        let document = Document {
+            root_node: node(
+                1,
+                "Root",
+                "",
+                vec![
+                    node(
+                        2,
+                        "A/B",
+                        r#"reference!("path:Root/X") reference!("sibling:A") resource!("a.png")"#,
+                        Vec::new(),
+                    ),
+                    node(3, "A", "<p> </p>", Vec::new()),
+                    node(4, "A", "text", Vec::new()),
+                ],
+            ),
+            resources: BTreeMap::new(),
+        };
+        let problems = check_document(&document)
+            .into_iter()
+            .map(|p| (p.node_id, p.path, p.kind, p.detail))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                (
+                    3,
+                    "Root/A".to_string(),
+                    ProblemKind::DuplicateName,
+                    "A".to_string()
+                ),
+                (
+                    4,
+                    "Root/A".to_string(),
+                    ProblemKind::DuplicateName,
+                    "A".to_string()
+                ),
+                (
+                    2,
+                    r"Root/A\/B".to_string(),
+                    ProblemKind::BrokenReference,
+                    "path:Root/X".to_string()
+                ),
+                (
+                    2,
+                    r"Root/A\/B".to_string(),
+                    ProblemKind::MissingResource,
+                    "a.png".to_string()
+                ),
+                (
+                    3,
+                    "Root/A".to_string(),
+                    ProblemKind::EmptyNode,
+                    String::new()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1125 @@
+//! Command-line interface that works without opening a window.
+//!
+//! Commands are run when the first argument is a command name, otherwise the GUI is opened
+
+use crate::{
+    check::check_document,
+    formats::{self, ExportFormat, ExportOptions, ResourceHandling},
+    text_processor::{
+        extract_document, process_node_path, rewrite_pasted_references, with_rewritten_references,
+    },
+    types::{Document, Node, Resource},
+    utils::{
+        get_node_parent, get_node_resources_names, map_node_ids, merge_resources, modify_document,
+        node_to_tree_vec, randomize_node, DocumentModification,
+    },
+};
+use regex::RegexBuilder;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+const USAGE: &str = "Usage:
+  rustree [<document>] [--node <path>]
+      Opens the window with the document (.rtd, .json, .opml or .org) and selects
+      the node (path:Root/Node)
+  rustree convert <input> -o <output> [options]
+      Converts a document (.rtd, .json, .opml or .org) to another format.
+      The format is detected by the output extension: rtd, txt, html, md, json,
+      opml, epub, tex, docx, pdf or org. PDF only has Western European characters,
+      others are written as ?
+      -o, --output <file>         Output file, images are written next to it
+      -f, --format <extension>    Output format if it isn't the output extension
+      --subtree <path>            Exported node (path:Root/Node), the root by default
+      --toc                       Add a table of contents
+      --numbering                 Number headings (1.2.3)
+      --depth <levels>            Levels of children to export
+      --exclude <path>            Leave out a node with its children (repeatable)
+      --exclude-tag <tag>         Leave out nodes with the tag (repeatable)
+      --resources <handling>      embed, files or omit
+      --wrap <width>              Wrap plain text at the width, 0 disables wrapping
+      --markdown                  Convert HTML content to Markdown
+      --language <tag>            Language of the text (en, pt-BR) declared in EPUB
+  rustree ls <document> [--subtree <path>] [--json]
+      Prints paths of the nodes
+  rustree cat <document> <path> [--html] [--wrap <width>] [--json]
+      Prints the node content as text or HTML
+  rustree grep <document> <regex> [--ignore-case] [--json]
+      Finds node names and content lines matching the regular expression
+  rustree resources <document> [--json]
+      Prints resources and their sizes in bytes
+  rustree check <document> [--json]
+      Finds broken references, missing resources, duplicate names of siblings and
+      empty nodes
+  rustree extract <document> <path> -o <output>
+      Saves the node with its children and the resources they use as a new document
+  rustree graft <document> <path> <other document> [-o <output>]
+      Adds the other document as a child of the node
+  rustree rename <document> <path> <name> [-o <output>]
+      Renames the node
+  rustree delete <document> <path> [-o <output>]
+      Deletes the node with its children
+  rustree add-resource <document> <file> [--name <name>] [-o <output>]
+      Adds the file as a resource named after the file by default
+  rustree remove-resource <document> <name> [--force] [-o <output>]
+      Removes the resource, --force removes it even if nodes use it
+  Paths of nodes are written with \\/ for / in names, id:<id> is a node by its id.
+  Commands that change a document overwrite it unless --output is given.
+  Documents are saved as .rtd or .json by the extension.
+  Arguments after -- are not options (rustree grep doc.rtd -- -word)
+  rustree help
+      Shows this message";
+
+/// Short names of options
+const ALIASES: [(&str, &str); 2] = [("-o", "--output"), ("-f", "--format")];
+
+/// Commands handled by `run`
+const COMMANDS: [&str; 15] = [
+    "convert",
+    "ls",
+    "cat",
+    "grep",
+    "resources",
+    "check",
+    "extract",
+    "graft",
+    "rename",
+    "delete",
+    "add-resource",
+    "remove-resource",
+    "help",
+    "--help",
+    "-h",
+];
+
+/// Prefixes of references that `find_node` accepts instead of paths
+const REFERENCE_KINDS: [&str; 5] = ["path:", "id:", "sibling:", "child:", "parent:"];
+
+/// `true` if the arguments (without the program name) are a command, not a document
+pub fn is_command(args: &[String]) -> bool {
+    args.first()
+        .is_some_and(|command| COMMANDS.contains(&command.as_str()))
+}
+
+/// Runs the command given by the arguments (without the program name) and returns the
+/// exit code. `None` if the arguments are not a command
+pub fn run(args: &[String]) -> Option<i32> {
+    run_with_output(args, &mut std::io::stdout().lock())
+}
+
+/// Runs the command like `run`, its output is written to `out`
+fn run_with_output(args: &[String], out: &mut dyn Write) -> Option<i32> {
+    if !is_command(args) {
+        return None;
+    }
+    let result = match args.first()?.as_str() {
+        "convert" => convert(&args[1..]),
+        "ls" => list_nodes(&args[1..], out),
+        "cat" => print_node(&args[1..], out),
+        // Like grep, nothing found is a failure for scripts, but not an error
+        "grep" => match find_text(&args[1..], out) {
+            Ok(false) => return Some(1),
+            result => result.map(|_| ()),
+        },
+        "resources" => list_resources(&args[1..], out),
+        "check" => check(&args[1..], out),
+        "extract" => extract_node(&args[1..]),
+        "graft" => graft_document(&args[1..]),
+        "rename" => rename_node(&args[1..]),
+        "delete" => delete_node(&args[1..]),
+        "add-resource" => add_resource(&args[1..]),
+        "remove-resource" => remove_resource(&args[1..]),
+        "help" | "--help" | "-h" => print_line(out, USAGE),
+        _ => return None,
+    };
+    Some(match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("rustree: {e}");
+            1
+        }
+    })
+}
+
+/// Arguments of a command
+struct Arguments {
+    positional: Vec<String>,
+    /// Options and their values (`None` for flags) in the order they are given
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Arguments {
+    /// Parses arguments, `with_values` are options followed by a value, `flags` are the other ones
+    fn parse(args: &[String], with_values: &[&str], flags: &[&str]) -> Result<Self, String> {
+        let mut arguments = Self {
+            positional: Vec::new(),
+            options: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // Arguments after `--` are positional even if they start with '-'
+            if arg == "--" {
+                arguments.positional.extend(args.cloned());
+                break;
+            }
+            let name = ALIASES
+                .iter()
+                .find(|(alias, _)| alias == arg)
+                .map_or(arg.as_str(), |(_, name)| name);
+            if with_values.contains(&name) {
+                let value = args
+                    .next()
+                    .ok_or(format!("Option {arg} requires a value"))?;
+                arguments
+                    .options
+                    .push((name.to_string(), Some(value.clone())));
+            } else if flags.contains(&name) {
+                arguments.options.push((name.to_string(), None));
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                return Err(format!("Unknown option {arg} (see rustree help)"));
+            } else {
+                arguments.positional.push(arg.clone());
+            }
+        }
+        Ok(arguments)
+    }
+
+    /// Gets the last value of the option
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).last().copied()
+    }
+
+    fn values(&self, name: &str) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter(|(n, _)| n == name)
+            .filter_map(|(_, value)| value.as_deref())
+            .collect()
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(n, _)| n == name)
+    }
+
+    /// Gets the positional argument at the index
+    fn positional(&self, index: usize, description: &str) -> Result<&str, String> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or(format!("Missing {description} (see rustree help)"))
+    }
+}
+
+/// Document to open in the window and the node to select in it
+pub struct OpenArguments {
+    pub document: Option<PathBuf>,
+    pub node: Option<String>,
+}
+
+/// Parses arguments of opening the window: `[<document>] [--node <path>]`
+pub fn open_arguments(args: &[String]) -> Result<OpenArguments, String> {
+    let arguments = Arguments::parse(args, &["--node"], &[])?;
+    if arguments.positional.len() > 1 {
+        return Err("Only one document can be opened".to_string());
+    }
+    let document = arguments.positional.first().map(PathBuf::from);
+    let node = arguments.value("--node").map(str::to_string);
+    if node.is_some() && document.is_none() {
+        return Err("--node requires a document".to_string());
+    }
+    Ok(OpenArguments { document, node })
+}
+
+/// Writes the text to the output. A closed output (`rustree ls | head`) is an error
+fn print_text(out: &mut dyn Write, text: &str) -> Result<(), String> {
+    out.write_all(text.as_bytes())
+        .map_err(|e| format!("Can't write the output: {e}"))
+}
+
+fn print_line(out: &mut dyn Write, line: &str) -> Result<(), String> {
+    print_text(out, &format!("{line}\n"))
+}
+
+/// Prints the value as JSON
+fn print_json(out: &mut dyn Write, value: &impl Serialize) -> Result<(), String> {
+    print_line(out, &serde_json::to_string_pretty(value).unwrap())
+}
+
+fn parse_number(value: &str, option: &str) -> Result<usize, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{option} requires a number, got {value}"))
+}
+
+/// Reads a document by its extension (.rtd, .json, .opml or .org)
+pub fn load_document(path: &Path) -> Result<Document, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Can't read {}: {e}", path.display()))?;
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let text = || String::from_utf8_lossy(&bytes).to_string();
+    let result = match extension.as_str() {
+        "rtd" => formats::rtd::deserealize(bytes.clone())
+            .map(formats::rtd::rtd_document_to_document)
+            .map_err(|e| e.to_string()),
+        "json" => formats::json::json_to_document(text()),
+        "opml" | "xml" => formats::opml::opml_to_node(text()).map(|root_node| Document {
+            root_node,
+            resources: BTreeMap::new(),
+        }),
+        "org" => formats::org::org_to_node(text()).map(|root_node| Document {
+            root_node,
+            resources: BTreeMap::new(),
+        }),
+        _ => Err("unsupported format, use .rtd, .json, .opml or .org".to_string()),
+    };
+    result.map_err(|e| format!("Can't open {}: {e}", path.display()))
+}
+
+/// Saves a document by its extension (.rtd or .json)
+pub fn save_document(path: &Path, document: Document) -> Result<(), String> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let bytes = match extension.as_str() {
+        "rtd" => formats::rtd::serealize(formats::rtd::document_to_rtd_document(document))
+            .map_err(|e| format!("Can't save {}: {e}", path.display()))?,
+        "json" => formats::json::document_to_json(document).into_bytes(),
+        _ => {
+            return Err(format!(
+                "Can't save {}: unsupported format, use .rtd or .json",
+                path.display()
+            ))
+        }
+    };
+    fs::write(path, bytes).map_err(|e| format!("Can't write {}: {e}", path.display()))
+}
+
+/// Finds the id of the node by a reference (`path:Root/Node`, `id:1`), a path without
+/// the `path:` prefix is accepted too
+pub fn find_node(document: &Document, reference: &str) -> Result<u32, String> {
+    // Other arguments are paths, even with ':' in names
+    let reference = if REFERENCE_KINDS
+        .iter()
+        .any(|kind| reference.starts_with(kind))
+    {
+        reference.to_string()
+    } else {
+        format!("path:{reference}")
+    };
+    match process_node_path(
+        reference.clone(),
+        document.clone(),
+        document.root_node.clone(),
+    ) {
+        0 => Err(format!("Node {reference} doesn't exist")),
+        id => Ok(id),
+    }
+}
+
+fn convert(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(
+        args,
+        &[
+            "--output",
+            "--format",
+            "--subtree",
+            "--depth",
+            "--exclude",
+            "--exclude-tag",
+            "--resources",
+            "--wrap",
+            "--language",
+        ],
+        &["--toc", "--numbering", "--markdown"],
+    )?;
+    let input = PathBuf::from(arguments.positional(0, "input file")?);
+    if let Some(extra) = arguments.positional.get(1) {
+        return Err(format!("Unexpected argument {extra}"));
+    }
+    let output = PathBuf::from(
+        arguments
+            .value("--output")
+            .ok_or("Missing output file (-o <file>)")?,
+    );
+    let format = match arguments.value("--format") {
+        Some(format) => {
+            ExportFormat::from_extension(format).ok_or(format!("Unknown format {format}"))?
+        }
+        None => output
+            .extension()
+            .and_then(|e| ExportFormat::from_extension(&e.to_string_lossy()))
+            .ok_or(format!(
+                "Can't detect the format of {}, use --format",
+                output.display()
+            ))?,
+    };
+
+    let mut options = ExportOptions {
+        table_of_contents: arguments.flag("--toc"),
+        numbering: arguments.flag("--numbering"),
+        excluded_paths: arguments
+            .values("--exclude")
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        excluded_tags: arguments
+            .values("--exclude-tag")
+            .into_iter()
+            .map(|tag| tag.trim_matches(':').to_string())
+            .collect(),
+        convert_to_markdown: arguments.flag("--markdown"),
+        ..Default::default()
+    };
+    if let Some(depth) = arguments.value("--depth") {
+        options.max_depth = Some(parse_number(depth, "--depth")?);
+    }
+    if let Some(width) = arguments.value("--wrap") {
+        options.wrap_width = parse_number(width, "--wrap")?;
+    }
+    if let Some(language) = arguments.value("--language") {
+        options.language = language.to_string();
+    }
+    if let Some(resources) = arguments.value("--resources") {
+        options.resources = match resources {
+            "embed" => ResourceHandling::Embed,
+            "files" => ResourceHandling::SeparateFiles,
+            "omit" => ResourceHandling::Omit,
+            _ => {
+                return Err(format!(
+                    "Unknown resource handling {resources}, use embed, files or omit"
+                ))
+            }
+        };
+        if !format.resource_handlings().contains(&options.resources) {
+            return Err(format!(
+                "{} export doesn't support --resources {resources}",
+                format.name()
+            ));
+        }
+    }
+
+    let document = load_document(&input)?;
+    let node_id = match arguments.value("--subtree") {
+        Some(reference) => find_node(&document, reference)?,
+        None => document.root_node.id,
+    };
+    let exported = formats::export_node(&document, node_id, format, &options)
+        .map_err(|e| format!("Can't export to {}: {e}", format.name()))?;
+    fs::write(&output, exported.bytes)
+        .map_err(|e| format!("Can't write {}: {e}", output.display()))?;
+    let directory = output.parent().unwrap_or(Path::new(""));
+    for (name, bytes) in exported.files {
+        let path = directory.join(name);
+        fs::write(&path, bytes).map_err(|e| format!("Can't write {}: {e}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct NodeEntry {
+    id: u32,
+    path: String,
+}
+
+fn list_nodes(args: &[String], out: &mut dyn Write) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &["--subtree"], &["--json"])?;
+    let document = load_document(Path::new(arguments.positional(0, "document")?))?;
+    let subtree = match arguments.value("--subtree") {
+        Some(reference) => document
+            .clone()
+            .get_node(find_node(&document, reference)?)
+            .unwrap(),
+        None => document.root_node.clone(),
+    };
+    let subtree_ids = formats::node_paths(&subtree);
+    let nodes = node_to_tree_vec(document.root_node, Vec::new(), None)
+        .into_iter()
+        .filter(|(_, id)| subtree_ids.contains_key(id))
+        .map(|(path, id)| NodeEntry { id, path })
+        .collect::<Vec<_>>();
+    if arguments.flag("--json") {
+        print_json(out, &nodes)?;
+    } else {
+        for node in nodes {
+            print_line(out, &node.path)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct NodeContent {
+    id: u32,
+    path: String,
+    name: String,
+    metadata: BTreeMap<String, String>,
+    /// HTML content with macros
+    content: String,
+    text: String,
+    children: Vec<String>,
+}
+
+fn print_node(args: &[String], out: &mut dyn Write) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &["--wrap"], &["--html", "--json"])?;
+    let document = load_document(Path::new(arguments.positional(0, "document")?))?;
+    let id = find_node(&document, arguments.positional(1, "node path")?)?;
+    let node = document.clone().get_node(id).unwrap();
+    let wrap_width = match arguments.value("--wrap") {
+        Some(width) => parse_number(width, "--wrap")?,
+        None => 0,
+    };
+    if arguments.flag("--json") {
+        print_json(
+            out,
+            &NodeContent {
+                id,
+                path: formats::node_paths(&document.root_node)
+                    .remove(&id)
+                    .unwrap_or_default(),
+                text: formats::text::content_to_text(&node, &document, wrap_width),
+                children: node.children.iter().map(|n| n.name.clone()).collect(),
+                name: node.name,
+                metadata: node.metadata,
+                content: node.content,
+            },
+        )?;
+    } else if arguments.flag("--html") {
+        print_line(out, &node.content)?;
+    } else {
+        print_text(
+            out,
+            &formats::text::content_to_text(&node, &document, wrap_width),
+        )?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TextMatch {
+    id: u32,
+    path: String,
+    /// `name` or `content`
+    field: &'static str,
+    /// The matching name or content line
+    text: String,
+}
+
+/// Prints the matches, `false` if there are none
+fn find_text(args: &[String], out: &mut dyn Write) -> Result<bool, String> {
+    let arguments = Arguments::parse(args, &[], &["--ignore-case", "-i", "--json"])?;
+    let document = load_document(Path::new(arguments.positional(0, "document")?))?;
+    let regex = RegexBuilder::new(arguments.positional(1, "regular expression")?)
+        .case_insensitive(arguments.flag("--ignore-case") || arguments.flag("-i"))
+        .build()
+        .map_err(|e| format!("Invalid regular expression: {e}"))?;
+    let mut matches = Vec::new();
+    for (path, id) in node_to_tree_vec(document.root_node.clone(), Vec::new(), None) {
+        let node = document.clone().get_node(id).unwrap();
+        if regex.is_match(&node.name) {
+            matches.push(TextMatch {
+                id,
+                path: path.clone(),
+                field: "name",
+                text: node.name.clone(),
+            });
+        }
+        for line in formats::text::content_to_text(&node, &document, 0).lines() {
+            if regex.is_match(line) {
+                matches.push(TextMatch {
+                    id,
+                    path: path.clone(),
+                    field: "content",
+                    text: line.trim().to_string(),
+                });
+            }
+        }
+    }
+    if arguments.flag("--json") {
+        print_json(out, &matches)?;
+    } else {
+        for m in &matches {
+            if m.field == "name" {
+                print_line(out, &m.path)?;
+            } else {
+                print_line(out, &format!("{}: {}", m.path, m.text))?;
+            }
+        }
+    }
+    Ok(!matches.is_empty())
+}
+
+#[derive(Serialize)]
+struct ResourceEntry {
+    name: String,
+    size: usize,
+    /// MIME type of images
+    media_type: Option<&'static str>,
+}
+
+fn list_resources(args: &[String], out: &mut dyn Write) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[], &["--json"])?;
+    let document = load_document(Path::new(arguments.positional(0, "document")?))?;
+    let resources = document
+        .resources
+        .iter()
+        .map(|(name, res)| ResourceEntry {
+            name: name.clone(),
+            size: res.bytes.len(),
+            media_type: formats::image_type(&res.bytes).map(|(_, media_type)| media_type),
+        })
+        .collect::<Vec<_>>();
+    if arguments.flag("--json") {
+        print_json(out, &resources)?;
+    } else {
+        for res in resources {
+            print_line(out, &format!("{}\t{}", res.size, res.name))?;
+        }
+    }
+    Ok(())
+}
+
+fn check(args: &[String], out: &mut dyn Write) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &[], &["--json"])?;
+    let document = load_document(Path::new(arguments.positional(0, "document")?))?;
+    let problems = check_document(&document);
+    if arguments.flag("--json") {
+        print_json(out, &problems)?;
+    } else {
+        for problem in &problems {
+            print_line(out, &format!("{}: {}", problem.path, problem.description()))?;
+        }
+    }
+    if !problems.is_empty() {
+        return Err(format!("{} problems found", problems.len()));
+    }
+    Ok(())
+}
+
+/// Parses arguments of a command that changes the document, opens the document and
+/// returns the arguments, the document and the path it is saved to
+fn open_for_modification(
+    args: &[String],
+    with_values: &[&str],
+    flags: &[&str],
+) -> Result<(Arguments, Document, PathBuf), String> {
+    let arguments = Arguments::parse(args, &[with_values, &["--output"]].concat(), flags)?;
+    let path = PathBuf::from(arguments.positional(0, "document")?);
+    let document = load_document(&path)?;
+    let output = arguments.value("--output").map_or(path, PathBuf::from);
+    Ok((arguments, document, output))
+}
+
+fn extract_node(args: &[String]) -> Result<(), String> {
+    let arguments = Arguments::parse(args, &["--output"], &[])?;
+    let document = load_document(Path::new(arguments.positional(0, "document")?))?;
+    let id = find_node(&document, arguments.positional(1, "node path")?)?;
+    let output = arguments
+        .value("--output")
+        .ok_or("Missing output file (-o <file>)")?;
+    save_document(
+        Path::new(output),
+        extract_document(&document, id).ok_or("The node doesn't exist")?,
+    )
+}
+
+fn graft_document(args: &[String]) -> Result<(), String> {
+    let (arguments, mut document, output) = open_for_modification(args, &[], &[])?;
+    let id = find_node(&document, arguments.positional(1, "node path")?)?;
+    let other = load_document(Path::new(arguments.positional(2, "grafted document")?))?;
+    let merged = merge_resources(&mut document, other.clone());
+    let node = randomize_node(&merged);
+    let grafted = modify_document(document, DocumentModification::PasteNode(node.clone(), id));
+    // References of the grafted document point to its nodes with their new ids and paths
+    let ids = map_node_ids(&merged, &node);
+    let modifications = rewrite_pasted_references(&other, &grafted, &ids, node.id);
+    save_document(
+        &output,
+        modify_document(grafted, DocumentModification::Batch(modifications)),
+    )
+}
+
+fn rename_node(args: &[String]) -> Result<(), String> {
+    let (arguments, document, output) = open_for_modification(args, &[], &[])?;
+    let id = find_node(&document, arguments.positional(1, "node path")?)?;
+    let name = arguments.positional(2, "new name")?.trim().to_string();
+    if name.is_empty() {
+        return Err("Node name can't be empty".to_string());
+    }
+    if let Some(parent) = get_node_parent(document.root_node.clone(), id) {
+        let parent = document.clone().get_node(parent).unwrap();
+        if parent.children.iter().any(|n| n.id != id && n.name == name) {
+            return Err(format!(
+                "There is already a node named {name} in {}",
+                parent.name
+            ));
+        }
+    }
+    let node = document.clone().get_node(id).unwrap();
+    save_document(
+        &output,
+        modify_document(
+            document.clone(),
+            with_rewritten_references(
+                &document,
+                DocumentModification::EditNode(id, Node { name, ..node }),
+            ),
+        ),
+    )
+}
+
+fn delete_node(args: &[String]) -> Result<(), String> {
+    let (arguments, document, output) = open_for_modification(args, &[], &[])?;
+    let id = find_node(&document, arguments.positional(1, "node path")?)?;
+    if id == document.root_node.id {
+        return Err("The root node can't be deleted".to_string());
+    }
+    save_document(
+        &output,
+        modify_document(document, DocumentModification::DeleteNode(id)),
+    )
+}
+
+fn add_resource(args: &[String]) -> Result<(), String> {
+    let (arguments, document, output) = open_for_modification(args, &["--name"], &[])?;
+    let file = PathBuf::from(arguments.positional(1, "resource file")?);
+    let bytes = fs::read(&file).map_err(|e| format!("Can't read {}: {e}", file.display()))?;
+    let name = match arguments.value("--name") {
+        Some(name) => name.to_string(),
+        None => file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    if document.resources.contains_key(&name) {
+        return Err(format!("Resource {name} already exists"));
+    }
+    save_document(
+        &output,
+        modify_document(
+            document,
+            DocumentModification::AddResource(name, Resource::new(bytes)),
+        ),
+    )
+}
+
+fn remove_resource(args: &[String]) -> Result<(), String> {
+    let (arguments, document, output) = open_for_modification(args, &[], &["--force"])?;
+    let name = arguments.positional(1, "resource name")?.to_string();
+    if !document.resources.contains_key(&name) {
+        return Err(format!("Resource {name} doesn't exist"));
+    }
+    if !arguments.flag("--force") && get_node_resources_names(&document.root_node).contains(&name) {
+        return Err(format!(
+            "Resource {name} is used by nodes, --force removes it anyway"
+        ));
+    }
+    save_document(
+        &output,
+        modify_document(document, DocumentModification::DeleteResource(name)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_nodes_by_references_and_paths_with_colons() {
+        let child = Node {
+            id: 2,
+            ..Node::new("a:b".to_string(), String::new(), Vec::new())
+        };
+        let document = Document {
+            root_node: Node {
+                id: 1,
+                ..Node::new("Root".to_string(), String::new(), vec![child])
+            },
+            resources: BTreeMap::new(),
+        };
+        assert_eq!(find_node(&document, "Root/a:b"), Ok(2));
+        assert_eq!(find_node(&document, "path:Root/a:b"), Ok(2));
+        assert_eq!(find_node(&document, "id:2"), Ok(2));
+        assert_eq!(find_node(&document, "child:a:b"), Ok(2));
+        assert!(find_node(&document, "Root/a").is_err());
+    }
+
+    /// Directory for the files of a test, removed at its end
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(test: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("rustree-{}-{test}", std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn file(&self, name: &str) -> String {
+            self.0.join(name).to_string_lossy().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    /// Saves Root > Chapter (with an image) > Section and Root > -notes as doc.json
+    fn write_document(dir: &TempDir) -> String {
+        let chapter = node(
+            2,
+            "Chapter",
+            r#"<p>First line</p><p>See reference!("path:Root/-notes")</p><img src="resource!("image.png")">"#,
+            vec![node(3, "Section", "<p>Second line</p>", Vec::new())],
+        );
+        let document = Document {
+            root_node: node(
+                1,
+                "Root",
+                "<p>Intro</p>",
+                vec![
+                    chapter,
+                    node(4, "-notes", "<p>-dashed text</p>", Vec::new()),
+                ],
+            ),
+            resources: BTreeMap::from([("image.png".to_string(), Resource::new(vec![1, 2, 3]))]),
+        };
+        let path = dir.file("doc.json");
+        save_document(Path::new(&path), document).unwrap();
+        path
+    }
+
+    /// Runs the command and returns the exit code and the output
+    fn run_command(args: &[&str]) -> (Option<i32>, String) {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let mut out = Vec::new();
+        let code = run_with_output(&args, &mut out);
+        (code, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn parses_options_and_positional_arguments_after_double_dash() {
+        let args = ["a", "-o", "out", "--", "-b", "--json"].map(str::to_string);
+        let arguments = Arguments::parse(&args, &["--output"], &["--json"]).unwrap();
+        assert_eq!(arguments.positional, ["a", "-b", "--json"]);
+        assert_eq!(arguments.value("--output"), Some("out"));
+        assert!(!arguments.flag("--json"));
+        assert!(Arguments::parse(&args[..3], &[], &[]).is_err());
+    }
+
+    #[test]
+    fn finds_text_starting_with_a_dash_after_double_dash() {
+        let dir = TempDir::new("double-dash");
+        let document = write_document(&dir);
+        assert_eq!(run_command(&["grep", &document, "-dash"]).0, Some(1));
+        assert_eq!(
+            run_command(&["grep", &document, "--", "-dash"]),
+            (Some(0), "Root/-notes: -dashed text\n".to_string())
+        );
+    }
+
+    #[test]
+    fn converts_documents_and_fails_with_exit_code_1() {
+        let dir = TempDir::new("convert");
+        let document = write_document(&dir);
+        let output = dir.file("out.md");
+        assert_eq!(
+            run_command(&[
+                "convert",
+                &document,
+                "-o",
+                &output,
+                "--subtree",
+                "Root/Chapter"
+            ])
+            .0,
+            Some(0)
+        );
+        let markdown = fs::read_to_string(&output).unwrap();
+        assert!(markdown.contains(">Chapter<"));
+        assert!(markdown.contains("Second line"));
+        assert!(!markdown.contains("Intro"));
+
+        let output = dir.file("out.txt");
+        assert_eq!(
+            run_command(&["convert", &document, "-o", &output, "--depth", "0"]).0,
+            Some(0)
+        );
+        assert!(!fs::read_to_string(&output).unwrap().contains("Chapter"));
+
+        for args in [
+            vec!["convert", &document],
+            vec!["convert", &document, "-o", "out.unknown"],
+            vec!["convert", &document, "-o", &output, "--depth", "x"],
+            vec![
+                "convert",
+                &document,
+                "-o",
+                &output,
+                "--subtree",
+                "Root/Nothing",
+            ],
+            vec!["convert", &document, "-o", &output, "--unknown"],
+            vec!["convert", "missing.json", "-o", &output],
+        ] {
+            assert_eq!(run_command(&args), (Some(1), String::new()), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn runs_only_commands() {
+        assert_eq!(run_command(&["doc.rtd"]), (None, String::new()));
+        assert_eq!(run_command(&[]), (None, String::new()));
+        let (code, output) = run_command(&["help"]);
+        assert_eq!(code, Some(0));
+        assert!(output.starts_with("Usage:"));
+    }
+
+    #[test]
+    fn lists_and_prints_nodes() {
+        let dir = TempDir::new("ls-cat");
+        let document = write_document(&dir);
+        assert_eq!(
+            run_command(&["ls", &document]),
+            (
+                Some(0),
+                "Root\nRoot/Chapter\nRoot/Chapter/Section\nRoot/-notes\n".to_string()
+            )
+        );
+        let (code, output) = run_command(&["ls", &document, "--subtree", "id:2", "--json"]);
+        assert_eq!(code, Some(0));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&output).unwrap(),
+            serde_json::json!([
+                {"id": 2, "path": "Root/Chapter"},
+                {"id": 3, "path": "Root/Chapter/Section"},
+            ])
+        );
+
+        assert_eq!(
+            run_command(&["cat", &document, "Root/Chapter/Section"]),
+            (Some(0), "Second line\n".to_string())
+        );
+        assert_eq!(
+            run_command(&["cat", &document, "Root/Chapter/Section", "--html"]),
+            (Some(0), "<p>Second line</p>\n".to_string())
+        );
+        let (code, output) = run_command(&["cat", &document, "Root/Chapter", "--json"]);
+        assert_eq!(code, Some(0));
+        let node = serde_json::from_str::<serde_json::Value>(&output).unwrap();
+        assert_eq!(node["path"], "Root/Chapter");
+        assert_eq!(node["children"], serde_json::json!(["Section"]));
+        assert!(node["text"].as_str().unwrap().contains("First line"));
+
+        assert_eq!(run_command(&["cat", &document, "Root/Nothing"]).0, Some(1));
+        assert_eq!(run_command(&["ls", &dir.file("missing.rtd")]).0, Some(1));
+    }
+
+    #[test]
+    fn finds_text_in_names_and_content() {
+        let dir = TempDir::new("grep");
+        let document = write_document(&dir);
+        assert_eq!(
+            run_command(&["grep", &document, "line"]),
+            (
+                Some(0),
+                "Root/Chapter: First line\nRoot/Chapter/Section: Second line\n".to_string()
+            )
+        );
+        assert_eq!(
+            run_command(&["grep", &document, "^SECTION$", "-i"]),
+            (Some(0), "Root/Chapter/Section\n".to_string())
+        );
+        let (code, output) = run_command(&["grep", &document, "^Sec", "--json"]);
+        assert_eq!(code, Some(0));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&output).unwrap(),
+            serde_json::json!([
+                {"id": 3, "path": "Root/Chapter/Section", "field": "name", "text": "Section"},
+                {"id": 3, "path": "Root/Chapter/Section", "field": "content", "text": "Second line"},
+            ])
+        );
+        // Nothing found exits with 1 without an error, an invalid expression is an error
+        assert_eq!(
+            run_command(&["grep", &document, "nothing"]),
+            (Some(1), String::new())
+        );
+        assert_eq!(
+            run_command(&["grep", &document, "("]),
+            (Some(1), String::new())
+        );
+    }
+
+    #[test]
+    fn lists_resources_and_checks_documents() {
+        let dir = TempDir::new("resources-check");
+        let document = write_document(&dir);
+        assert_eq!(
+            run_command(&["resources", &document]),
+            (Some(0), "3\timage.png\n".to_string())
+        );
+        let (code, output) = run_command(&["resources", &document, "--json"]);
+        assert_eq!(code, Some(0));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&output).unwrap(),
+            serde_json::json!([{"name": "image.png", "size": 3, "media_type": null}])
+        );
+
+        assert_eq!(run_command(&["check", &document]), (Some(0), String::new()));
+        assert_eq!(
+            run_command(&["remove-resource", &document, "image.png", "--force"]).0,
+            Some(0)
+        );
+        assert_eq!(
+            run_command(&["check", &document]),
+            (
+                Some(1),
+                "Root/Chapter: resource!(\"image.png\") is not in the resources\n".to_string()
+            )
+        );
+    }
+
+    /// Paths of the nodes of the saved document
+    fn paths(path: &str) -> Vec<String> {
+        node_to_tree_vec(
+            load_document(Path::new(path)).unwrap().root_node,
+            Vec::new(),
+            None,
+        )
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect()
+    }
+
+    #[test]
+    fn extracts_and_grafts_nodes() {
+        let dir = TempDir::new("extract-graft");
+        let document = write_document(&dir);
+        let extracted = dir.file("extracted.json");
+        assert_eq!(
+            run_command(&["extract", &document, "Root/Chapter", "-o", &extracted]).0,
+            Some(0)
+        );
+        assert_eq!(paths(&extracted), ["Chapter", "Chapter/Section"]);
+        let resources = load_document(Path::new(&extracted)).unwrap().resources;
+        assert_eq!(resources.keys().collect::<Vec<_>>(), ["image.png"]);
+        assert_eq!(
+            run_command(&["extract", &document, "Root/Chapter"]).0,
+            Some(1)
+        );
+
+        let grafted = dir.file("grafted.json");
+        assert_eq!(
+            run_command(&[
+                "graft",
+                &document,
+                "Root/-notes",
+                &extracted,
+                "-o",
+                &grafted
+            ])
+            .0,
+            Some(0)
+        );
+        assert_eq!(
+            paths(&grafted),
+            [
+                "Root",
+                "Root/Chapter",
+                "Root/Chapter/Section",
+                "Root/-notes",
+                "Root/-notes/Chapter",
+                "Root/-notes/Chapter/Section"
+            ]
+        );
+        // The input isn't changed when the output is given
+        assert_eq!(paths(&document).len(), 4);
+        assert_eq!(
+            run_command(&["graft", &document, "Root/Nothing", &extracted]).0,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn renames_and_deletes_nodes() {
+        let dir = TempDir::new("rename-delete");
+        let document = write_document(&dir);
+        assert_eq!(
+            run_command(&["rename", &document, "--", "Root/-notes", "Notes"]).0,
+            Some(0)
+        );
+        assert_eq!(paths(&document)[3], "Root/Notes");
+        // References to the renamed node follow it
+        let (_, content) = run_command(&["cat", &document, "Root/Chapter", "--html"]);
+        assert!(content.contains(r#"reference!("path:Root/Notes")"#));
+        for args in [
+            ["rename", &document, "Root/Notes", "Chapter"],
+            ["rename", &document, "Root/Notes", " "],
+            ["rename", &document, "Root/Nothing", "Name"],
+        ] {
+            assert_eq!(run_command(&args).0, Some(1), "{args:?}");
+        }
+
+        assert_eq!(
+            run_command(&["delete", &document, "Root/Chapter"]).0,
+            Some(0)
+        );
+        assert_eq!(paths(&document), ["Root", "Root/Notes"]);
+        assert_eq!(run_command(&["delete", &document, "Root"]).0, Some(1));
+    }
+
+    #[test]
+    fn adds_and_removes_resources() {
+        let dir = TempDir::new("resources");
+        let document = write_document(&dir);
+        let file = dir.file("photo.jpg");
+        fs::write(&file, [4, 5]).unwrap();
+        assert_eq!(run_command(&["add-resource", &document, &file]).0, Some(0));
+        assert_eq!(
+            run_command(&["add-resource", &document, &file, "--name", "other.jpg"]).0,
+            Some(0)
+        );
+        assert_eq!(
+            run_command(&["resources", &document]).1,
+            "3\timage.png\n2\tother.jpg\n2\tphoto.jpg\n"
+        );
+        assert_eq!(run_command(&["add-resource", &document, &file]).0, Some(1));
+        assert_eq!(
+            run_command(&["add-resource", &document, &dir.file("missing.jpg")]).0,
+            Some(1)
+        );
+
+        assert_eq!(
+            run_command(&["remove-resource", &document, "photo.jpg"]).0,
+            Some(0)
+        );
+        // Resources used by nodes are removed only with --force
+        assert_eq!(
+            run_command(&["remove-resource", &document, "image.png"]).0,
+            Some(1)
+        );
+        assert_eq!(
+            run_command(&["remove-resource", &document, "missing.png", "--force"]).0,
+            Some(1)
+        );
+        assert_eq!(
+            run_command(&["resources", &document]).1,
+            "3\timage.png\n2\tother.jpg\n"
+        );
+    }
+}
@@ -1,11 +1,9 @@
 use std::collections::BTreeMap;
 
 use crate::{
-    app::Application,
-    formats,
+    app::{dialogs::ask_shortcut, Application, DocumentTab},
     settings::{self, update_shortcuts_browser, ShortcutsManager, UIElement},
     types::*,
-    utils::ask_shortcut,
 };
 use fltk::{app, enums::Shortcut, prelude::*};
 
@@ -63,9 +61,11 @@ impl Application {
                             self.save_editing_node();
                         } else {
                             self.current_node_id = id;
+                            self.add_recent_node(id);
                             if let Some(node) = self.document.clone().get_node(id) {
                                 self.set_node_view_value(node.content);
                             }
+                            self.update_backlinks();
                         }
                     }
                     Message::Quit => {
@@ -74,76 +74,22 @@ impl Application {
                             && self.node_started_editing
                         {
                             continue;
-                        } else if self.save_document_dialog() {
+                        }
+                        // The session is taken before tabs are selected to ask to save them
+                        self.main_settings.session =
+                            self.main_settings.restore_session.then(|| self.session());
+                        if self.save_all_tabs_dialog() {
                             self.resources_manager.clear_resources();
                             self.clone().main_settings.write();
                             app::quit();
                         }
                     }
-                    Message::ExportNode(export_type) => {
-                        let root_node = self.document.clone().root_node;
-                        let node_to_export;
-                        let ext;
-                        let bytes;
-                        match export_type {
-                            TreeExportType::TextDoc => {
-                                node_to_export = Some(root_node.clone());
-                                ext = ".txt";
-                                bytes = formats::node_to_text(root_node).into();
-                            }
-                            TreeExportType::TextNode => {
-                                node_to_export = Some(current_editing_node.clone());
-                                ext = ".txt";
-                                bytes = formats::node_to_text(current_editing_node).into();
-                            }
-                            TreeExportType::HtmlDoc => {
-                                node_to_export = Some(root_node.clone());
-                                ext = ".html";
-                                bytes = formats::node_to_html(root_node.clone(), true).into();
-                            }
-                            TreeExportType::HtmlNode => {
-                                node_to_export = Some(current_editing_node.clone());
-                                ext = ".html";
-                                bytes = formats::node_to_html(current_editing_node, true).into();
-                            }
-                            TreeExportType::MdDoc => {
-                                node_to_export = Some(root_node.clone());
-                                ext = ".md";
-                                bytes = formats::node_to_md(root_node, true, false).into();
-                            }
-                            TreeExportType::MdNode => {
-                                node_to_export = Some(current_editing_node.clone());
-                                ext = ".md";
-                                bytes =
-                                    formats::node_to_md(current_editing_node, true, false).into();
-                            }
-                            TreeExportType::RtdNode => {
-                                node_to_export = Some(current_editing_node.clone());
-                                ext = ".rtd";
-                                bytes = if let Ok(b) = formats::rtd::serealize(
-                                    formats::rtd::document_to_rtd_document(Document {
-                                        root_node: current_editing_node.clone(),
-                                        resources: self.document.clone().resources,
-                                    }),
-                                ) {
-                                    b
-                                } else {
-                                    Vec::new()
-                                };
-                            }
-                            TreeExportType::JsonDoc => {
-                                node_to_export = Some(root_node.clone());
-                                ext = ".json";
-                                bytes = formats::node_to_json(root_node).into();
-                            }
-                            TreeExportType::JsonNode => {
-                                node_to_export = Some(current_editing_node.clone());
-                                ext = ".json";
-                                bytes = formats::node_to_json(current_editing_node).into();
-                            }
-                        }
-                        if let Some(node) = node_to_export {
-                            formats::save_format_dialog(node.clone().name + ext, bytes);
+                    Message::ExportNode(export_type) => self.export_node(export_type),
+                    Message::ImportNode(import_type) => {
+                        if self.is_node_editing {
+                            self.save_editing_node();
+                        } else {
+                            self.import_node(import_type);
                         }
                     }
                     Message::Undo => {
@@ -220,15 +166,34 @@ impl Application {
                     Message::SaveAs => {
                         self.save_as_btn();
                     }
+                    Message::NewDocument => {
+                        self.open_tab(DocumentTab::new_document());
+                    }
                     Message::OpenDocument => {
                         self.open_document();
                     }
+                    Message::CloseTab => self.close_tab(),
+                    Message::SelectTab(index) => self.select_tab(index),
+                    Message::OpenRecentDocument(index) => self.open_recent_document(index),
+                    Message::ClearRecentDocuments => {
+                        self.main_settings.recent_documents.clear();
+                        self.update_recent_documents_menu();
+                    }
                     Message::SaveDocument => {
                         if self.is_node_editing && !self.save_editing_node() {
                             continue;
                         }
                         self.save_document();
                     }
+                    Message::ShowSearch => self.show_search(),
+                    Message::Search => self.search(),
+                    Message::OpenSearchResult => self.open_search_result(),
+                    Message::ShowReplace => self.show_replace(),
+                    Message::QuickOpen => self.quick_open(),
+                    Message::CompleteReference => self.complete_reference(),
+                    Message::OpenBacklink => self.open_backlink(),
+                    Message::CheckDocument => self.check_document(),
+                    Message::OpenProblem => self.open_problem(),
                     Message::OpenSettings => {
                         settings::show(self);
                     }
@@ -236,6 +201,9 @@ impl Application {
                         let settings = &mut self.main_settings;
                         if let Some(ui) = &mut settings.ui {
                             settings.editor_text_size = ui.editor_text_size.value() as i32;
+                            settings.recent_documents_length =
+                                ui.recent_documents_length.value() as usize;
+                            settings.restore_session = ui.restore_session.is_checked();
                             if let Some(index) = ui.shortcuts_browser.selected_items().first() {
                                 if let Some((_, element_type)) = settings
                                     .elements_shortcuts_browser_indexes
@@ -271,6 +239,7 @@ impl Application {
                             }
                         }
                         self.apply_settings();
+                        self.update_recent_documents_menu();
                     }
                     Message::ResetShortcuts => {
                         let settings = &mut self.main_settings;
@@ -0,0 +1,589 @@
+use super::{
+    escape_xml,
+    html::{self, HtmlElement, HtmlNode},
+    image_size, image_type, process_node_content, utc_date_time, write_zip, ExportOptions,
+    NODE_LINK_PREFIX, RESOURCE_LINK_PREFIX,
+};
+use crate::{
+    types::{Document, Node},
+    utils::node_to_tree_vec,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// English Metric Units in a pixel (at 96 DPI)
+const EMU_PER_PIXEL: u64 = 9525;
+/// Maximum image width (6 inches)
+const MAX_IMAGE_WIDTH: u64 = 5486400;
+
+const WORD_NAMESPACES: &str = "xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\" xmlns:wp=\"http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing\" xmlns:a=\"http://schemas.openxmlformats.org/drawingml/2006/main\" xmlns:pic=\"http://schemas.openxmlformats.org/drawingml/2006/picture\"";
+
+const RELATIONSHIPS_NAMESPACE: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships";
+const RELATIONSHIP_TYPE_PREFIX: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships/";
+
+const ROOT_RELATIONSHIPS: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\"><Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"word/document.xml\"/><Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties\" Target=\"docProps/core.xml\"/></Relationships>";
+
+const CONTENT_TYPES: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\"><Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/><Default Extension=\"xml\" ContentType=\"application/xml\"/><Default Extension=\"png\" ContentType=\"image/png\"/><Default Extension=\"jpg\" ContentType=\"image/jpeg\"/><Default Extension=\"gif\" ContentType=\"image/gif\"/><Override PartName=\"/word/document.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml\"/><Override PartName=\"/word/styles.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml\"/><Override PartName=\"/word/numbering.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml\"/><Override PartName=\"/word/settings.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.settings+xml\"/><Override PartName=\"/docProps/core.xml\" ContentType=\"application/vnd.openxmlformats-package.core-properties+xml\"/></Types>";
+
+const SETTINGS: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:settings xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\"><w:updateFields w:val=\"true\"/><w:defaultTabStop w:val=\"708\"/></w:settings>";
+
+const SECTION_PROPERTIES: &str = "<w:sectPr><w:pgSz w:w=\"11906\" w:h=\"16838\"/><w:pgMar w:top=\"1440\" w:right=\"1440\" w:bottom=\"1440\" w:left=\"1440\" w:header=\"708\" w:footer=\"708\" w:gutter=\"0\"/></w:sectPr>";
+
+/// Formatting of runs
+#[derive(Clone, Default)]
+struct RunStyle {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strike: bool,
+    code: bool,
+    superscript: bool,
+    subscript: bool,
+    preformatted: bool,
+    /// Attribute of the hyperlink element (`r:id` or `w:anchor`)
+    link: Option<String>,
+}
+
+/// Properties of paragraphs
+#[derive(Clone, Default)]
+struct ParagraphStyle {
+    style: Option<&'static str>,
+    /// Numbering id and level
+    numbering: Option<(usize, usize)>,
+    alignment: Option<&'static str>,
+}
+
+/// Writes the main document part of a DOCX package
+struct DocxWriter<'a> {
+    document: &'a Document,
+    node_ids: BTreeSet<u32>,
+    /// Relationships of the main document part (id, type, target, is external)
+    relationships: Vec<(String, &'static str, String, bool)>,
+    /// Media parts (path in the package and bytes)
+    media: Vec<(String, Vec<u8>)>,
+    /// Relationship ids and sizes in EMU of the embedded resources
+    images: BTreeMap<String, Option<(String, u64, u64)>>,
+    /// Abstract numbering ids and levels of the numbering instances
+    numbering: Vec<(usize, usize)>,
+    drawings_count: usize,
+    bookmarks_count: usize,
+    body: String,
+    /// Runs of the current paragraph
+    runs: String,
+}
+
+/// Creates a Word document (Office Open XML) from the document
+pub fn document_to_docx(document: Document, options: &ExportOptions) -> Result<Vec<u8>, String> {
+    let mut writer = DocxWriter {
+        document: &document,
+        node_ids: node_to_tree_vec(document.root_node.clone(), Vec::new(), None)
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect(),
+        relationships: vec![
+            (
+                "rId1".to_string(),
+                "styles",
+                "styles.xml".to_string(),
+                false,
+            ),
+            (
+                "rId2".to_string(),
+                "numbering",
+                "numbering.xml".to_string(),
+                false,
+            ),
+            (
+                "rId3".to_string(),
+                "settings",
+                "settings.xml".to_string(),
+                false,
+            ),
+        ],
+        media: Vec::new(),
+        images: BTreeMap::new(),
+        numbering: Vec::new(),
+        drawings_count: 0,
+        bookmarks_count: 0,
+        body: String::new(),
+        runs: String::new(),
+    };
+    if options.table_of_contents {
+        writer.body.push_str("<w:p><w:pPr><w:pStyle w:val=\"TOCHeading\"/></w:pPr><w:r><w:t>Table of Contents</w:t></w:r></w:p><w:p><w:r><w:fldChar w:fldCharType=\"begin\" w:dirty=\"true\"/></w:r><w:r><w:instrText xml:space=\"preserve\"> TOC \\o \"1-9\" \\h \\z \\u </w:instrText></w:r><w:r><w:fldChar w:fldCharType=\"separate\"/></w:r><w:r><w:t>Update the field to see the table of contents.</w:t></w:r><w:r><w:fldChar w:fldCharType=\"end\"/></w:r></w:p>");
+    }
+    writer.write_node(&document.root_node, 0);
+
+    let mut files = vec![
+        ("[Content_Types].xml".to_string(), CONTENT_TYPES.into()),
+        ("_rels/.rels".to_string(), ROOT_RELATIONSHIPS.into()),
+        (
+            "docProps/core.xml".to_string(),
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:dcterms=\"http://purl.org/dc/terms/\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\"><dc:title>{}</dc:title><dcterms:created xsi:type=\"dcterms:W3CDTF\">{}</dcterms:created></cp:coreProperties>",
+                escape_xml(&document.root_node.name),
+                utc_date_time()
+            )
+            .into(),
+        ),
+        (
+            "word/document.xml".to_string(),
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:document {WORD_NAMESPACES}><w:body>{}{SECTION_PROPERTIES}</w:body></w:document>",
+                writer.body
+            )
+            .into(),
+        ),
+        ("word/styles.xml".to_string(), styles().into()),
+        (
+            "word/numbering.xml".to_string(),
+            numbering(&writer.numbering).into(),
+        ),
+        ("word/settings.xml".to_string(), SETTINGS.into()),
+        (
+            "word/_rels/document.xml.rels".to_string(),
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<Relationships xmlns=\"{RELATIONSHIPS_NAMESPACE}\">{}</Relationships>",
+                writer
+                    .relationships
+                    .iter()
+                    .map(|(id, relationship_type, target, is_external)| format!(
+                        "<Relationship Id=\"{id}\" Type=\"{RELATIONSHIP_TYPE_PREFIX}{relationship_type}\" Target=\"{}\"{}/>",
+                        escape_xml(target),
+                        if *is_external {
+                            " TargetMode=\"External\""
+                        } else {
+                            ""
+                        }
+                    ))
+                    .collect::<String>()
+            )
+            .into(),
+        ),
+    ];
+    files.extend(writer.media);
+    write_zip(files)
+}
+
+impl DocxWriter<'_> {
+    fn write_node(&mut self, node: &Node, depth: usize) {
+        self.bookmarks_count += 1;
+        self.body.push_str(&format!(
+            "<w:p><w:pPr><w:pStyle w:val=\"Heading{}\"/></w:pPr><w:bookmarkStart w:id=\"{1}\" w:name=\"node_{2}\"/>{3}<w:bookmarkEnd w:id=\"{1}\"/></w:p>",
+            (depth + 1).min(9),
+            self.bookmarks_count,
+            node.id,
+            text_runs(&node.name, "")
+        ));
+        let node_ids = &self.node_ids;
+        let content = process_node_content(
+            node,
+            self.document,
+            |name| format!("{RESOURCE_LINK_PREFIX}{name}"),
+            |id| {
+                if node_ids.contains(&id) {
+                    format!("{NODE_LINK_PREFIX}{id}")
+                } else {
+                    String::new()
+                }
+            },
+        );
+        self.write_nodes(
+            &html::parse_html(&content),
+            &RunStyle::default(),
+            &ParagraphStyle::default(),
+        );
+        self.flush(&ParagraphStyle::default());
+        for child in &node.children {
+            self.write_node(child, depth + 1);
+        }
+    }
+
+    fn write_nodes(&mut self, nodes: &[HtmlNode], run: &RunStyle, paragraph: &ParagraphStyle) {
+        for node in nodes {
+            match node {
+                HtmlNode::Text(text) => self.write_text(text, run),
+                HtmlNode::Element(e) => self.write_element(e, run, paragraph),
+            }
+        }
+    }
+
+    fn write_text(&mut self, text: &str, run: &RunStyle) {
+        let text = if run.preformatted {
+            text.to_string()
+        } else {
+            let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            let start = if text.starts_with(char::is_whitespace) && !self.runs.is_empty() {
+                " "
+            } else {
+                ""
+            };
+            let end = if text.ends_with(char::is_whitespace) && !collapsed.is_empty() {
+                " "
+            } else {
+                ""
+            };
+            format!("{start}{collapsed}{end}")
+        };
+        if text.is_empty() {
+            return;
+        }
+        let mut properties = String::new();
+        if run.link.is_some() {
+            properties.push_str("<w:rStyle w:val=\"Hyperlink\"/>");
+        }
+        if run.code || run.preformatted {
+            properties.push_str(
+                "<w:rFonts w:ascii=\"Courier New\" w:hAnsi=\"Courier New\" w:cs=\"Courier New\"/>",
+            );
+        }
+        if run.bold {
+            properties.push_str("<w:b/>");
+        }
+        if run.italic {
+            properties.push_str("<w:i/>");
+        }
+        if run.strike {
+            properties.push_str("<w:strike/>");
+        }
+        if run.underline {
+            properties.push_str("<w:u w:val=\"single\"/>");
+        }
+        if run.superscript {
+            properties.push_str("<w:vertAlign w:val=\"superscript\"/>");
+        } else if run.subscript {
+            properties.push_str("<w:vertAlign w:val=\"subscript\"/>");
+        }
+        let runs = text_runs(&text, &properties);
+        if let Some(link) = &run.link {
+            self.runs
+                .push_str(&format!("<w:hyperlink {link}>{runs}</w:hyperlink>"));
+        } else {
+            self.runs.push_str(&runs);
+        }
+    }
+
+    fn write_element(&mut self, e: &HtmlElement, run: &RunStyle, paragraph: &ParagraphStyle) {
+        let mut run = run.clone();
+        let mut block = paragraph.clone();
+        let is_block = match e.tag.as_str() {
+            "b" | "strong" => {
+                run.bold = true;
+                false
+            }
+            "i" | "em" | "cite" | "var" => {
+                run.italic = true;
+                false
+            }
+            "u" | "ins" => {
+                run.underline = true;
+                false
+            }
+            "s" | "strike" | "del" => {
+                run.strike = true;
+                false
+            }
+            "code" | "kbd" | "tt" | "samp" => {
+                run.code = true;
+                false
+            }
+            "sup" => {
+                run.superscript = true;
+                false
+            }
+            "sub" => {
+                run.subscript = true;
+                false
+            }
+            "a" => {
+                run.link = match e.attribute("href") {
+                    Some(href) if href.starts_with(NODE_LINK_PREFIX) => Some(format!(
+                        "w:anchor=\"node_{}\"",
+                        &href[NODE_LINK_PREFIX.len()..]
+                    )),
+                    Some(href) if !href.is_empty() => {
+                        let id = format!("rId{}", self.relationships.len() + 1);
+                        self.relationships
+                            .push((id.clone(), "hyperlink", href.to_string(), true));
+                        Some(format!("r:id=\"{id}\""))
+                    }
+                    _ => None,
+                };
+                false
+            }
+            "br" => {
+                self.runs.push_str("<w:r><w:br/></w:r>");
+                return;
+            }
+            "img" => {
+                if let Some(name) = e
+                    .attribute("src")
+                    .and_then(|src| src.strip_prefix(RESOURCE_LINK_PREFIX))
+                {
+                    self.write_image(name);
+                }
+                return;
+            }
+            "hr" => {
+                self.flush(paragraph);
+                self.body.push_str(
+                    "<w:p><w:pPr><w:pBdr><w:bottom w:val=\"single\" w:sz=\"6\" w:space=\"1\" w:color=\"auto\"/></w:pBdr></w:pPr></w:p>",
+                );
+                return;
+            }
+            "table" => {
+                self.flush(paragraph);
+                self.write_table(e, &run);
+                return;
+            }
+            "ul" | "ol" => {
+                self.flush(paragraph);
+                let level = paragraph.numbering.map_or(0, |(_, level)| level + 1);
+                self.numbering
+                    .push((if e.tag == "ul" { 0 } else { 1 }, level));
+                block.numbering = Some((self.numbering.len(), level));
+                block.style = Some("ListParagraph");
+                for child in &e.children {
+                    if let HtmlNode::Element(li) = child {
+                        self.write_nodes(&li.children, &run, &block);
+                        self.flush(&block);
+                    }
+                }
+                return;
+            }
+            "p" | "div" | "li" | "dt" | "dd" => true,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                run.bold = true;
+                true
+            }
+            "blockquote" => {
+                block.style = Some("Quote");
+                true
+            }
+            "center" => {
+                block.alignment = Some("center");
+                true
+            }
+            "pre" => {
+                run.preformatted = true;
+                block.style = Some("Code");
+                true
+            }
+            _ => false,
+        };
+        if is_block {
+            self.flush(paragraph);
+            self.write_nodes(&e.children, &run, &block);
+            self.flush(&block);
+        } else {
+            self.write_nodes(&e.children, &run, paragraph);
+        }
+    }
+
+    fn write_image(&mut self, name: &str) {
+        if !self.images.contains_key(name) {
+            let image = self.document.resources.get(name).and_then(|res| {
+                let (ext, _) = image_type(&res.bytes).filter(|(ext, _)| *ext != "svg")?;
+                let (width, height) = image_size(&res.bytes)?;
+                let (mut cx, mut cy) = (
+                    u64::from(width) * EMU_PER_PIXEL,
+                    u64::from(height) * EMU_PER_PIXEL,
+                );
+                if cx > MAX_IMAGE_WIDTH {
+                    cy = cy * MAX_IMAGE_WIDTH / cx;
+                    cx = MAX_IMAGE_WIDTH;
+                }
+                let id = format!("rId{}", self.relationships.len() + 1);
+                let path = format!("media/image{}.{ext}", self.media.len() + 1);
+                self.relationships
+                    .push((id.clone(), "image", path.clone(), false));
+                self.media.push((format!("word/{path}"), res.bytes.clone()));
+                Some((id, cx, cy))
+            });
+            self.images.insert(name.to_string(), image);
+        }
+        if let Some(Some((id, cx, cy))) = self.images.get(name).cloned() {
+            self.drawings_count += 1;
+            self.runs.push_str(&format!(
+                "<w:r><w:drawing><wp:inline distT=\"0\" distB=\"0\" distL=\"0\" distR=\"0\"><wp:extent cx=\"{cx}\" cy=\"{cy}\"/><wp:docPr id=\"{0}\" name=\"Picture {0}\" descr=\"{1}\"/><wp:cNvGraphicFramePr><a:graphicFrameLocks noChangeAspect=\"1\"/></wp:cNvGraphicFramePr><a:graphic><a:graphicData uri=\"http://schemas.openxmlformats.org/drawingml/2006/picture\"><pic:pic><pic:nvPicPr><pic:cNvPr id=\"{0}\" name=\"{1}\"/><pic:cNvPicPr/></pic:nvPicPr><pic:blipFill><a:blip r:embed=\"{id}\"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill><pic:spPr><a:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"{cx}\" cy=\"{cy}\"/></a:xfrm><a:prstGeom prst=\"rect\"><a:avLst/></a:prstGeom></pic:spPr></pic:pic></a:graphicData></a:graphic></wp:inline></w:drawing></w:r>",
+                self.drawings_count,
+                escape_xml(name)
+            ));
+        }
+    }
+
+    fn write_table(&mut self, table: &HtmlElement, run: &RunStyle) {
+        let rows = html::table_rows(&table.children);
+        let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let body = std::mem::take(&mut self.body);
+        let mut xml = format!(
+            "<w:tbl><w:tblPr><w:tblStyle w:val=\"TableGrid\"/><w:tblW w:w=\"0\" w:type=\"auto\"/></w:tblPr><w:tblGrid>{}</w:tblGrid>",
+            "<w:gridCol/>".repeat(columns)
+        );
+        for row in rows {
+            xml.push_str("<w:tr>");
+            for i in 0..columns {
+                let mut cell_run = run.clone();
+                if let Some(cell) = row.get(i) {
+                    cell_run.bold = cell.tag == "th";
+                    self.write_nodes(&cell.children, &cell_run, &ParagraphStyle::default());
+                }
+                self.flush(&ParagraphStyle::default());
+                let mut cell = std::mem::take(&mut self.body);
+                // A cell must end with a paragraph
+                if !cell.ends_with("</w:p>") {
+                    cell.push_str("<w:p/>");
+                }
+                xml.push_str(&format!(
+                    "<w:tc><w:tcPr><w:tcW w:w=\"0\" w:type=\"auto\"/></w:tcPr>{cell}</w:tc>"
+                ));
+            }
+            xml.push_str("</w:tr>");
+        }
+        xml.push_str("</w:tbl>");
+        self.body = body + &xml;
+    }
+
+    /// Writes the runs as a paragraph
+    fn flush(&mut self, paragraph: &ParagraphStyle) {
+        if self.runs.is_empty() {
+            return;
+        }
+        let mut properties = String::new();
+        if let Some(style) = paragraph.style {
+            properties.push_str(&format!("<w:pStyle w:val=\"{style}\"/>"));
+        }
+        if let Some((id, level)) = paragraph.numbering {
+            properties.push_str(&format!(
+                "<w:numPr><w:ilvl w:val=\"{level}\"/><w:numId w:val=\"{id}\"/></w:numPr>"
+            ));
+        }
+        if let Some(alignment) = paragraph.alignment {
+            properties.push_str(&format!("<w:jc w:val=\"{alignment}\"/>"));
+        }
+        self.body.push_str(&format!(
+            "<w:p><w:pPr>{properties}</w:pPr>{}</w:p>",
+            std::mem::take(&mut self.runs)
+        ));
+    }
+}
+
+/// Creates runs with the properties from text (line breaks and tabs are kept)
+fn text_runs(text: &str, properties: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            line.split('\t')
+                .map(|part| {
+                    format!(
+                        "<w:t xml:space=\"preserve\">{}</w:t>",
+                        part.replace('&', "&amp;")
+                            .replace('<', "&lt;")
+                            .replace('>', "&gt;")
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("<w:tab/>")
+        })
+        .map(|content| format!("<w:r><w:rPr>{properties}</w:rPr>{content}</w:r>"))
+        .collect::<Vec<_>>()
+        .join("<w:r><w:br/></w:r>")
+}
+
+fn numbering(instances: &[(usize, usize)]) -> String {
+    let abstract_numbering = |id: usize, format: &str| {
+        format!(
+            "<w:abstractNum w:abstractNumId=\"{id}\"><w:multiLevelType w:val=\"hybridMultilevel\"/>{}</w:abstractNum>",
+            (0..9)
+                .map(|level| format!(
+                    "<w:lvl w:ilvl=\"{level}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"{format}\"/><w:lvlText w:val=\"{}\"/><w:lvlJc w:val=\"left\"/><w:pPr><w:ind w:left=\"{}\" w:hanging=\"360\"/></w:pPr></w:lvl>",
+                    if format == "bullet" {
+                        "\u{2022}".to_string()
+                    } else {
+                        format!("%{}.", level + 1)
+                    },
+                    720 * (level + 1)
+                ))
+                .collect::<String>()
+        )
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:numbering xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">{}{}{}</w:numbering>",
+        abstract_numbering(0, "bullet"),
+        abstract_numbering(1, "decimal"),
+        instances
+            .iter()
+            .enumerate()
+            .map(|(i, (abstract_id, level))| format!(
+                "<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"{abstract_id}\"/><w:lvlOverride w:ilvl=\"{level}\"><w:startOverride w:val=\"1\"/></w:lvlOverride></w:num>",
+                i + 1
+            ))
+            .collect::<String>()
+    )
+}
+
+fn styles() -> String {
+    let headings = (1..=9)
+        .map(|level| {
+            format!(
+                "<w:style w:type=\"paragraph\" w:styleId=\"Heading{level}\"><w:name w:val=\"heading {level}\"/><w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/><w:qFormat/><w:pPr><w:keepNext/><w:spacing w:before=\"240\" w:after=\"120\"/><w:outlineLvl w:val=\"{}\"/></w:pPr><w:rPr><w:b/><w:sz w:val=\"{}\"/></w:rPr></w:style>",
+                level - 1,
+                (40 - level * 4).max(22)
+            )
+        })
+        .collect::<String>();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:styles xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\"><w:docDefaults><w:rPrDefault><w:rPr><w:rFonts w:ascii=\"Calibri\" w:hAnsi=\"Calibri\" w:cs=\"Calibri\"/><w:sz w:val=\"22\"/></w:rPr></w:rPrDefault><w:pPrDefault><w:pPr><w:spacing w:after=\"120\"/></w:pPr></w:pPrDefault></w:docDefaults><w:style w:type=\"paragraph\" w:default=\"1\" w:styleId=\"Normal\"><w:name w:val=\"Normal\"/><w:qFormat/></w:style>{headings}<w:style w:type=\"paragraph\" w:styleId=\"TOCHeading\"><w:name w:val=\"TOC Heading\"/><w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/><w:rPr><w:b/><w:sz w:val=\"32\"/></w:rPr></w:style><w:style w:type=\"paragraph\" w:styleId=\"ListParagraph\"><w:name w:val=\"List Paragraph\"/><w:basedOn w:val=\"Normal\"/><w:pPr><w:spacing w:after=\"0\"/><w:ind w:left=\"720\"/></w:pPr></w:style><w:style w:type=\"paragraph\" w:styleId=\"Quote\"><w:name w:val=\"Quote\"/><w:basedOn w:val=\"Normal\"/><w:pPr><w:ind w:left=\"720\" w:right=\"720\"/></w:pPr><w:rPr><w:i/></w:rPr></w:style><w:style w:type=\"paragraph\" w:styleId=\"Code\"><w:name w:val=\"Code\"/><w:basedOn w:val=\"Normal\"/><w:pPr><w:spacing w:after=\"0\"/></w:pPr><w:rPr><w:rFonts w:ascii=\"Courier New\" w:hAnsi=\"Courier New\" w:cs=\"Courier New\"/><w:sz w:val=\"20\"/></w:rPr></w:style><w:style w:type=\"character\" w:styleId=\"Hyperlink\"><w:name w:val=\"Hyperlink\"/><w:rPr><w:color w:val=\"0563C1\"/><w:u w:val=\"single\"/></w:rPr></w:style><w:style w:type=\"table\" w:styleId=\"TableGrid\"><w:name w:val=\"Table Grid\"/><w:tblPr><w:tblBorders><w:top w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/><w:left w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/><w:bottom w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/><w:right w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/><w:insideH w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/><w:insideV w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"auto\"/></w:tblBorders></w:tblPr></w:style></w:styles>"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    fn document_xml(document: Document) -> String {
+        let docx = document_to_docx(document, &Default::default()).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(docx)).unwrap();
+        let mut xml = String::new();
+        archive
+            .by_name("word/document.xml")
+            .unwrap()
+            .read_to_string(&mut xml)
+            .unwrap();
+        xml
+    }
+
+    #[test]
+    fn escapes_text_and_links_references_to_bookmarks() {
+        let xml = document_xml(Document {
+            root_node: node(
+                1,
+                "Root",
+                r#"<p><a href="reference!("id:2")">see</a> <a href="reference!("id:9")">gone</a></p>"#,
+                vec![node(
+                    2,
+                    "B & <C>",
+                    "<p>1 &lt; 2 &amp; \"3\"</p>",
+                    Vec::new(),
+                )],
+            ),
+            resources: BTreeMap::new(),
+        });
+        assert!(xml.contains("w:name=\"node_2\""), "{xml}");
+        assert!(xml.contains("<w:hyperlink w:anchor=\"node_2\""), "{xml}");
+        assert!(!xml.contains("node_9"), "{xml}");
+        assert!(xml.contains("B &amp; &lt;C&gt;"), "{xml}");
+        assert!(xml.contains("1 &lt; 2 &amp; \"3\""), "{xml}");
+        assert!(roxmltree::Document::parse(&xml).is_ok());
+    }
+}
@@ -0,0 +1,304 @@
+use super::{
+    escape_xml, html, image_type, process_node_content, utc_date_time, write_zip, ExportOptions,
+    DEFAULT_LANGUAGE,
+};
+use crate::types::{Document, Node};
+use rand::Rng;
+use std::collections::BTreeMap;
+
+const XHTML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">";
+
+const CONTAINER_XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n<rootfiles>\n<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n</rootfiles>\n</container>\n";
+
+/// A resource packaged into the EPUB
+struct EpubResource {
+    path: String,
+    media_type: &'static str,
+    bytes: Vec<u8>,
+}
+
+/// Creates an EPUB 3 book from the document.
+/// The root node is the title page, each of its children is a chapter.
+/// The table of contents is shown after the title page if the options ask for it
+pub fn document_to_epub(document: Document, options: &ExportOptions) -> Result<Vec<u8>, String> {
+    let root = &document.root_node;
+    let chapters = std::iter::once(root)
+        .chain(root.children.iter())
+        .collect::<Vec<_>>();
+
+    // Chapter files of the nodes, used to resolve references
+    let mut node_files = BTreeMap::new();
+    node_files.insert(root.id, chapter_file(0));
+    for (i, chapter) in root.children.iter().enumerate() {
+        add_node_files(chapter, &chapter_file(i + 1), &mut node_files);
+    }
+
+    let resources = document
+        .resources
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (name, res))| {
+            // Only images can be packaged without fallbacks
+            let (ext, media_type) = image_type(&res.bytes)?;
+            Some((
+                name.clone(),
+                EpubResource {
+                    path: format!("resources/{i}.{ext}"),
+                    media_type,
+                    bytes: res.bytes.clone(),
+                },
+            ))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut files = vec![
+        ("mimetype".to_string(), b"application/epub+zip".to_vec()),
+        (
+            "META-INF/container.xml".to_string(),
+            CONTAINER_XML.as_bytes().to_vec(),
+        ),
+        (
+            "OEBPS/content.opf".to_string(),
+            package_document(root, chapters.len(), &resources, options).into_bytes(),
+        ),
+        (
+            "OEBPS/nav.xhtml".to_string(),
+            navigation_document(root, &node_files).into_bytes(),
+        ),
+    ];
+    for (i, chapter) in chapters.iter().enumerate() {
+        let body = if i == 0 {
+            // Children of the root node are separate chapters
+            node_section(
+                &Node {
+                    children: Vec::new(),
+                    ..root.clone()
+                },
+                0,
+                &document,
+                &resources,
+                &node_files,
+            )
+        } else {
+            node_section(chapter, 0, &document, &resources, &node_files)
+        };
+        files.push((
+            format!("OEBPS/{}", chapter_file(i)),
+            format!(
+                "{XHTML_HEADER}\n<head>\n<title>{}</title>\n</head>\n<body>\n{body}</body>\n</html>\n",
+                escape_xml(&chapter.name)
+            )
+            .into_bytes(),
+        ));
+    }
+    for res in resources.into_values() {
+        files.push((format!("OEBPS/{}", res.path), res.bytes));
+    }
+    write_zip(files)
+}
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{index}.xhtml")
+}
+
+fn add_node_files(node: &Node, file: &str, node_files: &mut BTreeMap<u32, String>) {
+    node_files.insert(node.id, file.to_string());
+    for child in &node.children {
+        add_node_files(child, file, node_files);
+    }
+}
+
+/// Creates a section with the node heading, its content and children
+fn node_section(
+    node: &Node,
+    depth: usize,
+    document: &Document,
+    resources: &BTreeMap<String, EpubResource>,
+    node_files: &BTreeMap<u32, String>,
+) -> String {
+    let content = process_node_content(
+        node,
+        document,
+        |name| {
+            resources
+                .get(name)
+                .map(|res| res.path.clone())
+                .unwrap_or_default()
+        },
+        |id| {
+            node_files
+                .get(&id)
+                .map(|file| format!("{file}#node-{id}"))
+                .unwrap_or_default()
+        },
+    );
+    let level = (depth + 1).min(6);
+    format!(
+        "<section id=\"node-{}\">\n<h{level}>{}</h{level}>\n{}\n{}</section>\n",
+        node.id,
+        escape_xml(&node.name),
+        html::to_xhtml(&html::parse_html(&content)),
+        node.children
+            .iter()
+            .map(|n| node_section(n, depth + 1, document, resources, node_files))
+            .collect::<String>()
+    )
+}
+
+fn navigation_document(root: &Node, node_files: &BTreeMap<u32, String>) -> String {
+    let title_page = Node {
+        children: Vec::new(),
+        ..root.clone()
+    };
+    format!(
+        "{XHTML_HEADER}\n<head>\n<title>{}</title>\n</head>\n<body>\n<nav epub:type=\"toc\" id=\"toc\">\n<h1>Table of Contents</h1>\n<ol>\n{}</ol>\n</nav>\n</body>\n</html>\n",
+        escape_xml(&root.name),
+        std::iter::once(&title_page)
+            .chain(root.children.iter())
+            .map(|n| navigation_item(n, node_files))
+            .collect::<String>()
+    )
+}
+
+fn navigation_item(node: &Node, node_files: &BTreeMap<u32, String>) -> String {
+    format!(
+        "<li><a href=\"{}#node-{}\">{}</a>{}</li>\n",
+        node_files.get(&node.id).cloned().unwrap_or_default(),
+        node.id,
+        escape_xml(&node.name),
+        if node.children.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "\n<ol>\n{}</ol>",
+                node.children
+                    .iter()
+                    .map(|n| navigation_item(n, node_files))
+                    .collect::<String>()
+            )
+        }
+    )
+}
+
+fn package_document(
+    root: &Node,
+    chapters_count: usize,
+    resources: &BTreeMap<String, EpubResource>,
+    options: &ExportOptions,
+) -> String {
+    let language = Some(options.language.trim())
+        .filter(|language| !language.is_empty())
+        .unwrap_or(DEFAULT_LANGUAGE);
+    let mut rng = rand::thread_rng();
+    let uuid = format!(
+        "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+        rng.gen::<u32>(),
+        rng.gen::<u16>(),
+        rng.gen::<u16>() & 0xfff,
+        (rng.gen::<u16>() & 0x3fff) | 0x8000,
+        rng.gen::<u64>() & 0xffff_ffff_ffff
+    );
+    let chapters = 0..chapters_count;
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<dc:identifier id=\"book-id\">urn:uuid:{uuid}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n<meta property=\"dcterms:modified\">{}</meta>\n</metadata>\n<manifest>\n<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n{}{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+        escape_xml(&root.name),
+        escape_xml(language),
+        utc_date_time(),
+        chapters
+            .clone()
+            .map(|i| format!(
+                "<item id=\"chapter-{i}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+                chapter_file(i)
+            ))
+            .collect::<String>(),
+        resources
+            .values()
+            .enumerate()
+            .map(|(i, res)| format!(
+                "<item id=\"resource-{i}\" href=\"{}\" media-type=\"{}\"/>\n",
+                res.path, res.media_type
+            ))
+            .collect::<String>(),
+        chapters
+            .map(|i| {
+                let item = format!("<itemref idref=\"chapter-{i}\"/>\n");
+                if i == 0 && options.table_of_contents {
+                    item + "<itemref idref=\"nav\"/>\n"
+                } else {
+                    item
+                }
+            })
+            .collect::<String>()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    /// Exports the document and reads the file from the archive
+    fn epub_file(document: Document, options: &ExportOptions, file: &str) -> String {
+        let epub = document_to_epub(document, options).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(epub)).unwrap();
+        let mut text = String::new();
+        archive
+            .by_name(file)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    fn document() -> Document {
+        Document {
+            root_node: node(
+                1,
+                "Root",
+                "<p>text</p>",
+                vec![
+                    node(
+                        2,
+                        "A",
+                        r#"<p><a href="reference!("id:3")">see</a></p>"#,
+                        Vec::new(),
+                    ),
+                    node(3, "B & <C>", "<p>1 &lt; 2</p>", Vec::new()),
+                ],
+            ),
+            resources: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn escapes_names_and_links_references_to_chapters() {
+        let chapter = epub_file(document(), &Default::default(), "OEBPS/chapter-1.xhtml");
+        assert!(
+            chapter.contains("href=\"chapter-2.xhtml#node-3\""),
+            "{chapter}"
+        );
+        let chapter = epub_file(document(), &Default::default(), "OEBPS/chapter-2.xhtml");
+        assert!(chapter.contains("B &amp; &lt;C&gt;"), "{chapter}");
+        assert!(chapter.contains("1 &lt; 2"), "{chapter}");
+        let navigation = epub_file(document(), &Default::default(), "OEBPS/nav.xhtml");
+        assert!(navigation.contains("<a href=\"chapter-2.xhtml#node-3\">B &amp; &lt;C&gt;</a>"));
+    }
+
+    #[test]
+    fn declares_the_language_of_the_options() {
+        let package = |options: &ExportOptions| epub_file(document(), options, "OEBPS/content.opf");
+        assert!(package(&Default::default()).contains("<dc:language>en</dc:language>"));
+        let options = ExportOptions {
+            language: "pt-BR".to_string(),
+            ..Default::default()
+        };
+        assert!(package(&options).contains("<dc:language>pt-BR</dc:language>"));
+    }
+}
@@ -1,12 +1,516 @@
-use regex::Regex;
-
-pub fn remove_html_tags(html: String) -> String {
-    Regex::new(r"<.*?>|<\/.*?>")
-        .unwrap()
-        .replace_all(&html.replace("<br>", "\n"), "")
-        .to_string()
-}
+use std::ops::Range;
 
 pub fn to_markdown(html: String) -> String {
     html2md::parse_html(&html)
 }
+
+/// Elements that can't have children
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is not HTML
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+#[derive(Debug, Clone)]
+pub enum HtmlNode {
+    /// Text with decoded entities
+    Text(String),
+    Element(HtmlElement),
+}
+
+#[derive(Debug, Clone)]
+pub struct HtmlElement {
+    /// Lowercase tag name
+    pub tag: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<HtmlNode>,
+}
+
+impl HtmlElement {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Text of the nodes and their children
+pub fn nodes_text(nodes: &[HtmlNode]) -> String {
+    nodes
+        .iter()
+        .map(|n| match n {
+            HtmlNode::Text(text) => text.clone(),
+            HtmlNode::Element(e) if e.tag == "br" => "\n".to_string(),
+            HtmlNode::Element(e) => nodes_text(&e.children),
+        })
+        .collect()
+}
+
+/// Elements that start a new line in the text of HTML
+const BLOCK_ELEMENTS: [&str; 22] = [
+    "p",
+    "div",
+    "br",
+    "hr",
+    "li",
+    "ul",
+    "ol",
+    "dl",
+    "dt",
+    "dd",
+    "tr",
+    "td",
+    "th",
+    "table",
+    "blockquote",
+    "pre",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+];
+
+/// Text of HTML without tags. Entities are decoded and block elements are separated
+/// with line breaks
+pub fn remove_html_tags(html: &str) -> String {
+    html_text(html).text
+}
+
+/// Text of HTML (as `remove_html_tags`) with the bytes of the HTML each byte of the text
+/// comes from, so matches in the text can be found in the HTML
+#[derive(Debug, Clone, Default)]
+pub struct HtmlText {
+    pub text: String,
+    /// Byte ranges in the HTML: the character, the entity or the tag of a line break
+    sources: Vec<Range<usize>>,
+}
+
+impl HtmlText {
+    /// Range of the HTML with the text range, it may contain tags
+    pub fn html_range(&self, range: Range<usize>) -> Range<usize> {
+        if range.is_empty() {
+            let start = self
+                .sources
+                .get(range.start)
+                .map_or(usize::MAX, |s| s.start);
+            return start..start;
+        }
+        self.sources[range.start].start..self.sources[range.end - 1].end
+    }
+
+    fn push(&mut self, c: char, source: Range<usize>) {
+        self.text.push(c);
+        for _ in 0..c.len_utf8() {
+            self.sources.push(source.clone());
+        }
+    }
+
+    fn push_line_break(&mut self, source: Range<usize>) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.push('\n', source);
+        }
+    }
+
+    /// Pushes the text with decoded entities, `offset` is its position in the HTML
+    fn push_decoded(&mut self, text: &str, offset: usize) {
+        let mut chars = text.char_indices();
+        while let Some((i, c)) = chars.next() {
+            let entity = (c == '&')
+                .then(|| text[i + 1..].find(';'))
+                .flatten()
+                .filter(|end| *end <= 10)
+                .and_then(|end| decode_entity(&text[i + 1..i + 1 + end]).map(|c| (c, end + 2)));
+            match entity {
+                Some((decoded, len)) => {
+                    self.push(decoded, offset + i..offset + i + len);
+                    // The rest of the entity is ASCII
+                    for _ in 1..len {
+                        chars.next();
+                    }
+                }
+                None => self.push(c, offset + i..offset + i + c.len_utf8()),
+            }
+        }
+    }
+}
+
+/// Text of HTML with the sources of its bytes. Elements are opened and closed as in
+/// `parse_html`, so the text is the text of its nodes
+pub fn html_text(html: &str) -> HtmlText {
+    let mut result = HtmlText::default();
+    let mut stack = vec![HtmlElement {
+        tag: String::new(),
+        attributes: Vec::new(),
+        children: Vec::new(),
+    }];
+    let is_block = |tag: &str| BLOCK_ELEMENTS.contains(&tag);
+    let mut offset = 0;
+    while offset < html.len() {
+        let rest = &html[offset..];
+        let start = offset;
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            offset += 4 + comment.find("-->").map_or(comment.len(), |i| i + 3);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            offset += rest.find('>').map_or(rest.len(), |i| i + 1);
+        } else if let Some(closing) = rest.strip_prefix("</") {
+            let end = closing.find('>').unwrap_or(closing.len());
+            let tag = closing[..end].trim().to_lowercase();
+            offset += 2 + (end + 1).min(closing.len());
+            if stack.iter().skip(1).any(|e| e.tag == tag) {
+                let mut closes_block = false;
+                while let Some(element) = pop_element(&mut stack) {
+                    closes_block |= is_block(&element);
+                    if element == tag {
+                        break;
+                    }
+                }
+                if closes_block {
+                    result.push_line_break(start..offset);
+                }
+            }
+        } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+        {
+            let (element, self_closing, after) = parse_tag(&rest[1..]);
+            offset = html.len() - after.len();
+            let open = stack.len();
+            close_implicitly(&mut stack, &element.tag);
+            if stack.len() < open || is_block(&element.tag) {
+                result.push_line_break(start..offset);
+            }
+            if RAW_TEXT_ELEMENTS.contains(&element.tag.as_str()) {
+                let closing = format!("</{}", element.tag);
+                offset = after
+                    .to_ascii_lowercase()
+                    .find(&closing)
+                    .and_then(|i| after[i..].find('>').map(|j| offset + i + j + 1))
+                    .unwrap_or(html.len());
+            } else if !self_closing && !VOID_ELEMENTS.contains(&element.tag.as_str()) {
+                stack.push(element);
+            }
+        } else {
+            let first = rest.chars().next().unwrap().len_utf8();
+            let end = rest[first..].find('<').map_or(rest.len(), |i| i + first);
+            result.push_decoded(&rest[..end], offset);
+            offset += end;
+        }
+    }
+    let trimmed = result.text.trim_end().len();
+    result.text.truncate(trimmed);
+    result.sources.truncate(trimmed);
+    result
+}
+
+/// Finds rows of a table (including rows in `thead`, `tbody` and `tfoot`)
+pub fn table_rows(nodes: &[HtmlNode]) -> Vec<Vec<&HtmlElement>> {
+    let mut rows = Vec::new();
+    for node in nodes {
+        if let HtmlNode::Element(e) = node {
+            if e.tag == "tr" {
+                rows.push(
+                    e.children
+                        .iter()
+                        .filter_map(|n| match n {
+                            HtmlNode::Element(cell) if cell.tag == "td" || cell.tag == "th" => {
+                                Some(cell)
+                            }
+                            _ => None,
+                        })
+                        .collect(),
+                );
+            } else {
+                rows.extend(table_rows(&e.children));
+            }
+        }
+    }
+    rows
+}
+
+/// Parses HTML into a tree of nodes.
+/// It is tolerant: unclosed elements are closed at the end and unknown closing tags are ignored
+pub fn parse_html(html: &str) -> Vec<HtmlNode> {
+    // The last element is the one being filled, the first one is a fake root
+    let mut stack = vec![HtmlElement {
+        tag: String::new(),
+        attributes: Vec::new(),
+        children: Vec::new(),
+    }];
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |i| &comment[i + 3..]);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |i| &rest[i + 1..]);
+        } else if let Some(closing) = rest.strip_prefix("</") {
+            let end = closing.find('>').unwrap_or(closing.len());
+            let tag = closing[..end].trim().to_lowercase();
+            rest = closing.get(end + 1..).unwrap_or("");
+            if stack.iter().skip(1).any(|e| e.tag == tag) {
+                while let Some(element) = pop_element(&mut stack) {
+                    if element == tag {
+                        break;
+                    }
+                }
+            }
+        } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic())
+        {
+            let (element, self_closing, after) = parse_tag(&rest[1..]);
+            rest = after;
+            close_implicitly(&mut stack, &element.tag);
+            if RAW_TEXT_ELEMENTS.contains(&element.tag.as_str()) {
+                // The content of scripts and styles is skipped
+                let closing = format!("</{}", element.tag);
+                rest = rest
+                    .to_ascii_lowercase()
+                    .find(&closing)
+                    .and_then(|i| rest[i..].find('>').map(|j| &rest[i + j + 1..]))
+                    .unwrap_or("");
+            } else if self_closing || VOID_ELEMENTS.contains(&element.tag.as_str()) {
+                push_child(&mut stack, HtmlNode::Element(element));
+            } else {
+                stack.push(element);
+            }
+        } else {
+            let first = rest.chars().next().unwrap().len_utf8();
+            let end = rest[first..].find('<').map_or(rest.len(), |i| i + first);
+            push_child(&mut stack, HtmlNode::Text(decode_entities(&rest[..end])));
+            rest = &rest[end..];
+        }
+    }
+    while pop_element(&mut stack).is_some() {}
+    stack.pop().unwrap().children
+}
+
+/// Pops the last element into its parent and returns its tag
+fn pop_element(stack: &mut Vec<HtmlElement>) -> Option<String> {
+    if stack.len() > 1 {
+        let element = stack.pop().unwrap();
+        let tag = element.tag.clone();
+        push_child(stack, HtmlNode::Element(element));
+        Some(tag)
+    } else {
+        None
+    }
+}
+
+fn push_child(stack: &mut [HtmlElement], node: HtmlNode) {
+    let parent = stack.last_mut().unwrap();
+    // Merging texts split by ignored tags
+    if let (HtmlNode::Text(text), Some(HtmlNode::Text(last))) = (&node, parent.children.last_mut())
+    {
+        last.push_str(text);
+    } else {
+        parent.children.push(node);
+    }
+}
+
+/// Closes elements that can't contain the opening one (e.g. `<li>` closes the previous `<li>`)
+fn close_implicitly(stack: &mut Vec<HtmlElement>, tag: &str) {
+    let (closes, stops): (&[&str], &[&str]) = match tag {
+        "li" => (&["li"], &["ul", "ol"]),
+        "dt" | "dd" => (&["dt", "dd"], &["dl"]),
+        "tr" => (&["tr", "td", "th"], &["table"]),
+        "td" | "th" => (&["td", "th"], &["tr", "table"]),
+        "p" | "ul" | "ol" | "table" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "pre"
+        | "blockquote" | "div" | "hr" => (&["p"], &["li", "td", "th", "div", "blockquote"]),
+        _ => return,
+    };
+    while let Some(index) = stack
+        .iter()
+        .rposition(|e| closes.contains(&e.tag.as_str()) || stops.contains(&e.tag.as_str()))
+    {
+        if index == 0 || !closes.contains(&stack[index].tag.as_str()) {
+            break;
+        }
+        while stack.len() > index {
+            pop_element(stack);
+        }
+    }
+}
+
+/// Parses a tag after `<`, returns the element, whether it is self-closing and the rest
+fn parse_tag(text: &str) -> (HtmlElement, bool, &str) {
+    let name_end = text
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(text.len());
+    let mut element = HtmlElement {
+        tag: text[..name_end].to_lowercase(),
+        attributes: Vec::new(),
+        children: Vec::new(),
+    };
+    let mut rest = &text[name_end..];
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            return (element, true, after);
+        } else if let Some(after) = rest.strip_prefix('>') {
+            return (element, false, after);
+        } else if rest.is_empty() {
+            return (element, false, rest);
+        } else if let Some(after) = rest.strip_prefix('/') {
+            rest = after;
+            continue;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+            .unwrap_or(rest.len())
+            .max(1);
+        let name = rest[..name_end].to_lowercase();
+        rest = rest[name_end..].trim_start();
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            rest = after.trim_start();
+            if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                let end = rest[1..].find(quote).map_or(rest.len(), |i| i + 1);
+                value = decode_entities(&rest[1..end]);
+                rest = rest.get(end + 1..).unwrap_or("");
+            } else {
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(rest.len());
+                value = decode_entities(&rest[..end]);
+                rest = &rest[end..];
+            }
+        }
+        element.attributes.push((name, value));
+    }
+}
+
+/// Decodes HTML entities (`&amp;`, `&#33;`, `&#x21;`, ...)
+pub fn decode_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|c| (c, end + 2)));
+        if let Some((c, len)) = decoded {
+            result.push(c);
+            rest = &rest[len..];
+        } else {
+            result.push('&');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code = if let Some(hex) = number.strip_prefix(['x', 'X']) {
+            u32::from_str_radix(hex, 16).ok()?
+        } else {
+            number.parse().ok()?
+        };
+        return char::from_u32(code);
+    }
+    Some(match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "deg" => '°',
+        "plusmn" => '±',
+        "times" => '×',
+        "divide" => '÷',
+        "middot" => '·',
+        "bull" => '•',
+        "hellip" => '…',
+        "ndash" => '–',
+        "mdash" => '—',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "euro" => '€',
+        "pound" => '£',
+        "yen" => '¥',
+        "cent" => '¢',
+        "sect" => '§',
+        "para" => '¶',
+        "larr" => '←',
+        "rarr" => '→',
+        "uarr" => '↑',
+        "darr" => '↓',
+        _ => return None,
+    })
+}
+
+/// Serializes nodes as XHTML
+pub fn to_xhtml(nodes: &[HtmlNode]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            HtmlNode::Text(text) => escape_text(text),
+            HtmlNode::Element(e) => {
+                let attributes = e
+                    .attributes
+                    .iter()
+                    .filter(|(name, _)| {
+                        name.chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                    })
+                    .map(|(name, value)| format!(" {name}=\"{}\"", escape_text(value)))
+                    .collect::<String>();
+                if VOID_ELEMENTS.contains(&e.tag.as_str()) {
+                    format!("<{}{attributes}/>", e.tag)
+                } else {
+                    format!(
+                        "<{}{attributes}>{}</{}>",
+                        e.tag,
+                        to_xhtml(&e.children),
+                        e.tag
+                    )
+                }
+            }
+        })
+        .collect()
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_separates_blocks_and_decodes_entities() {
+        assert_eq!(
+            remove_html_tags("<p>a <b>b</b></p><ul><li>c<li>d</ul>e<br>f &amp; &lt;g&gt;"),
+            "a b\nc\nd\ne\nf & <g>"
+        );
+        assert_eq!(
+            remove_html_tags("<script>x</script><!-- y -->z<p> </p>"),
+            "z"
+        );
+    }
+
+    #[test]
+    fn text_ranges_point_to_the_html() {
+        let html = "<p>AT&amp;T <i>é</i>x</p>";
+        let text = html_text(html);
+        assert_eq!(text.text, "AT&T éx");
+        assert_eq!(&html[text.html_range(0..4)], "AT&amp;T");
+        assert_eq!(&html[text.html_range(5..8)], "é</i>x");
+    }
+}
@@ -0,0 +1,157 @@
+//! JSON document format.
+//!
+//! A JSON document has the following schema:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "root_node": {
+//!     "id": 1053420121,
+//!     "name": "Root",
+//!     "content": "<b>HTML</b> content with resource!(\"image.png\")",
+//!     "children": [],
+//!     "metadata": {
+//!       "CREATED": "2024-01-31"
+//!     }
+//!   },
+//!   "resources": {
+//!     "image.png": "iVBORw0KGgo..."
+//!   }
+//! }
+//! ```
+//!
+//! - `version` is the version of the schema (currently `1`).
+//! - `root_node` is a node: `id` is an unsigned 32-bit integer that is unique in the
+//!   document, `name` is unique among the siblings, `content` is the node text in HTML and
+//!   `children` are the child nodes, `metadata` maps property names to values (it is omitted
+//!   when empty). Only `name` is required, a missing `id` is generated.
+//! - `resources` maps resource names to their bytes encoded in standard base64.
+//!
+//! A node exported as JSON has the same schema, it is the root node and only the
+//! resources used by the node and its children are included.
+
+use crate::types::{Document, Node, Resource};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const JSON_DOCUMENT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct JsonDocument {
+    #[serde(default = "default_version")]
+    version: u32,
+    root_node: Node,
+    #[serde(default)]
+    resources: BTreeMap<String, String>,
+}
+
+fn default_version() -> u32 {
+    JSON_DOCUMENT_VERSION
+}
+
+pub fn document_to_json(document: Document) -> String {
+    serde_json::to_string_pretty(&JsonDocument {
+        version: JSON_DOCUMENT_VERSION,
+        root_node: document.root_node,
+        resources: document
+            .resources
+            .iter()
+            .map(|(name, res)| (name.clone(), STANDARD.encode(&res.bytes)))
+            .collect(),
+    })
+    .unwrap()
+}
+
+pub fn json_to_document(text: String) -> Result<Document, String> {
+    let json = serde_json::from_str::<JsonDocument>(&text).map_err(|e| e.to_string())?;
+    if json.version > JSON_DOCUMENT_VERSION {
+        return Err(format!(
+            "Unsupported JSON document version {} (the latest supported is {JSON_DOCUMENT_VERSION})",
+            json.version
+        ));
+    }
+    let mut resources = BTreeMap::new();
+    for (name, base64) in json.resources {
+        match STANDARD.decode(base64) {
+            Ok(bytes) => {
+                resources.insert(name, Resource::new(bytes));
+            }
+            Err(e) => return Err(format!("Can't decode resource {name}: {e}")),
+        }
+    }
+    check_node(&json.root_node, &mut BTreeSet::new())?;
+    Ok(Document {
+        root_node: json.root_node,
+        resources,
+    })
+}
+
+/// Checks that ids are unique and names are not empty and unique among siblings
+fn check_node(node: &Node, ids: &mut BTreeSet<u32>) -> Result<(), String> {
+    if node.name.trim().is_empty() {
+        return Err(format!("Node {} has an empty name", node.id));
+    }
+    if !ids.insert(node.id) {
+        return Err(format!("Node id {} is used more than once", node.id));
+    }
+    let mut names = BTreeSet::new();
+    for child in &node.children {
+        if !names.insert(&child.name) {
+            return Err(format!(
+                "There is already a node named {} in {}",
+                child.name, node.name
+            ));
+        }
+        check_node(child, ids)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> Document {
+        let mut child = Node {
+            id: 2,
+            ..Node::new(
+                "Child".to_string(),
+                "<p>\"quoted\" \\</p>".to_string(),
+                Vec::new(),
+            )
+        };
+        child
+            .metadata
+            .insert("CREATED".to_string(), "2024-01-31".to_string());
+        Document {
+            root_node: Node {
+                id: 1,
+                ..Node::new(
+                    "Root".to_string(),
+                    r#"reference!("id:2") resource!("a.png")"#.to_string(),
+                    vec![child],
+                )
+            },
+            resources: BTreeMap::from([("a.png".to_string(), Resource::new(vec![0, 1, 255]))]),
+        }
+    }
+
+    #[test]
+    fn documents_round_trip() {
+        let json = document_to_json(document());
+        let imported = json_to_document(json.clone()).unwrap();
+        assert_eq!(document_to_json(imported), json);
+    }
+
+    #[test]
+    fn rejects_newer_versions_and_repeated_ids() {
+        let json = document_to_json(document());
+        let newer = json.replace("\"version\": 1", "\"version\": 2");
+        assert!(json_to_document(newer).unwrap_err().contains("version 2"));
+        let repeated = json.replace("\"id\": 2", "\"id\": 1");
+        assert!(json_to_document(repeated)
+            .unwrap_err()
+            .contains("more than once"));
+    }
+}
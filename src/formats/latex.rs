@@ -0,0 +1,401 @@
+use super::{
+    html::{self, HtmlElement, HtmlNode},
+    image_type, process_node_content, ExportOptions, NODE_LINK_PREFIX,
+};
+use crate::{
+    types::{Document, Node},
+    utils::node_to_tree_vec,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+const SECTIONING_COMMANDS: [&str; 6] = [
+    "chapter",
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+    "subparagraph",
+];
+
+/// Creates a LaTeX document from the document.
+/// Returns the `.tex` file and the image files that must be written next to it
+pub fn document_to_latex(
+    document: Document,
+    options: &ExportOptions,
+) -> (String, Vec<(String, Vec<u8>)>) {
+    let root = &document.root_node;
+    let node_ids = node_to_tree_vec(root.clone(), Vec::new(), None)
+        .into_iter()
+        .map(|(_, id)| id)
+        .collect::<BTreeSet<_>>();
+
+    // Only PNG and JPEG images are supported by pdflatex. Files are named after the whole
+    // resource name (`a.png` is `a-png.png`), so `a.png` and `a.jpg` don't overwrite each other
+    let mut file_names = BTreeSet::new();
+    let images = document
+        .resources
+        .iter()
+        .filter_map(|(name, res)| {
+            let (ext, _) =
+                image_type(&res.bytes).filter(|(ext, _)| ["png", "jpg"].contains(ext))?;
+            let stem = name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                .collect::<String>();
+            // Names that differ only in other characters get numbers
+            let file_name = (1..)
+                .map(|i| match i {
+                    1 => format!("{stem}.{ext}"),
+                    _ => format!("{stem}-{i}.{ext}"),
+                })
+                .find(|file_name| !file_names.contains(file_name))
+                .unwrap();
+            file_names.insert(file_name.clone());
+            Some((name.clone(), (file_name, res.bytes.clone())))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut body = node_to_latex(root, 0, &document, &node_ids, &images);
+    if !options.numbering {
+        // Unnumbered sections can only be referenced by page
+        body = body.replace("~(\\ref{node:", "~(p.~\\pageref{node:");
+    }
+    let title = escape_latex(&root.name);
+    // T1 fonts have no Cyrillic letters, T2A fonts and babel write them in any language
+    let fonts = if format!("{title}{body}").chars().any(is_cyrillic) {
+        "\\usepackage[T2A,T1]{fontenc}\n\\usepackage[russian,english]{babel}"
+    } else {
+        "\\usepackage[T1]{fontenc}"
+    };
+    let tex = format!(
+        "\\documentclass{{report}}\n\\usepackage[utf8]{{inputenc}}\n{fonts}\n\\usepackage{{graphicx}}\n\\usepackage{{hyperref}}\n{}\n\\title{{{title}}}\n\\date{{}}\n\n\\begin{{document}}\n\\maketitle\n{}\n{body}\\end{{document}}\n",
+        if options.numbering {
+            ""
+        } else {
+            "\\setcounter{secnumdepth}{-2}\n"
+        },
+        if options.table_of_contents {
+            "\\tableofcontents\n"
+        } else {
+            ""
+        }
+    );
+    (tex, images.into_values().collect())
+}
+
+fn node_to_latex(
+    node: &Node,
+    depth: usize,
+    document: &Document,
+    node_ids: &BTreeSet<u32>,
+    images: &BTreeMap<String, (String, Vec<u8>)>,
+) -> String {
+    let content = process_node_content(
+        node,
+        document,
+        |name| {
+            images
+                .get(name)
+                .map(|(file, _)| file.clone())
+                .unwrap_or_default()
+        },
+        |id| {
+            if node_ids.contains(&id) {
+                format!("{NODE_LINK_PREFIX}{id}")
+            } else {
+                String::new()
+            }
+        },
+    );
+    format!(
+        "\\{}{{{}}}\n\\label{{node:{}}}\n\n{}\n\n{}",
+        SECTIONING_COMMANDS[depth.min(SECTIONING_COMMANDS.len() - 1)],
+        escape_latex(&node.name),
+        node.id,
+        html_to_latex(&html::parse_html(&content)).trim(),
+        node.children
+            .iter()
+            .map(|n| node_to_latex(n, depth + 1, document, node_ids, images))
+            .collect::<String>()
+    )
+}
+
+/// Converts HTML to LaTeX
+fn html_to_latex(nodes: &[HtmlNode]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            HtmlNode::Text(text) => escape_latex(text),
+            HtmlNode::Element(e) => element_to_latex(e),
+        })
+        .collect()
+}
+
+fn element_to_latex(e: &HtmlElement) -> String {
+    let content = || html_to_latex(&e.children);
+    match e.tag.as_str() {
+        "b" | "strong" => format!("\\textbf{{{}}}", content()),
+        "i" | "em" | "cite" | "var" => format!("\\emph{{{}}}", content()),
+        "u" | "ins" => format!("\\underline{{{}}}", content()),
+        "code" | "kbd" | "tt" | "samp" => format!("\\texttt{{{}}}", content()),
+        "sup" => format!("\\textsuperscript{{{}}}", content()),
+        "sub" => format!("\\textsubscript{{{}}}", content()),
+        "br" => "\\newline{}\n".to_string(),
+        "hr" => "\n\n\\noindent\\rule{\\linewidth}{0.4pt}\n\n".to_string(),
+        "p" | "div" => format!("\n\n{}\n\n", content().trim()),
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            format!("\n\n\\textbf{{{}}}\n\n", content().trim())
+        }
+        "blockquote" => format!("\n\\begin{{quote}}\n{}\n\\end{{quote}}\n", content().trim()),
+        "center" => format!(
+            "\n\\begin{{center}}\n{}\n\\end{{center}}\n",
+            content().trim()
+        ),
+        "pre" => {
+            let text = html::nodes_text(&e.children);
+            let text = text.trim_matches('\n');
+            // verbatim ends at the first \end{verbatim}, so such text is escaped instead
+            if text.contains("\\end{verbatim}") {
+                let lines = text
+                    .lines()
+                    .map(|line| escape_latex(line).replace(' ', "~"))
+                    .collect::<Vec<_>>();
+                format!(
+                    "\n\n\\noindent\\texttt{{{}}}\n\n",
+                    lines.join("\\newline{}\n")
+                )
+            } else {
+                format!("\n\\begin{{verbatim}}\n{text}\n\\end{{verbatim}}\n")
+            }
+        }
+        "ul" | "ol" => {
+            let environment = if e.tag == "ul" {
+                "itemize"
+            } else {
+                "enumerate"
+            };
+            let items = e
+                .children
+                .iter()
+                .filter_map(|n| match n {
+                    HtmlNode::Element(li) if li.tag == "li" => {
+                        Some(format!("\\item {}\n", html_to_latex(&li.children).trim()))
+                    }
+                    _ => None,
+                })
+                .collect::<String>();
+            if items.is_empty() {
+                String::new()
+            } else {
+                format!("\n\\begin{{{environment}}}\n{items}\\end{{{environment}}}\n")
+            }
+        }
+        "table" => table_to_latex(e),
+        "img" => match e.attribute("src") {
+            Some(src) if !src.is_empty() => {
+                format!("\n\n\\includegraphics[width=0.8\\linewidth,keepaspectratio]{{{src}}}\n\n")
+            }
+            _ => e.attribute("alt").map(escape_latex).unwrap_or_default(),
+        },
+        "a" => match e.attribute("href") {
+            Some(href) if href.starts_with(NODE_LINK_PREFIX) => format!(
+                "{}~(\\ref{{node:{}}})",
+                content(),
+                &href[NODE_LINK_PREFIX.len()..]
+            ),
+            Some(href) if !href.is_empty() => {
+                format!("\\href{{{}}}{{{}}}", escape_url(href), content())
+            }
+            _ => content(),
+        },
+        _ => content(),
+    }
+}
+
+fn table_to_latex(table: &HtmlElement) -> String {
+    let rows = html::table_rows(&table.children);
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+    format!(
+        "\n\\begin{{tabular}}{{|{}}}\n\\hline\n{}\\end{{tabular}}\n",
+        "l|".repeat(columns),
+        rows.iter()
+            .map(|row| {
+                let mut cells = row
+                    .iter()
+                    .map(|cell| {
+                        let text = html_to_latex(&cell.children).trim().replace('\n', " ");
+                        if cell.tag == "th" {
+                            format!("\\textbf{{{text}}}")
+                        } else {
+                            text
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                cells.resize(columns, String::new());
+                format!("{} \\\\\n\\hline\n", cells.join(" & "))
+            })
+            .collect::<String>()
+    )
+}
+
+/// Escapes LaTeX special characters
+fn escape_latex(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' => "\\textbackslash{}".to_string(),
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => format!("\\{c}"),
+            '~' => "\\textasciitilde{}".to_string(),
+            '^' => "\\textasciicircum{}".to_string(),
+            '\u{a0}' => "~".to_string(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+/// Escapes the URL for `\href`. Characters that are special in LaTeX arguments are
+/// percent-encoded, `%` and `#` are escaped
+fn escape_url(url: &str) -> String {
+    url.chars()
+        .map(|c| match c {
+            '\\' | '{' | '}' | '~' | '_' | '^' => format!("%{:02X}", c as u8),
+            _ => c.to_string(),
+        })
+        .collect::<String>()
+        .replace('%', "\\%")
+        .replace('#', "\\#")
+}
+
+fn is_cyrillic(c: char) -> bool {
+    ('\u{400}'..='\u{4ff}').contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Resource;
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    #[test]
+    fn escapes_special_characters_and_links_references() {
+        let document = Document {
+            root_node: node(
+                1,
+                "Root",
+                r#"<p>50% &amp; $5 {a}_b ~^\</p><p><a href="reference!("id:2")">see</a> <a href="reference!("id:9")">gone</a></p>"#,
+                vec![node(2, "A_B #1", "", Vec::new())],
+            ),
+            resources: BTreeMap::new(),
+        };
+        let (tex, _) = document_to_latex(document.clone(), &Default::default());
+        assert!(
+            tex.contains(
+                r"50\% \& \$5 \{a\}\_b \textasciitilde{}\textasciicircum{}\textbackslash{}"
+            ),
+            "{tex}"
+        );
+        assert!(tex.contains(r"{A\_B \#1}"), "{tex}");
+        assert!(tex.contains(r"see~(p.~\pageref{node:2})"), "{tex}");
+        assert!(tex.contains("gone") && !tex.contains("node:9"), "{tex}");
+        let options = ExportOptions {
+            numbering: true,
+            ..Default::default()
+        };
+        let (tex, _) = document_to_latex(document, &options);
+        assert!(tex.contains(r"see~(\ref{node:2})"), "{tex}");
+    }
+
+    #[test]
+    fn escapes_link_targets() {
+        let document = Document {
+            root_node: node(
+                1,
+                "Root",
+                r#"<a href="http://x.org/~a_b/{c}\d?e=50%#f">link</a>"#,
+                Vec::new(),
+            ),
+            resources: BTreeMap::new(),
+        };
+        let (tex, _) = document_to_latex(document, &Default::default());
+        assert!(
+            tex.contains(r"\href{http://x.org/\%7Ea\%5Fb/\%7Bc\%7D\%5Cd?e=50\%\#f}{link}"),
+            "{tex}"
+        );
+    }
+
+    #[test]
+    fn preformatted_text_can_contain_the_end_of_verbatim() {
+        let document = Document {
+            root_node: node(
+                1,
+                "Root",
+                "<pre>a  b</pre><pre>x\n\\end{verbatim}\n  y_</pre>",
+                Vec::new(),
+            ),
+            resources: BTreeMap::new(),
+        };
+        let (tex, _) = document_to_latex(document, &Default::default());
+        assert!(
+            tex.contains("\\begin{verbatim}\na  b\n\\end{verbatim}"),
+            "{tex}"
+        );
+        assert!(
+            tex.contains(
+                r"\noindent\texttt{x\newline{}
+\textbackslash{}end\{verbatim\}\newline{}
+~~y\_}"
+            ),
+            "{tex}"
+        );
+        assert_eq!(tex.matches(r"\end{verbatim}").count(), 1, "{tex}");
+    }
+
+    #[test]
+    fn cyrillic_text_loads_cyrillic_fonts() {
+        let document = |content: &str| Document {
+            root_node: node(1, "Root", content, Vec::new()),
+            resources: BTreeMap::new(),
+        };
+        let (tex, _) = document_to_latex(document("<p>Привет</p>"), &Default::default());
+        assert!(tex.contains("\\usepackage[T2A,T1]{fontenc}\n"), "{tex}");
+        assert!(
+            tex.contains("\\usepackage[russian,english]{babel}\n"),
+            "{tex}"
+        );
+        let (tex, _) = document_to_latex(document("<p>Déjà vu</p>"), &Default::default());
+        assert!(tex.contains("\\usepackage[T1]{fontenc}\n"), "{tex}");
+        assert!(!tex.contains("babel"), "{tex}");
+    }
+
+    #[test]
+    fn images_with_the_same_stem_are_separate_files() {
+        let document = Document {
+            root_node: Node::new(
+                "Root".to_string(),
+                r#"<img src="resource!("a.png")"><img src="resource!("a.jpg")"><img src="resource!("a-png")">"#
+                    .to_string(),
+                Vec::new(),
+            ),
+            resources: BTreeMap::from([
+                ("a.png".to_string(), Resource::new(b"\x89PNG".to_vec())),
+                ("a.jpg".to_string(), Resource::new(vec![0xFF, 0xD8])),
+                ("a-png".to_string(), Resource::new(b"\x89PNG".to_vec())),
+            ]),
+        };
+        let (tex, images) = document_to_latex(document, &Default::default());
+        let mut names = images.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a-jpg.jpg", "a-png-2.png", "a-png.png"]);
+        for name in names {
+            assert!(tex.contains(&name), "{tex}");
+        }
+    }
+}
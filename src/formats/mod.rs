@@ -1,52 +1,453 @@
-use crate::{app::text_processor::process_text, types::Node};
-use fltk::dialog;
-use std::fs;
+use crate::{
+    text_processor::{process_node_path, process_text, reference_macro},
+    types::{Document, Node},
+    utils::node_to_tree_vec,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
-mod html;
+pub mod docx;
+pub mod epub;
+pub mod html;
+pub mod json;
+pub mod latex;
+pub mod opml;
+pub mod org;
+pub mod pdf;
 pub mod rtd;
+pub mod text;
 
-fn ask_table_of_contents() -> bool {
-    dialog::choice2_default("Create table of contents?", "No", "Yes", "") == Some(1)
+/// Formats nodes can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Rtd,
+    Text,
+    Html,
+    Markdown,
+    Json,
+    Opml,
+    Epub,
+    Latex,
+    Docx,
+    Pdf,
+    Org,
 }
 
-pub fn node_to_text(node: Node) -> String {
-    format!(
-        "- {}\n{}\n\n{}",
-        node.name,
-        html::remove_html_tags(node.content),
-        node.children
-            .iter()
-            .map(|n| node_to_text(n.clone()))
-            .collect::<String>()
-    )
+impl ExportFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Rtd => "Rustree document",
+            ExportFormat::Text => "Text",
+            ExportFormat::Html => "HTML",
+            ExportFormat::Markdown => "Markdown",
+            ExportFormat::Json => "JSON",
+            ExportFormat::Opml => "OPML",
+            ExportFormat::Epub => "EPUB",
+            ExportFormat::Latex => "LaTeX",
+            ExportFormat::Docx => "DOCX",
+            ExportFormat::Pdf => "PDF",
+            ExportFormat::Org => "Org",
+        }
+    }
+
+    /// File extension with the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Rtd => ".rtd",
+            ExportFormat::Text => ".txt",
+            ExportFormat::Html => ".html",
+            ExportFormat::Markdown => ".md",
+            ExportFormat::Json => ".json",
+            ExportFormat::Opml => ".opml",
+            ExportFormat::Epub => ".epub",
+            ExportFormat::Latex => ".tex",
+            ExportFormat::Docx => ".docx",
+            ExportFormat::Pdf => ".pdf",
+            ExportFormat::Org => ".org",
+        }
+    }
+
+    /// Finds the format by a file extension without the dot (e.g. `md` or `html`)
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Some(match extension.to_lowercase().as_str() {
+            "rtd" => ExportFormat::Rtd,
+            "txt" | "text" => ExportFormat::Text,
+            "html" | "htm" => ExportFormat::Html,
+            "md" | "markdown" => ExportFormat::Markdown,
+            "json" => ExportFormat::Json,
+            "opml" => ExportFormat::Opml,
+            "epub" => ExportFormat::Epub,
+            "tex" | "latex" => ExportFormat::Latex,
+            "docx" => ExportFormat::Docx,
+            "pdf" => ExportFormat::Pdf,
+            "org" => ExportFormat::Org,
+            _ => return None,
+        })
+    }
+
+    /// Whether the format can have a table of contents
+    pub fn has_table_of_contents(self) -> bool {
+        matches!(
+            self,
+            ExportFormat::Text
+                | ExportFormat::Html
+                | ExportFormat::Markdown
+                | ExportFormat::Epub
+                | ExportFormat::Latex
+                | ExportFormat::Docx
+                | ExportFormat::Pdf
+                | ExportFormat::Org
+        )
+    }
+
+    /// Whether node headings can be numbered
+    pub fn has_numbering(self) -> bool {
+        !matches!(
+            self,
+            ExportFormat::Rtd | ExportFormat::Json | ExportFormat::Opml
+        )
+    }
+
+    /// Supported ways of exporting resources, the first one is the default
+    pub fn resource_handlings(self) -> &'static [ResourceHandling] {
+        match self {
+            ExportFormat::Text | ExportFormat::Opml => &[],
+            ExportFormat::Html | ExportFormat::Markdown => &[
+                ResourceHandling::Embed,
+                ResourceHandling::SeparateFiles,
+                ResourceHandling::Omit,
+            ],
+            ExportFormat::Latex | ExportFormat::Org => {
+                &[ResourceHandling::SeparateFiles, ResourceHandling::Omit]
+            }
+            ExportFormat::Rtd
+            | ExportFormat::Json
+            | ExportFormat::Epub
+            | ExportFormat::Docx
+            | ExportFormat::Pdf => &[ResourceHandling::Embed, ResourceHandling::Omit],
+        }
+    }
 }
 
-pub fn node_to_html(node: Node, is_document: bool) -> String {
-    if is_document {
-        format!(
-            "{}{}",
-            if ask_table_of_contents() {
-                generate_table_of_contents(node.clone(), is_document) + "<hr>"
-            } else {
-                String::new()
-            },
-            node_to_html(node, false)
+/// How resources are exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResourceHandling {
+    /// Resources are included into the exported file
+    Embed,
+    /// Resources are written as separate files next to the exported file
+    SeparateFiles,
+    /// Resources and images are left out
+    Omit,
+}
+
+impl ResourceHandling {
+    pub fn name(self) -> &'static str {
+        match self {
+            ResourceHandling::Embed => "Embed",
+            ResourceHandling::SeparateFiles => "Separate files",
+            ResourceHandling::Omit => "Omit",
+        }
+    }
+}
+
+/// Options of exporting nodes, formats ignore the options they don't support
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub table_of_contents: bool,
+    /// Maximum depth of the exported children, `None` for no limit
+    pub max_depth: Option<usize>,
+    /// Paths of nodes left out with their children (as in `path:` references)
+    pub excluded_paths: Vec<String>,
+    /// Nodes with any of these tags are left out with their children
+    pub excluded_tags: Vec<String>,
+    /// Node headings are numbered by their position in the outline (`1.2.3`)
+    pub numbering: bool,
+    pub resources: ResourceHandling,
+    /// Maximum line length of plain text in characters, `0` disables wrapping
+    pub wrap_width: usize,
+    /// Markdown content is converted from HTML (otherwise it is HTML that works in GFM)
+    pub convert_to_markdown: bool,
+    /// Language of the text as a BCP 47 tag (`en`, `pt-BR`), for formats that declare it
+    pub language: String,
+}
+
+/// Language of exported documents when the options don't set one
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            table_of_contents: false,
+            max_depth: None,
+            excluded_paths: Vec::new(),
+            excluded_tags: Vec::new(),
+            numbering: false,
+            resources: ResourceHandling::Embed,
+            wrap_width: 80,
+            convert_to_markdown: false,
+            language: DEFAULT_LANGUAGE.to_string(),
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Gets the resource handling if the format supports it, otherwise the default one
+    pub fn resource_handling(&self, format: ExportFormat) -> Option<ResourceHandling> {
+        let handlings = format.resource_handlings();
+        if handlings.contains(&self.resources) {
+            Some(self.resources)
+        } else {
+            handlings.first().copied()
+        }
+    }
+}
+
+/// Exported file and additional files (e.g. images) that must be written next to it
+pub struct ExportedFile {
+    pub bytes: Vec<u8>,
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+/// Exports the node with id and its children
+pub fn export_node(
+    document: &Document,
+    node_id: u32,
+    format: ExportFormat,
+    options: &ExportOptions,
+) -> Result<ExportedFile, String> {
+    let document = prepare_document(document, node_id, format, options)?;
+    let mut files = Vec::new();
+    let bytes = match format {
+        ExportFormat::Rtd => {
+            rtd::serealize(rtd::document_to_rtd_document(document)).map_err(|e| e.to_string())?
+        }
+        ExportFormat::Text => text::node_to_text(&document.root_node, &document, options).into(),
+        ExportFormat::Html => {
+            let (html, resources) = node_to_html(&document.root_node, &document, options);
+            files = resources;
+            html.into()
+        }
+        ExportFormat::Markdown => {
+            let (md, resources) = node_to_md(&document.root_node, &document, options);
+            files = resources;
+            md.into()
+        }
+        ExportFormat::Json => json::document_to_json(document).into(),
+        ExportFormat::Opml => opml::node_to_opml(document.root_node).into(),
+        ExportFormat::Epub => epub::document_to_epub(document, options)?,
+        ExportFormat::Latex => {
+            let (tex, images) = latex::document_to_latex(document, options);
+            files = images;
+            tex.into()
+        }
+        ExportFormat::Docx => docx::document_to_docx(document, options)?,
+        ExportFormat::Pdf => pdf::document_to_pdf(document, options)?,
+        ExportFormat::Org => {
+            let (org, images) = org::document_to_org(document, options);
+            files = images;
+            org.into()
+        }
+    };
+    Ok(ExportedFile { bytes, files })
+}
+
+/// Creates a document of the exported node without the excluded nodes.
+/// References are rewritten to paths in the new document
+fn prepare_document(
+    document: &Document,
+    node_id: u32,
+    format: ExportFormat,
+    options: &ExportOptions,
+) -> Result<Document, String> {
+    let node = document
+        .clone()
+        .get_node(node_id)
+        .ok_or("The exported node doesn't exist")?;
+    let mut exported = filter_node(node, 0, options, &node_paths(&document.root_node));
+    // Formats that can't number headings themselves get numbered node names
+    if options.numbering
+        && matches!(
+            format,
+            ExportFormat::Html
+                | ExportFormat::Markdown
+                | ExportFormat::Epub
+                | ExportFormat::Docx
+                | ExportFormat::Pdf
         )
+    {
+        exported.children = number_nodes(exported.children, "");
+    }
+    let omit_resources = options.resource_handling(format) == Some(ResourceHandling::Omit);
+    let paths = node_paths(&exported);
+    let exported = rewrite_node_content(exported, document, &paths, omit_resources);
+    let resources = if omit_resources {
+        BTreeMap::new()
+    } else if node_id == document.root_node.id
+        && matches!(format, ExportFormat::Rtd | ExportFormat::Json)
+    {
+        // Documents keep unused resources
+        document.resources.clone()
     } else {
-        format!(
-            "<h1 id=\"{}\">{}</h1>{}{}",
-            node.id,
-            node.name,
-            node.content,
-            node.children
-                .iter()
-                .map(|n| node_to_html(n.clone(), false))
-                .collect::<String>()
-        )
+        document.node_document(exported.clone()).resources
+    };
+    Ok(Document {
+        root_node: exported,
+        resources,
+    })
+}
+
+/// Paths of the node and its children by their ids
+pub fn node_paths(node: &Node) -> BTreeMap<u32, String> {
+    node_to_tree_vec(node.clone(), Vec::new(), None)
+        .into_iter()
+        .map(|(path, id)| (id, path))
+        .collect()
+}
+
+/// Removes children that are deeper than the limit or excluded
+fn filter_node(
+    node: Node,
+    depth: usize,
+    options: &ExportOptions,
+    paths: &BTreeMap<u32, String>,
+) -> Node {
+    let children = if options.max_depth.is_some_and(|max| depth >= max) {
+        Vec::new()
+    } else {
+        node.children
+            .into_iter()
+            .filter(|child| !is_excluded(child, options, paths))
+            .map(|child| filter_node(child, depth + 1, options, paths))
+            .collect()
+    };
+    Node { children, ..node }
+}
+
+fn is_excluded(node: &Node, options: &ExportOptions, paths: &BTreeMap<u32, String>) -> bool {
+    let excluded_path = paths.get(&node.id).is_some_and(|path| {
+        options.excluded_paths.iter().any(|excluded| {
+            let excluded = excluded.trim();
+            excluded.strip_prefix("path:").unwrap_or(excluded) == path
+        })
+    });
+    let excluded_tag = node.metadata.get(org::TAGS_KEY).is_some_and(|tags| {
+        tags.split(':')
+            .any(|tag| !tag.is_empty() && options.excluded_tags.iter().any(|t| t == tag))
+    });
+    excluded_path || excluded_tag
+}
+
+/// Prefixes names of the nodes and their children with their numbers in the outline
+fn number_nodes(nodes: Vec<Node>, prefix: &str) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let number = format!("{prefix}{}", i + 1);
+            Node {
+                name: format!("{number} {}", node.name),
+                children: number_nodes(node.children, &format!("{number}.")),
+                ..node
+            }
+        })
+        .collect()
+}
+
+/// Rewrites references resolved in the original document to the new paths
+/// (references to nodes that aren't exported are kept) and removes omitted resources
+fn rewrite_node_content(
+    node: Node,
+    document: &Document,
+    paths: &BTreeMap<u32, String>,
+    omit_resources: bool,
+) -> Node {
+    let mut content = node.content.clone();
+    if omit_resources {
+        content = Regex::new(r#"<img\b[^>]*resource!\("[^"]*"\)[^>]*>"#)
+            .unwrap()
+            .replace_all(&content, "")
+            .to_string();
+    }
+    let content = process_text(
+        content,
+        |name| {
+            if omit_resources {
+                String::new()
+            } else {
+                format!("resource!(\"{name}\")")
+            }
+        },
+        |path| {
+            let id = process_node_path(path.to_string(), document.clone(), node.clone());
+            match paths.get(&id) {
+                Some(path) => reference_macro(&format!("path:{path}"), id),
+                None => format!("reference!(\"{path}\")"),
+            }
+        },
+    );
+    Node {
+        content,
+        children: node
+            .children
+            .into_iter()
+            .map(|child| rewrite_node_content(child, document, paths, omit_resources))
+            .collect(),
+        ..node
     }
 }
 
-fn generate_table_of_contents(node: Node, is_document: bool) -> String {
+/// Converts the node and its children to HTML, headings have ids of the nodes.
+/// Returns the HTML and the resource files that must be written next to it
+pub fn node_to_html(
+    node: &Node,
+    document: &Document,
+    options: &ExportOptions,
+) -> (String, Vec<(String, Vec<u8>)>) {
+    let mut files = Vec::new();
+    let links = resource_links(
+        document,
+        options.resource_handling(ExportFormat::Html),
+        &mut files,
+    );
+    let html = format!(
+        "{}{}",
+        if options.table_of_contents {
+            generate_table_of_contents(node, true) + "<hr>"
+        } else {
+            String::new()
+        },
+        nodes_to_html(node, document, &links)
+    );
+    (html, files)
+}
+
+fn nodes_to_html(node: &Node, document: &Document, links: &BTreeMap<String, String>) -> String {
+    format!(
+        "<h1 id=\"{}\">{}</h1>{}{}",
+        node.id,
+        escape_xml(&node.name),
+        process_node_content(
+            node,
+            document,
+            |name| links.get(name).cloned().unwrap_or_default(),
+            |id| format!("#{id}")
+        ),
+        node.children
+            .iter()
+            .map(|n| nodes_to_html(n, document, links))
+            .collect::<String>()
+    )
+}
+
+fn generate_table_of_contents(node: &Node, is_document: bool) -> String {
     if is_document {
         format!(
             "<h1>Table of Contents</h1><ul>{}</ul>",
@@ -56,13 +457,13 @@ fn generate_table_of_contents(node: Node, is_document: bool) -> String {
         format!(
             "<li><a href=\"#{}\">{}</a>{}</li>",
             node.id,
-            node.name,
+            escape_xml(&node.name),
             if !node.children.is_empty() {
                 format!(
                     "<ul>{}</ul>",
                     node.children
                         .iter()
-                        .map(|n| generate_table_of_contents(n.clone(), false))
+                        .map(|n| generate_table_of_contents(n, false))
                         .collect::<String>()
                 )
             } else {
@@ -72,54 +473,293 @@ fn generate_table_of_contents(node: Node, is_document: bool) -> String {
     }
 }
 
-pub fn node_to_md(node: Node, is_document: bool, is_html: bool) -> String {
-    if is_document {
-        format!(
-            "{}{}",
-            if ask_table_of_contents() {
-                generate_table_of_contents(node.clone(), is_document) + "<hr>"
-            } else {
-                String::new()
-            },
-            node_to_md(node, false, dialog::choice2_default(
-                "Convert to markdown? (it doesn't work very well) otherwise the nodes will be unchanged (in HTML format that works in GFM)",
-                "No",
-                "Yes",
-                ""
-            ) == Some(0))
-        )
+/// Converts the node and its children to Markdown, headings are HTML with ids of the nodes.
+/// Returns the Markdown and the resource files that must be written next to it
+pub fn node_to_md(
+    node: &Node,
+    document: &Document,
+    options: &ExportOptions,
+) -> (String, Vec<(String, Vec<u8>)>) {
+    let mut files = Vec::new();
+    let links = resource_links(
+        document,
+        options.resource_handling(ExportFormat::Markdown),
+        &mut files,
+    );
+    let md = format!(
+        "{}{}",
+        if options.table_of_contents {
+            generate_table_of_contents(node, true) + "<hr>"
+        } else {
+            String::new()
+        },
+        nodes_to_md(node, document, &links, options.convert_to_markdown)
+    );
+    (md, files)
+}
+
+fn nodes_to_md(
+    node: &Node,
+    document: &Document,
+    links: &BTreeMap<String, String>,
+    convert_to_markdown: bool,
+) -> String {
+    let content = process_node_content(
+        node,
+        document,
+        |name| links.get(name).cloned().unwrap_or_default(),
+        |id| format!("#{id}"),
+    );
+    format!(
+        "<h2 id=\"{}\">{}</h2>{}{}",
+        node.id,
+        escape_xml(&node.name),
+        if convert_to_markdown {
+            html::to_markdown(content)
+        } else {
+            content
+        },
+        node.children
+            .iter()
+            .map(|n| nodes_to_md(n, document, links, convert_to_markdown))
+            .collect::<String>()
+    )
+}
+
+/// Links to resources in HTML: data URIs of embedded resources
+/// or names of the files written next to the exported file (they are added to `files`)
+fn resource_links(
+    document: &Document,
+    handling: Option<ResourceHandling>,
+    files: &mut Vec<(String, Vec<u8>)>,
+) -> BTreeMap<String, String> {
+    let mut links = BTreeMap::new();
+    for (name, res) in &document.resources {
+        if handling == Some(ResourceHandling::SeparateFiles) {
+            let file = resource_file_name(name);
+            links.insert(name.clone(), file.clone());
+            files.push((file, res.bytes.clone()));
+        } else {
+            let media_type = image_type(&res.bytes)
+                .map_or("application/octet-stream", |(_, media_type)| media_type);
+            links.insert(
+                name.clone(),
+                format!("data:{media_type};base64,{}", STANDARD.encode(&res.bytes)),
+            );
+        }
+    }
+    links
+}
+
+/// Name of the resource file written next to the exported file
+pub fn resource_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c == '/' || c == '\\' { '-' } else { c })
+        .collect()
+}
+
+/// Prefix of links to nodes produced while processing macros in exporters
+pub const NODE_LINK_PREFIX: &str = "#node:";
+
+/// Prefix of links to resources used by exporters that embed resources themselves
+pub const RESOURCE_LINK_PREFIX: &str = "#resource:";
+
+/// Replaces resource!() and reference!() macros in the node content with links.
+/// `node_link` gets the id of the referenced node (`0` if it doesn't exist)
+pub fn process_node_content(
+    node: &Node,
+    document: &Document,
+    resource_link: impl Fn(&str) -> String,
+    node_link: impl Fn(u32) -> String,
+) -> String {
+    process_text(node.content.clone(), resource_link, |path| {
+        node_link(process_node_path(
+            path.to_string(),
+            document.clone(),
+            node.clone(),
+        ))
+    })
+}
+
+/// Detects image type by its bytes, returns its extension and MIME type
+pub fn image_type(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    if bytes.starts_with(b"\x89PNG") {
+        Some(("png", "image/png"))
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        Some(("jpg", "image/jpeg"))
+    } else if bytes.starts_with(b"GIF8") {
+        Some(("gif", "image/gif"))
+    } else if String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]).contains("<svg") {
+        Some(("svg", "image/svg+xml"))
     } else {
-        let content = process_text(node.content, |s| s.to_string(), |s| s.to_string());
-        format!(
-            "<h2 id=\"{}\">{}</h2>{}{}",
-            node.id,
-            node.name,
-            if !is_html {
-                html::to_markdown(content)
-            } else {
-                content
-            },
-            node.children
-                .iter()
-                .map(|n| node_to_md(n.clone(), false, is_html))
-                .collect::<String>()
-        )
+        None
+    }
+}
+
+/// Gets image width and height in pixels (PNG, JPEG and GIF)
+pub fn image_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be = |i: usize| u32::from(bytes[i]) << 8 | u32::from(bytes[i + 1]);
+    match image_type(bytes)? {
+        ("png", _) if bytes.len() >= 24 => Some((
+            u32::from_be_bytes(bytes[16..20].try_into().ok()?),
+            u32::from_be_bytes(bytes[20..24].try_into().ok()?),
+        )),
+        ("gif", _) if bytes.len() >= 10 => Some((
+            u32::from(u16::from_le_bytes([bytes[6], bytes[7]])),
+            u32::from(u16::from_le_bytes([bytes[8], bytes[9]])),
+        )),
+        ("jpg", _) => {
+            let i = jpeg_start_of_frame(bytes)?;
+            Some((be(i + 7), be(i + 5)))
+        }
+        _ => None,
     }
 }
 
-pub fn node_to_json(node: Node) -> String {
-    serde_json::to_string_pretty(&node).unwrap()
+/// Gets the number of color components of a JPEG image
+pub fn jpeg_components(bytes: &[u8]) -> Option<u8> {
+    jpeg_start_of_frame(bytes).map(|i| bytes[i + 9])
 }
 
-pub fn save_format_dialog(name: String, bytes: Vec<u8>) {
-    let mut nfc = dialog::NativeFileChooser::new(dialog::FileDialogType::BrowseSaveFile);
-    nfc.set_filter("");
-    nfc.set_preset_file(&name);
-    nfc.show();
-    let filename = nfc.filename();
-    if !filename.to_string_lossy().is_empty() {
-        if let Err(e) = fs::write(filename, bytes) {
-            dialog::alert_default(&e.to_string());
+/// Finds the offset of the JPEG start of frame marker
+fn jpeg_start_of_frame(bytes: &[u8]) -> Option<usize> {
+    let mut i = 2;
+    while i + 9 < bytes.len() && bytes[i] == 0xFF {
+        let marker = bytes[i + 1];
+        // Start of frame markers (except DHT, JPG and DAC)
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            return Some(i);
+        }
+        i += 2 + (usize::from(bytes[i + 2]) << 8 | usize::from(bytes[i + 3]));
+    }
+    None
+}
+
+/// Current UTC date and time in ISO 8601 format (e.g. `2024-01-31T12:00:00Z`)
+pub fn utc_date_time() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    // Converting days since 1970-01-01 to a civil date
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Creates a ZIP archive from files (path and bytes).
+/// The first file is stored uncompressed (it is required for the `mimetype` file of EPUB)
+pub fn write_zip(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (i, (path, bytes)) in files.into_iter().enumerate() {
+        let options = FileOptions::default().compression_method(if i == 0 {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        });
+        zip.start_file(path, options).map_err(|e| e.to_string())?;
+        zip.write_all(&bytes).map_err(|e| e.to_string())?;
+    }
+    Ok(zip.finish().map_err(|e| e.to_string())?.into_inner())
+}
+
+/// Escapes text for XML content and attributes
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+        .replace('\n', "&#10;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    fn document() -> Document {
+        let mut draft = node(5, "D", "<p>d</p>", Vec::new());
+        draft
+            .metadata
+            .insert(org::TAGS_KEY.to_string(), ":draft:".to_string());
+        Document {
+            root_node: node(
+                1,
+                "Root",
+                "",
+                vec![
+                    node(
+                        2,
+                        "A",
+                        r#"reference!("path:Root/B/C") reference!("id:5")"#,
+                        Vec::new(),
+                    ),
+                    node(
+                        3,
+                        "B",
+                        "",
+                        vec![node(4, "C", "reference!(\"parent:\")", Vec::new())],
+                    ),
+                    draft,
+                ],
+            ),
+            resources: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn exported_nodes_are_the_root_of_the_references() {
+        let exported =
+            prepare_document(&document(), 3, ExportFormat::Json, &Default::default()).unwrap();
+        assert_eq!(exported.root_node.name, "B");
+        assert_eq!(
+            exported.root_node.children[0].content,
+            r#"reference!("path:B")"#
+        );
+    }
+
+    #[test]
+    fn references_follow_numbered_names_and_skip_excluded_nodes() {
+        for options in [
+            ExportOptions {
+                numbering: true,
+                excluded_paths: vec!["Root/D".to_string()],
+                ..Default::default()
+            },
+            ExportOptions {
+                numbering: true,
+                excluded_tags: vec!["draft".to_string()],
+                ..Default::default()
+            },
+        ] {
+            let exported = prepare_document(&document(), 1, ExportFormat::Html, &options).unwrap();
+            let names = exported.root_node.children.iter().map(|n| n.name.as_str());
+            assert_eq!(names.collect::<Vec<_>>(), ["1 A", "2 B"]);
+            // References to nodes that aren't exported are kept
+            assert_eq!(
+                exported.root_node.children[0].content,
+                r#"reference!("path:Root/2 B/2.1 C") reference!("id:5")"#
+            );
         }
     }
 }
@@ -0,0 +1,141 @@
+use super::escape_xml;
+use crate::{types::Node, utils::make_children_names_unique};
+use roxmltree::{Document, ParsingOptions};
+
+pub fn node_to_opml(node: Node) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n<head>\n<title>{}</title>\n</head>\n<body>\n{}</body>\n</opml>\n",
+        escape_xml(&node.name),
+        node_to_outline(&node, 0)
+    )
+}
+
+fn node_to_outline(node: &Node, depth: usize) -> String {
+    let indent = "  ".repeat(depth + 1);
+    let note = if node.content.is_empty() {
+        String::new()
+    } else {
+        format!(" _note=\"{}\"", escape_xml(&node.content))
+    };
+    if node.children.is_empty() {
+        format!(
+            "{indent}<outline text=\"{}\"{note}/>\n",
+            escape_xml(&node.name)
+        )
+    } else {
+        format!(
+            "{indent}<outline text=\"{}\"{note}>\n{}{indent}</outline>\n",
+            escape_xml(&node.name),
+            node.children
+                .iter()
+                .map(|n| node_to_outline(n, depth + 1))
+                .collect::<String>()
+        )
+    }
+}
+
+/// Builds a node tree from OPML. If the body has a single outline it becomes
+/// the root node, otherwise the outlines are put into a node named after the title
+pub fn opml_to_node(text: String) -> Result<Node, String> {
+    // Some outliners write a DOCTYPE, roxmltree rejects it by default
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let xml = Document::parse_with_options(&text, options).map_err(|e| e.to_string())?;
+    let opml = xml.root_element();
+    if !opml.has_tag_name("opml") {
+        return Err("Not an OPML file".to_string());
+    }
+    let title = opml
+        .children()
+        .find(|n| n.has_tag_name("head"))
+        .and_then(|head| head.children().find(|n| n.has_tag_name("title")))
+        .and_then(|title| title.text())
+        .unwrap_or("Imported outline")
+        .to_string();
+    let body = opml
+        .children()
+        .find(|n| n.has_tag_name("body"))
+        .ok_or("OPML file has no body")?;
+    let mut outlines = outline_children(body);
+    if outlines.len() == 1 {
+        Ok(outlines.remove(0))
+    } else {
+        Ok(Node::new(title, String::new(), outlines))
+    }
+}
+
+fn outline_children(element: roxmltree::Node) -> Vec<Node> {
+    make_children_names_unique(
+        element
+            .children()
+            .filter(|n| n.has_tag_name("outline"))
+            .map(|outline| {
+                let name = outline
+                    .attribute("text")
+                    .or(outline.attribute("title"))
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+                Node::new(
+                    if name.is_empty() {
+                        "Untitled".to_string()
+                    } else {
+                        name
+                    },
+                    outline
+                        .attribute("_note")
+                        .or(outline.attribute("note"))
+                        .unwrap_or_default()
+                        .to_string(),
+                    outline_children(outline),
+                )
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_notes_with_markup_round_trip() {
+        let root = Node::new(
+            "Tom & \"Jerry\" <3".to_string(),
+            "<p>It's <b>bold</b>\nand &amp;</p>".to_string(),
+            vec![
+                Node::new("Child".to_string(), String::new(), Vec::new()),
+                Node::new("Other".to_string(), String::new(), Vec::new()),
+            ],
+        );
+        let opml = node_to_opml(root.clone());
+        assert!(opml.contains("text=\"Tom &amp; &quot;Jerry&quot; &lt;3\""));
+        let imported = opml_to_node(opml).unwrap();
+        assert_eq!(imported.name, root.name);
+        assert_eq!(imported.content, root.content);
+        let names = imported.children.iter().map(|n| n.name.as_str());
+        assert_eq!(names.collect::<Vec<_>>(), ["Child", "Other"]);
+    }
+
+    #[test]
+    fn outlines_without_a_single_root_are_put_in_a_titled_node() {
+        let opml = "<opml version=\"2.0\"><head><title>List</title></head><body>\
+                    <outline text=\"A\"/><outline text=\"A\"/><outline text=\" \"/></body></opml>";
+        let root = opml_to_node(opml.to_string()).unwrap();
+        assert_eq!(root.name, "List");
+        assert_eq!(root.children.len(), 3);
+        assert_ne!(root.children[0].name, root.children[1].name);
+        assert_eq!(root.children[2].name, "Untitled");
+    }
+
+    #[test]
+    fn opml_with_a_doctype_is_imported() {
+        let opml = "<?xml version=\"1.0\"?>\n<!DOCTYPE opml [<!ENTITY name \"Entity\">]>\n\
+                    <opml version=\"1.0\"><head><title>List</title></head><body>\
+                    <outline text=\"&name;\"/></body></opml>";
+        let root = opml_to_node(opml.to_string()).unwrap();
+        assert_eq!(root.name, "Entity");
+    }
+}
@@ -0,0 +1,714 @@
+use super::{
+    html::{self, HtmlElement, HtmlNode},
+    image_type, process_node_content, resource_file_name, ExportOptions,
+};
+use crate::{
+    text_processor::reference_macro,
+    types::{Document, Node},
+    utils::{make_children_names_unique, node_to_tree_vec},
+};
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+
+/// Metadata key of the headline tags (`:tag1:tag2:`), as in the Org property API
+pub const TAGS_KEY: &str = "TAGS";
+/// Metadata keys written in the planning line instead of the properties drawer
+const PLANNING_KEYS: [&str; 3] = ["DEADLINE", "SCHEDULED", "CLOSED"];
+/// Prefix of temporary references to headlines resolved after the tree is built
+const HEADLINE_REFERENCE_PREFIX: &str = "headline:";
+
+/// Creates an Org document from the document. The root node is the title,
+/// its children are top level headlines.
+/// Returns the `.org` file and the image files that must be written next to it
+pub fn document_to_org(
+    document: Document,
+    options: &ExportOptions,
+) -> (String, Vec<(String, Vec<u8>)>) {
+    let root = &document.root_node;
+    let names = node_to_tree_vec(root.clone(), Vec::new(), None)
+        .into_iter()
+        .filter_map(|(_, id)| {
+            document
+                .clone()
+                .get_node(id)
+                .map(|node| (id, node.name.clone()))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let images = document
+        .resources
+        .iter()
+        .filter(|(_, res)| image_type(&res.bytes).is_some())
+        .map(|(name, res)| (name.clone(), (resource_file_name(name), res.bytes.clone())))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut metadata = root.metadata.clone();
+    let file_tags = metadata
+        .remove(TAGS_KEY)
+        .map(|tags| format!("#+FILETAGS: {tags}\n"))
+        .unwrap_or_default();
+    let org = format!(
+        "{}#+TITLE: {}\n#+OPTIONS: toc:{} num:{}\n{file_tags}\n{}{}",
+        properties_drawer(&metadata),
+        root.name,
+        if options.table_of_contents {
+            "t"
+        } else {
+            "nil"
+        },
+        if options.numbering { "t" } else { "nil" },
+        node_content_to_org(root, &document, &names, &images),
+        root.children
+            .iter()
+            .map(|n| node_to_org(n, 1, &document, &names, &images))
+            .collect::<String>()
+    );
+    (org, images.into_values().collect())
+}
+
+fn node_to_org(
+    node: &Node,
+    level: usize,
+    document: &Document,
+    names: &BTreeMap<u32, String>,
+    images: &BTreeMap<String, (String, Vec<u8>)>,
+) -> String {
+    let mut metadata = node.metadata.clone();
+    let tags = metadata
+        .remove(TAGS_KEY)
+        .map(|tags| format!(" {tags}"))
+        .unwrap_or_default();
+    let planning = PLANNING_KEYS
+        .iter()
+        .filter_map(|key| metadata.remove(*key).map(|value| format!("{key}: {value}")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "{} {}{tags}\n{}{}{}{}",
+        "*".repeat(level),
+        node.name,
+        if planning.is_empty() {
+            String::new()
+        } else {
+            planning + "\n"
+        },
+        properties_drawer(&metadata),
+        node_content_to_org(node, document, names, images),
+        node.children
+            .iter()
+            .map(|n| node_to_org(n, level + 1, document, names, images))
+            .collect::<String>()
+    )
+}
+
+fn properties_drawer(metadata: &BTreeMap<String, String>) -> String {
+    if metadata.is_empty() {
+        String::new()
+    } else {
+        format!(
+            ":PROPERTIES:\n{}:END:\n",
+            metadata
+                .iter()
+                .map(|(key, value)| format!(":{key}: {value}\n"))
+                .collect::<String>()
+        )
+    }
+}
+
+fn node_content_to_org(
+    node: &Node,
+    document: &Document,
+    names: &BTreeMap<u32, String>,
+    images: &BTreeMap<String, (String, Vec<u8>)>,
+) -> String {
+    let content = process_node_content(
+        node,
+        document,
+        |name| {
+            images
+                .get(name)
+                .map(|(file, _)| file.clone())
+                .unwrap_or_default()
+        },
+        |id| {
+            names
+                .get(&id)
+                .map(|name| format!("*{name}"))
+                .unwrap_or_default()
+        },
+    );
+    let org = html_to_org(&html::parse_html(&content)).replace("\\\\\n ", "\\\\\n");
+    // Removing extra blank lines between blocks
+    let org = Regex::new(r"\n[ \t]*\n(?:[ \t]*\n)+")
+        .unwrap()
+        .replace_all(org.trim(), "\n\n")
+        .to_string();
+    if org.is_empty() {
+        org
+    } else {
+        org + "\n\n"
+    }
+}
+
+/// Converts HTML to Org markup
+fn html_to_org(nodes: &[HtmlNode]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            HtmlNode::Text(text) => {
+                // Whitespace is collapsed as in HTML
+                let mut collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if text.starts_with(char::is_whitespace) {
+                    collapsed.insert(0, ' ');
+                }
+                if text.ends_with(char::is_whitespace) && collapsed.trim() != "" {
+                    collapsed.push(' ');
+                }
+                // Text can't start a headline, a comment or a keyword
+                if Regex::new(r"^ ?((\*+|#)( |$)|#\+)")
+                    .unwrap()
+                    .is_match(&collapsed)
+                {
+                    format!("\u{200b}{collapsed}")
+                } else {
+                    collapsed
+                }
+            }
+            HtmlNode::Element(e) => element_to_org(e),
+        })
+        .collect()
+}
+
+fn element_to_org(e: &HtmlElement) -> String {
+    let content = || html_to_org(&e.children);
+    match e.tag.as_str() {
+        "b" | "strong" => emphasis('*', &content()),
+        "i" | "em" | "cite" | "var" => emphasis('/', &content()),
+        "u" | "ins" => emphasis('_', &content()),
+        "s" | "del" | "strike" => emphasis('+', &content()),
+        "code" | "kbd" | "tt" | "samp" => emphasis('~', &html::nodes_text(&e.children)),
+        "br" => "\\\\\n".to_string(),
+        "hr" => "\n\n-----\n\n".to_string(),
+        "p" | "div" | "center" | "dl" | "dt" | "dd" => format!("\n\n{}\n\n", content().trim()),
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            format!("\n\n{}\n\n", emphasis('*', content().trim()))
+        }
+        "blockquote" => format!("\n\n#+BEGIN_QUOTE\n{}\n#+END_QUOTE\n\n", content().trim()),
+        "pre" => format!(
+            "\n\n#+BEGIN_EXAMPLE\n{}\n#+END_EXAMPLE\n\n",
+            html::nodes_text(&e.children)
+                .trim_matches('\n')
+                .lines()
+                .map(|line| {
+                    // Lines that would end the block or start a headline are escaped with a comma
+                    if line.starts_with('*') || line.starts_with("#+") || line.starts_with(',') {
+                        format!(",{line}")
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+        "ul" | "ol" => {
+            let items = e
+                .children
+                .iter()
+                .filter_map(|n| match n {
+                    HtmlNode::Element(li) if li.tag == "li" => Some(li),
+                    _ => None,
+                })
+                .enumerate()
+                .map(|(i, li)| {
+                    let marker = if e.tag == "ul" {
+                        "- ".to_string()
+                    } else {
+                        format!("{}. ", i + 1)
+                    };
+                    let text = Regex::new(r"\n\s*\n")
+                        .unwrap()
+                        .replace_all(html_to_org(&li.children).trim(), "\n")
+                        .replace('\n', &format!("\n{}", " ".repeat(marker.len())));
+                    format!("{marker}{text}\n")
+                })
+                .collect::<String>();
+            format!("\n\n{items}\n")
+        }
+        "table" => table_to_org(e),
+        "img" => match e.attribute("src") {
+            Some(src) if !src.is_empty() => format!("[[file:{src}]]"),
+            _ => e.attribute("alt").unwrap_or_default().to_string(),
+        },
+        "a" => match e.attribute("href") {
+            Some(href) if !href.is_empty() => {
+                let description = content();
+                if description.trim().is_empty() {
+                    format!("[[{href}]]")
+                } else {
+                    format!("[[{href}][{}]]", description.trim())
+                }
+            }
+            _ => content(),
+        },
+        _ => content(),
+    }
+}
+
+fn table_to_org(table: &HtmlElement) -> String {
+    let rows = html::table_rows(&table.children);
+    if rows.is_empty() {
+        return String::new();
+    }
+    let has_header = rows[0].iter().all(|cell| cell.tag == "th");
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let mut org = String::from("\n\n");
+    for (i, row) in rows.iter().enumerate() {
+        let mut cells = row
+            .iter()
+            .map(|cell| {
+                html_to_org(&cell.children)
+                    .trim()
+                    .replace('\n', " ")
+                    .replace('|', "\\vert{}")
+            })
+            .collect::<Vec<_>>();
+        cells.resize(columns, String::new());
+        org += &format!("| {} |\n", cells.join(" | "));
+        if i == 0 && has_header && rows.len() > 1 {
+            org += &format!("|{}|\n", vec!["---"; columns].join("+"));
+        }
+    }
+    org + "\n"
+}
+
+/// Surrounds text with an emphasis marker keeping surrounding whitespace outside
+fn emphasis(marker: char, text: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let start = text.len() - text.trim_start().len();
+    format!(
+        "{}{marker}{trimmed}{marker}{}",
+        &text[..start],
+        &text[start + trimmed.len()..]
+    )
+}
+
+/// Builds a node tree from an Org document. Headlines become nodes, the text before
+/// the first headline is the content of the root node named after the title.
+/// If there is no title and no such text, a single top level headline becomes the root.
+/// Properties drawers, tags and planning lines are kept as node metadata
+pub fn org_to_node(text: String) -> Result<Node, String> {
+    if text.trim().is_empty() {
+        return Err("Org document is empty".to_string());
+    }
+    let headline_regex = Regex::new(r"^(\*+)\s+(.*?)(?:\s+(:[\w@#%:]+:))?\s*$").unwrap();
+    // Level and node of the headline being read and its ancestors, the first one is the root
+    let mut stack = vec![(0, Node::new(String::new(), String::new(), Vec::new()))];
+    let mut body = Vec::new();
+    let mut title = None;
+    for line in text.lines() {
+        if let Some(captures) = headline_regex.captures(line) {
+            let (_, node) = stack.last_mut().unwrap();
+            read_body(node, &body);
+            body.clear();
+            let level = captures[1].len();
+            while stack.len() > 1 && stack.last().unwrap().0 >= level {
+                let (_, node) = stack.pop().unwrap();
+                stack.last_mut().unwrap().1.children.push(node);
+            }
+            let mut node = Node::new(captures[2].trim().to_string(), String::new(), Vec::new());
+            if let Some(tags) = captures.get(3) {
+                node.metadata
+                    .insert(TAGS_KEY.to_string(), tags.as_str().to_string());
+            }
+            stack.push((level, node));
+        } else if stack.len() == 1 && keyword(line, "TITLE").is_some() {
+            title = keyword(line, "TITLE").map(str::to_string);
+        } else if stack.len() == 1 && keyword(line, "FILETAGS").is_some() {
+            stack[0].1.metadata.insert(
+                TAGS_KEY.to_string(),
+                keyword(line, "FILETAGS").unwrap_or_default().to_string(),
+            );
+        } else {
+            body.push(line);
+        }
+    }
+    read_body(&mut stack.last_mut().unwrap().1, &body);
+    while stack.len() > 1 {
+        let (_, node) = stack.pop().unwrap();
+        stack.last_mut().unwrap().1.children.push(node);
+    }
+    let (_, mut root) = stack.pop().unwrap();
+
+    if title.is_none()
+        && root.content.is_empty()
+        && root.metadata.is_empty()
+        && root.children.len() == 1
+    {
+        root = root.children.remove(0);
+    } else {
+        root.name = title
+            .filter(|t| !t.is_empty())
+            .unwrap_or("Imported Org document".to_string());
+    }
+    let root = name_nodes(root);
+    let paths = node_to_tree_vec(root.clone(), Vec::new(), None);
+    Ok(resolve_headline_references(root, &paths))
+}
+
+/// Gets the value of a `#+KEYWORD: value` line
+fn keyword<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let (key, value) = line.trim().strip_prefix("#+")?.split_once(':')?;
+    key.eq_ignore_ascii_case(name).then_some(value.trim())
+}
+
+/// Reads the planning line and properties drawer at the start of the body
+/// into the node metadata and the rest into its content
+fn read_body(node: &mut Node, body: &[&str]) {
+    let planning_regex = Regex::new(r"(DEADLINE|SCHEDULED|CLOSED):\s*([<\[][^>\]]*[>\]])").unwrap();
+    let property_regex = Regex::new(r"^\s*:([^:\s]+):\s*(.*?)\s*$").unwrap();
+    let mut lines = body;
+    if let Some(first) = lines.first() {
+        let planning = planning_regex.captures_iter(first).collect::<Vec<_>>();
+        if !planning.is_empty() && planning_regex.replace_all(first, "").trim().is_empty() {
+            for captures in planning {
+                node.metadata
+                    .insert(captures[1].to_string(), captures[2].to_string());
+            }
+            lines = &lines[1..];
+        }
+    }
+    if lines
+        .first()
+        .is_some_and(|l| l.trim().eq_ignore_ascii_case(":PROPERTIES:"))
+    {
+        if let Some(end) = lines
+            .iter()
+            .position(|l| l.trim().eq_ignore_ascii_case(":END:"))
+        {
+            for line in &lines[1..end] {
+                if let Some(captures) = property_regex.captures(line) {
+                    node.metadata.insert(
+                        captures[1].trim_end_matches('+').to_string(),
+                        captures[2].to_string(),
+                    );
+                }
+            }
+            lines = &lines[end + 1..];
+        }
+    }
+    node.content = org_to_html(lines);
+}
+
+/// Gives names to unnamed nodes and makes sibling names unique
+fn name_nodes(node: Node) -> Node {
+    Node {
+        name: if node.name.trim().is_empty() {
+            "Untitled".to_string()
+        } else {
+            node.name
+        },
+        children: make_children_names_unique(node.children.into_iter().map(name_nodes).collect()),
+        ..node
+    }
+}
+
+/// Replaces references to headlines with references to the paths of the first nodes with their names
+fn resolve_headline_references(node: Node, paths: &[(String, u32)]) -> Node {
+    let reference_regex = Regex::new(&format!(
+        r#"reference!\("{HEADLINE_REFERENCE_PREFIX}([^"]*)"\)"#
+    ))
+    .unwrap();
+    Node {
+        content: reference_regex
+            .replace_all(&node.content, |captures: &Captures| {
+                let name = captures[1].replace('/', "\\/");
+                match paths
+                    .iter()
+                    .find(|(path, _)| path == &name || path.ends_with(&format!("/{name}")))
+                {
+                    Some((path, id)) => reference_macro(&format!("path:{path}"), *id),
+                    None => format!("reference!(\"sibling:{}\")", &captures[1]),
+                }
+            })
+            .to_string(),
+        children: node
+            .children
+            .into_iter()
+            .map(|n| resolve_headline_references(n, paths))
+            .collect(),
+        ..node
+    }
+}
+
+/// A list item with its indentation
+struct ListItem {
+    indent: usize,
+    ordered: bool,
+    text: String,
+}
+
+/// Converts Org markup to HTML
+fn org_to_html(lines: &[&str]) -> String {
+    let list_regex = Regex::new(r"^(\s*)([-+]|\d+[.)]|\s\*)\s+(.*)$").unwrap();
+    let mut html = String::new();
+    let mut paragraph = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+        let is_rule = trimmed.len() >= 5 && trimmed.chars().all(|c| c == '-');
+        let block = trimmed
+            .get(..8)
+            .filter(|start| start.eq_ignore_ascii_case("#+BEGIN_"))
+            .map(|_| trimmed[8..].split_whitespace().next().unwrap_or_default());
+        if trimmed.is_empty()
+            || block.is_some()
+            || trimmed.starts_with('|')
+            || list_regex.is_match(line)
+            || is_rule
+            || trimmed.starts_with("#+")
+            || trimmed == "#"
+            || trimmed.starts_with("# ")
+        {
+            if !paragraph.is_empty() {
+                html += &format!("<p>{}</p>\n", inline_to_html(&paragraph.join("\n")));
+                paragraph.clear();
+            }
+        } else {
+            paragraph.push(trimmed);
+            i += 1;
+            continue;
+        }
+
+        if let Some(block) = block {
+            let end_line = format!("#+END_{block}");
+            let end = lines[i + 1..]
+                .iter()
+                .position(|l| l.trim().eq_ignore_ascii_case(&end_line))
+                .map_or(lines.len(), |p| p + i + 1);
+            let block_lines = &lines[i + 1..end];
+            if block.eq_ignore_ascii_case("QUOTE") {
+                html += &format!("<blockquote>{}</blockquote>\n", org_to_html(block_lines));
+            } else {
+                html += &format!(
+                    "<pre>{}</pre>\n",
+                    escape_html(
+                        &block_lines
+                            .iter()
+                            .map(|l| l.strip_prefix(',').unwrap_or(l))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    )
+                );
+            }
+            i = end + 1;
+        } else if trimmed.starts_with('|') {
+            let end = lines[i..]
+                .iter()
+                .position(|l| !l.trim().starts_with('|'))
+                .map_or(lines.len(), |p| p + i);
+            html += &table_to_html(&lines[i..end]);
+            i = end;
+        } else if list_regex.is_match(line) {
+            let mut items: Vec<ListItem> = Vec::new();
+            while i < lines.len() {
+                if let Some(captures) = list_regex.captures(lines[i]) {
+                    items.push(ListItem {
+                        indent: captures[1].len(),
+                        ordered: captures[2].starts_with(|c: char| c.is_ascii_digit()),
+                        text: captures[3].to_string(),
+                    });
+                } else if !lines[i].trim().is_empty()
+                    && lines[i].len() - lines[i].trim_start().len()
+                        > items.last().map_or(0, |item| item.indent)
+                {
+                    // Continuation of the last item
+                    let item = items.last_mut().unwrap();
+                    item.text.push('\n');
+                    item.text.push_str(lines[i].trim());
+                } else {
+                    break;
+                }
+                i += 1;
+            }
+            html += &list_to_html(&items);
+        } else if is_rule {
+            html += "<hr>\n";
+            i += 1;
+        } else {
+            // Blank lines, keywords and comments
+            i += 1;
+        }
+    }
+    if !paragraph.is_empty() {
+        html += &format!("<p>{}</p>\n", inline_to_html(&paragraph.join("\n")));
+    }
+    html.trim_end().to_string()
+}
+
+fn list_to_html(items: &[ListItem]) -> String {
+    let Some(first) = items.first() else {
+        return String::new();
+    };
+    let tag = if first.ordered { "ol" } else { "ul" };
+    let mut html = format!("<{tag}>");
+    let mut i = 0;
+    while i < items.len() {
+        let end = items[i + 1..]
+            .iter()
+            .position(|item| item.indent <= first.indent)
+            .map_or(items.len(), |p| p + i + 1);
+        html += &format!(
+            "<li>{}{}</li>",
+            inline_to_html(&items[i].text),
+            list_to_html(&items[i + 1..end])
+        );
+        i = end;
+    }
+    html + &format!("</{tag}>\n")
+}
+
+fn table_to_html(lines: &[&str]) -> String {
+    let is_separator = |line: &&str| line.trim().starts_with("|-");
+    // Rows before the first separator are the header
+    let header_rows = if lines.iter().skip(1).any(is_separator) {
+        lines.iter().position(is_separator).unwrap_or(0)
+    } else {
+        0
+    };
+    let rows = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !is_separator(line))
+        .map(|(i, line)| {
+            let cell_tag = if i < header_rows { "th" } else { "td" };
+            let cells = line.trim().trim_start_matches('|');
+            let cells = cells.strip_suffix('|').unwrap_or(cells);
+            format!(
+                "<tr>{}</tr>",
+                cells
+                    .split('|')
+                    .map(|cell| format!("<{cell_tag}>{}</{cell_tag}>", inline_to_html(cell.trim())))
+                    .collect::<String>()
+            )
+        })
+        .collect::<String>();
+    format!("<table>{rows}</table>\n")
+}
+
+/// Converts Org inline markup (emphasis, links and line breaks) to HTML
+fn inline_to_html(text: &str) -> String {
+    // Lines that look like Org syntax are exported after a zero-width space
+    let text = Regex::new("(?m)^\u{200b}").unwrap().replace_all(text, "");
+    let mut html = escape_html(&text);
+    for (marker, tag) in [
+        ('=', "code"),
+        ('~', "code"),
+        ('*', "b"),
+        ('/', "i"),
+        ('_', "u"),
+        ('+', "del"),
+    ] {
+        let marker = regex::escape(&marker.to_string());
+        let emphasis_regex = Regex::new(&format!(
+            r#"(^|[\s(\{{'"-]){marker}([^\s{marker}](?:[^{marker}]*?[^\s{marker}])?){marker}($|[\s.,:;!?'")\}}\]-])"#
+        ))
+        .unwrap();
+        // Twice because adjacent matches share the surrounding characters
+        for _ in 0..2 {
+            html = emphasis_regex
+                .replace_all(&html, format!("$1<{tag}>$2</{tag}>$3"))
+                .to_string();
+        }
+    }
+    html = Regex::new(r"\[\[([^\]]+)\](?:\[([^\]]+)\])?\]")
+        .unwrap()
+        .replace_all(&html, |captures: &Captures| {
+            let target = &captures[1];
+            let description = captures.get(2).map(|d| d.as_str());
+            if let Some(headline) = target.strip_prefix('*') {
+                format!(
+                    "<a href=\"reference!(\"{HEADLINE_REFERENCE_PREFIX}{headline}\")\">{}</a>",
+                    description.unwrap_or(headline)
+                )
+            } else {
+                let path = target.strip_prefix("file:").unwrap_or(target);
+                let is_image = [".png", ".jpg", ".jpeg", ".gif", ".svg"]
+                    .iter()
+                    .any(|ext| path.to_lowercase().ends_with(ext));
+                match description {
+                    None if is_image => format!("<img src=\"{path}\">"),
+                    _ => format!("<a href=\"{target}\">{}</a>", description.unwrap_or(target)),
+                }
+            }
+        })
+        .to_string();
+    html.replace("\\\\\n", "<br>\n")
+        .trim_end_matches("\\\\")
+        .to_string()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    #[test]
+    fn documents_round_trip_with_references_and_metadata() {
+        let mut child = node(
+            3,
+            "Child",
+            "<p>* not a headline</p><p>#+TITLE: not a keyword</p><ul><li># not a comment</li></ul>",
+            Vec::new(),
+        );
+        child
+            .metadata
+            .insert(TAGS_KEY.to_string(), ":work:".to_string());
+        child
+            .metadata
+            .insert("DEADLINE".to_string(), "<2024-01-31 Wed>".to_string());
+        let document = Document {
+            root_node: node(
+                1,
+                "Root",
+                "",
+                vec![node(
+                    2,
+                    "A",
+                    r#"<p><a href="reference!("id:3")">see</a></p>"#,
+                    vec![child],
+                )],
+            ),
+            resources: BTreeMap::new(),
+        };
+        let (org, _) = document_to_org(document, &Default::default());
+        assert!(org.contains("[[*Child][see]]"), "{org}");
+        let root = org_to_node(org).unwrap();
+        let a = &root.children[0];
+        assert_eq!(
+            a.content,
+            r#"<p><a href="reference!("path:Root/A/Child")">see</a></p>"#
+        );
+        let child = &a.children[0];
+        assert_eq!(
+            child.content,
+            "<p>* not a headline</p>\n<p>#+TITLE: not a keyword</p>\n<ul><li># not a comment</li></ul>"
+        );
+        assert_eq!(child.metadata[TAGS_KEY], ":work:");
+        assert_eq!(child.metadata["DEADLINE"], "<2024-01-31 Wed>");
+    }
+}
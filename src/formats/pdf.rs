@@ -0,0 +1,1015 @@
+use super::{
+    html::{self, HtmlElement, HtmlNode},
+    image_size, image_type, jpeg_components, process_node_content, ExportOptions, NODE_LINK_PREFIX,
+    RESOURCE_LINK_PREFIX,
+};
+use crate::types::{Document, Node};
+use flate2::{write::ZlibEncoder, Compression};
+use pdf_writer::{
+    types::{ActionType, AnnotationType, PageMode},
+    Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr,
+};
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Write},
+};
+
+/// A4 page size in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 56.0;
+const FONT_SIZE: f32 = 11.0;
+/// Line height relative to the font size
+const LINE_HEIGHT: f32 = 1.35;
+const LIST_INDENT: f32 = 18.0;
+/// Font sizes of node headings by depth
+const HEADING_SIZES: [f32; 4] = [22.0, 18.0, 15.0, 13.0];
+/// Font sizes of `h1`-`h6` in the node content
+const CONTENT_HEADING_SIZES: [f32; 6] = [18.0, 15.0, 13.0, 12.0, 11.0, 11.0];
+/// Space for page numbers on the right of the table of contents
+const CONTENTS_PAGE_NUMBER_WIDTH: f32 = 36.0;
+/// Written instead of characters the standard fonts can't write
+const REPLACEMENT_CHARACTER: u8 = b'?';
+
+/// Widths of Helvetica characters from space to `~` (in thousandths of the font size)
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// Widths of Helvetica-Bold characters from space to `~`
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611, 975, 722, 722, 722, 722, 667,
+    611, 778, 722, 278, 556, 722, 611, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 333, 278, 333, 584, 556, 333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556,
+    278, 889, 611, 611, 611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+/// Standard fonts used by the exporter (they don't need to be embedded)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Font {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Monospace,
+}
+
+impl Font {
+    const ALL: [Font; 5] = [
+        Font::Regular,
+        Font::Bold,
+        Font::Italic,
+        Font::BoldItalic,
+        Font::Monospace,
+    ];
+
+    fn resource_name(self) -> Name<'static> {
+        Name(match self {
+            Font::Regular => b"F1",
+            Font::Bold => b"F2",
+            Font::Italic => b"F3",
+            Font::BoldItalic => b"F4",
+            Font::Monospace => b"F5",
+        })
+    }
+
+    fn base_font(self) -> Name<'static> {
+        Name(match self {
+            Font::Regular => b"Helvetica",
+            Font::Bold => b"Helvetica-Bold",
+            Font::Italic => b"Helvetica-Oblique",
+            Font::BoldItalic => b"Helvetica-BoldOblique",
+            Font::Monospace => b"Courier",
+        })
+    }
+
+    /// Width of WinAnsi encoded text in points
+    fn text_width(self, text: &[u8], size: f32) -> f32 {
+        let widths = match self {
+            Font::Monospace => return text.len() as f32 * 0.6 * size,
+            Font::Bold | Font::BoldItalic => &HELVETICA_BOLD_WIDTHS,
+            Font::Regular | Font::Italic => &HELVETICA_WIDTHS,
+        };
+        text.iter()
+            .map(|c| match c {
+                32..=126 => f32::from(widths[usize::from(c - 32)]),
+                0xA0 => 278.0,
+                _ => 556.0,
+            })
+            .sum::<f32>()
+            * size
+            / 1000.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Link {
+    Node(u32),
+    Uri(String),
+}
+
+#[derive(Debug, Clone, Default)]
+struct TextStyle {
+    bold: bool,
+    italic: bool,
+    monospace: bool,
+    link: Option<Link>,
+}
+
+impl TextStyle {
+    fn font(&self) -> Font {
+        match (self.monospace, self.bold, self.italic) {
+            (true, _, _) => Font::Monospace,
+            (false, true, true) => Font::BoldItalic,
+            (false, true, false) => Font::Bold,
+            (false, false, true) => Font::Italic,
+            (false, false, false) => Font::Regular,
+        }
+    }
+}
+
+/// Part of a word with the same style
+struct Fragment {
+    /// WinAnsi encoded text
+    text: Vec<u8>,
+    font: Font,
+    link: Option<Link>,
+    space_before: bool,
+    line_break_before: bool,
+}
+
+/// An image XObject
+struct PdfImage {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    filter: Filter,
+    color_space: Name<'static>,
+    /// Compressed alpha channel
+    alpha: Option<Vec<u8>>,
+}
+
+struct Page {
+    content: Content,
+    links: Vec<(Rect, Link)>,
+}
+
+/// Lays out the document on pages
+struct PdfLayout<'a> {
+    document: &'a Document,
+    pages: Vec<Page>,
+    /// Top of the free space on the current page
+    y: f32,
+    /// Page index and top of the node headings
+    node_positions: BTreeMap<u32, (usize, f32)>,
+    images: Vec<PdfImage>,
+    image_indices: BTreeMap<String, Option<usize>>,
+    fragments: Vec<Fragment>,
+    /// Node ids, page indices and baselines of the entries of the table of contents
+    contents: Vec<(u32, usize, f32)>,
+    pending_space: bool,
+    pending_line_break: bool,
+    font_size: f32,
+    indent: f32,
+    /// Space on the right of paragraphs
+    right_indent: f32,
+    list_marker: Option<String>,
+    preformatted: bool,
+}
+
+/// Creates a PDF document from the document.
+/// The outline mirrors the node hierarchy and references are links to the node headings.
+/// Text is written with the standard fonts, so characters outside of WinAnsiEncoding
+/// (e.g. Cyrillic or CJK) are written as '?', HTML, EPUB or DOCX export keeps them
+pub fn document_to_pdf(document: Document, options: &ExportOptions) -> Result<Vec<u8>, String> {
+    let mut layout = PdfLayout {
+        document: &document,
+        pages: Vec::new(),
+        y: 0.0,
+        node_positions: BTreeMap::new(),
+        images: Vec::new(),
+        image_indices: BTreeMap::new(),
+        fragments: Vec::new(),
+        contents: Vec::new(),
+        pending_space: false,
+        pending_line_break: false,
+        font_size: FONT_SIZE,
+        indent: 0.0,
+        right_indent: 0.0,
+        list_marker: None,
+        preformatted: false,
+    };
+    layout.new_page();
+    if options.table_of_contents && !document.root_node.children.is_empty() {
+        layout.layout_contents(&document.root_node);
+    }
+    layout.layout_node(&document.root_node, 0)?;
+    layout.write_contents_page_numbers();
+    let PdfLayout {
+        pages,
+        node_positions,
+        images,
+        ..
+    } = layout;
+
+    let mut pdf = Pdf::new();
+    let mut next_ref = Ref::new(1);
+    let catalog_id = next_ref.bump();
+    let page_tree_id = next_ref.bump();
+    let outline_id = next_ref.bump();
+    let info_id = next_ref.bump();
+    let font_ids = Font::ALL.map(|_| next_ref.bump());
+    let image_ids = images
+        .iter()
+        .map(|image| {
+            (
+                next_ref.bump(),
+                image.alpha.as_ref().map(|_| next_ref.bump()),
+            )
+        })
+        .collect::<Vec<_>>();
+    let page_ids = pages
+        .iter()
+        .map(|_| (next_ref.bump(), next_ref.bump()))
+        .collect::<Vec<_>>();
+    let mut outline_ids = BTreeMap::new();
+    add_outline_ids(&document.root_node, &mut next_ref, &mut outline_ids);
+
+    pdf.catalog(catalog_id)
+        .pages(page_tree_id)
+        .outlines(outline_id)
+        .page_mode(PageMode::UseOutlines);
+    pdf.document_info(info_id)
+        .title(TextStr(&document.root_node.name))
+        .producer(TextStr("Rustree"));
+    pdf.pages(page_tree_id)
+        .kids(page_ids.iter().map(|(id, _)| *id))
+        .count(page_ids.len() as i32);
+
+    for (font, id) in Font::ALL.iter().zip(font_ids) {
+        pdf.type1_font(id)
+            .base_font(font.base_font())
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+    }
+    for (image, (id, alpha_id)) in images.iter().zip(&image_ids) {
+        let mut xobject = pdf.image_xobject(*id, &image.data);
+        xobject
+            .width(image.width as i32)
+            .height(image.height as i32)
+            .color_space_name(image.color_space)
+            .bits_per_component(8)
+            .filter(image.filter);
+        if let Some(alpha_id) = alpha_id {
+            xobject.s_mask(*alpha_id);
+        }
+        xobject.finish();
+        if let (Some(alpha), Some(alpha_id)) = (&image.alpha, alpha_id) {
+            pdf.image_xobject(*alpha_id, alpha)
+                .width(image.width as i32)
+                .height(image.height as i32)
+                .color_space_name(Name(b"DeviceGray"))
+                .bits_per_component(8)
+                .filter(Filter::FlateDecode);
+        }
+    }
+
+    for (page, (page_id, content_id)) in pages.into_iter().zip(&page_ids) {
+        let mut pdf_page = pdf.page(*page_id);
+        pdf_page
+            .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+            .parent(page_tree_id)
+            .contents(*content_id);
+        let mut resources = pdf_page.resources();
+        let mut fonts = resources.fonts();
+        for (font, id) in Font::ALL.iter().zip(font_ids) {
+            fonts.pair(font.resource_name(), id);
+        }
+        fonts.finish();
+        let mut x_objects = resources.x_objects();
+        for (i, (id, _)) in image_ids.iter().enumerate() {
+            x_objects.pair(Name(image_name(i).as_bytes()), *id);
+        }
+        x_objects.finish();
+        resources.finish();
+        let mut annotations = pdf_page.annotations();
+        for (rect, link) in page.links {
+            let destination = match &link {
+                Link::Node(id) => match node_positions.get(id) {
+                    Some(position) => Some(*position),
+                    None => continue,
+                },
+                Link::Uri(_) => None,
+            };
+            let mut annotation = annotations.push();
+            annotation
+                .subtype(AnnotationType::Link)
+                .rect(rect)
+                .border(0.0, 0.0, 0.0, None);
+            let mut action = annotation.action();
+            match (link, destination) {
+                (_, Some((page_index, top))) => {
+                    action
+                        .action_type(ActionType::GoTo)
+                        .destination()
+                        .page(page_ids[page_index].0)
+                        .xyz(0.0, top, None);
+                }
+                (Link::Uri(uri), _) => {
+                    action.action_type(ActionType::Uri).uri(Str(uri.as_bytes()));
+                }
+                _ => {}
+            }
+        }
+        annotations.finish();
+        pdf_page.finish();
+        pdf.stream(*content_id, &compress(&page.content.finish())?)
+            .filter(Filter::FlateDecode);
+    }
+
+    let root_item = outline_ids[&document.root_node.id];
+    pdf.outline(outline_id)
+        .first(root_item)
+        .last(root_item)
+        .count(outline_ids.len() as i32);
+    write_outline_item(
+        &mut pdf,
+        &document.root_node,
+        outline_id,
+        (None, None),
+        &outline_ids,
+        &node_positions,
+        &page_ids,
+    );
+    Ok(pdf.finish())
+}
+
+fn image_name(index: usize) -> String {
+    format!("Im{index}")
+}
+
+fn add_outline_ids(node: &Node, next_ref: &mut Ref, outline_ids: &mut BTreeMap<u32, Ref>) {
+    outline_ids.insert(node.id, next_ref.bump());
+    for child in &node.children {
+        add_outline_ids(child, next_ref, outline_ids);
+    }
+}
+
+/// Writes the outline item of the node and its children, returns the number of written items
+fn write_outline_item(
+    pdf: &mut Pdf,
+    node: &Node,
+    parent: Ref,
+    (prev, next): (Option<Ref>, Option<Ref>),
+    outline_ids: &BTreeMap<u32, Ref>,
+    node_positions: &BTreeMap<u32, (usize, f32)>,
+    page_ids: &[(Ref, Ref)],
+) -> i32 {
+    let id = outline_ids[&node.id];
+    let child_ids = node
+        .children
+        .iter()
+        .map(|child| outline_ids[&child.id])
+        .collect::<Vec<_>>();
+    let mut count = 0;
+    for (i, child) in node.children.iter().enumerate() {
+        count += write_outline_item(
+            pdf,
+            child,
+            id,
+            (
+                i.checked_sub(1).map(|i| child_ids[i]),
+                child_ids.get(i + 1).copied(),
+            ),
+            outline_ids,
+            node_positions,
+            page_ids,
+        );
+    }
+    let mut item = pdf.outline_item(id);
+    item.title(TextStr(&node.name)).parent(parent);
+    if let Some(prev) = prev {
+        item.prev(prev);
+    }
+    if let Some(next) = next {
+        item.next(next);
+    }
+    if let (Some(first), Some(last)) = (child_ids.first(), child_ids.last()) {
+        item.first(*first).last(*last).count(count);
+    }
+    if let Some((page_index, top)) = node_positions.get(&node.id) {
+        item.dest()
+            .page(page_ids[*page_index].0)
+            .xyz(0.0, *top, None);
+    }
+    count + 1
+}
+
+impl PdfLayout<'_> {
+    /// Lays out the table of contents on its own pages, its entries link to the nodes
+    fn layout_contents(&mut self, root: &Node) {
+        self.font_size = HEADING_SIZES[1];
+        self.push_text(
+            "Contents",
+            &TextStyle {
+                bold: true,
+                ..Default::default()
+            },
+        );
+        self.flush_paragraph();
+        self.font_size = FONT_SIZE;
+        self.right_indent = CONTENTS_PAGE_NUMBER_WIDTH;
+        let mut nodes = root
+            .children
+            .iter()
+            .rev()
+            .map(|n| (n, 0))
+            .collect::<Vec<_>>();
+        while let Some((node, depth)) = nodes.pop() {
+            self.indent = LIST_INDENT * depth as f32;
+            // The first line of the entry stays on the page with its page number
+            self.ensure_space(FONT_SIZE * LINE_HEIGHT);
+            self.contents
+                .push((node.id, self.pages.len() - 1, self.y - FONT_SIZE));
+            self.push_text(
+                &node.name,
+                &TextStyle {
+                    link: Some(Link::Node(node.id)),
+                    ..Default::default()
+                },
+            );
+            self.flush_paragraph();
+            nodes.extend(node.children.iter().rev().map(|n| (n, depth + 1)));
+        }
+        self.indent = 0.0;
+        self.right_indent = 0.0;
+        self.new_page();
+    }
+
+    /// Writes page numbers of the nodes next to the entries of the table of contents
+    fn write_contents_page_numbers(&mut self) {
+        for (id, page, baseline) in &self.contents {
+            let Some((node_page, _)) = self.node_positions.get(id) else {
+                continue;
+            };
+            let number = (node_page + 1).to_string();
+            let width = Font::Regular.text_width(number.as_bytes(), FONT_SIZE);
+            write_text(
+                &mut self.pages[*page].content,
+                Font::Regular,
+                FONT_SIZE,
+                PAGE_WIDTH - MARGIN - width,
+                *baseline,
+                number.as_bytes(),
+            );
+        }
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(Page {
+            content: Content::new(),
+            links: Vec::new(),
+        });
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    /// Starts a new page if there is not enough space left
+    fn ensure_space(&mut self, height: f32) {
+        if self.y - height < MARGIN && self.y < PAGE_HEIGHT - MARGIN {
+            self.new_page();
+        }
+    }
+
+    fn page(&mut self) -> &mut Page {
+        self.pages.last_mut().unwrap()
+    }
+
+    fn layout_node(&mut self, node: &Node, depth: usize) -> Result<(), String> {
+        let size = HEADING_SIZES[depth.min(HEADING_SIZES.len() - 1)];
+        if self.y < PAGE_HEIGHT - MARGIN {
+            self.y -= size * 0.5;
+        }
+        // The heading is kept with a few lines of the content
+        self.ensure_space(size * LINE_HEIGHT + FONT_SIZE * LINE_HEIGHT * 3.0);
+        self.node_positions
+            .insert(node.id, (self.pages.len() - 1, self.y));
+        self.font_size = size;
+        self.push_text(
+            &node.name,
+            &TextStyle {
+                bold: true,
+                ..Default::default()
+            },
+        );
+        self.flush_paragraph();
+        self.font_size = FONT_SIZE;
+
+        let content = process_node_content(
+            node,
+            self.document,
+            |name| format!("{RESOURCE_LINK_PREFIX}{name}"),
+            |id| format!("{NODE_LINK_PREFIX}{id}"),
+        );
+        self.layout_nodes(&html::parse_html(&content), &TextStyle::default())?;
+        self.flush_paragraph();
+        for child in &node.children {
+            self.layout_node(child, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn layout_nodes(&mut self, nodes: &[HtmlNode], style: &TextStyle) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                HtmlNode::Text(text) if self.preformatted => self.push_preformatted(text, style),
+                HtmlNode::Text(text) => self.push_text(text, style),
+                HtmlNode::Element(e) => self.layout_element(e, style)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn layout_element(&mut self, e: &HtmlElement, style: &TextStyle) -> Result<(), String> {
+        let mut inner = style.clone();
+        match e.tag.as_str() {
+            "b" | "strong" => inner.bold = true,
+            "i" | "em" | "cite" | "var" => inner.italic = true,
+            "code" | "kbd" | "tt" | "samp" => inner.monospace = true,
+            "a" => {
+                inner.link = match e.attribute("href") {
+                    Some(href) if href.starts_with(NODE_LINK_PREFIX) => {
+                        href[NODE_LINK_PREFIX.len()..].parse().ok().map(Link::Node)
+                    }
+                    Some(href) if href.contains(':') => Some(Link::Uri(href.to_string())),
+                    _ => None,
+                }
+            }
+            "br" => {
+                self.pending_line_break = true;
+                return Ok(());
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.flush_paragraph();
+                let level = usize::from(e.tag.as_bytes()[1] - b'1');
+                self.font_size = CONTENT_HEADING_SIZES[level];
+                inner.bold = true;
+                self.layout_nodes(&e.children, &inner)?;
+                self.flush_paragraph();
+                self.font_size = FONT_SIZE;
+                return Ok(());
+            }
+            "pre" => {
+                self.flush_paragraph();
+                self.preformatted = true;
+                inner.monospace = true;
+                self.layout_nodes(&e.children, &inner)?;
+                self.flush_paragraph();
+                self.preformatted = false;
+                return Ok(());
+            }
+            "p" | "div" | "center" | "blockquote" | "dl" | "dt" | "dd" => {
+                self.flush_paragraph();
+                self.layout_nodes(&e.children, &inner)?;
+                self.flush_paragraph();
+                return Ok(());
+            }
+            "ul" | "ol" => {
+                self.flush_paragraph();
+                let mut number = 0;
+                for child in &e.children {
+                    match child {
+                        HtmlNode::Element(li) if li.tag == "li" => {
+                            number += 1;
+                            self.indent += LIST_INDENT;
+                            self.list_marker = Some(if e.tag == "ul" {
+                                "•".to_string()
+                            } else {
+                                format!("{number}.")
+                            });
+                            self.layout_nodes(&li.children, &inner)?;
+                            self.flush_paragraph();
+                            self.list_marker = None;
+                            self.indent -= LIST_INDENT;
+                        }
+                        _ => self.layout_nodes(std::slice::from_ref(child), &inner)?,
+                    }
+                }
+                return Ok(());
+            }
+            "table" => {
+                self.flush_paragraph();
+                // Cells of a row are separated by vertical bars
+                for row in html::table_rows(&e.children) {
+                    for (i, cell) in row.iter().enumerate() {
+                        if i > 0 {
+                            self.push_text(" | ", &inner);
+                        }
+                        let mut cell_style = inner.clone();
+                        cell_style.bold |= cell.tag == "th";
+                        self.layout_nodes(&cell.children, &cell_style)?;
+                    }
+                    self.flush_paragraph();
+                }
+                return Ok(());
+            }
+            "hr" => {
+                self.flush_paragraph();
+                self.ensure_space(FONT_SIZE);
+                let y = self.y - FONT_SIZE / 2.0;
+                let x = MARGIN + self.indent;
+                self.page()
+                    .content
+                    .set_line_width(0.5)
+                    .move_to(x, y)
+                    .line_to(PAGE_WIDTH - MARGIN, y)
+                    .stroke();
+                self.y -= FONT_SIZE;
+                return Ok(());
+            }
+            "img" => {
+                match e
+                    .attribute("src")
+                    .and_then(|src| src.strip_prefix(RESOURCE_LINK_PREFIX))
+                {
+                    Some(name) if self.image_index(name)?.is_some() => {
+                        self.flush_paragraph();
+                        self.layout_image(name)?;
+                    }
+                    _ => self.push_text(e.attribute("alt").unwrap_or_default(), style),
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+        self.layout_nodes(&e.children, &inner)
+    }
+
+    /// Adds text to the current paragraph collapsing whitespace
+    fn push_text(&mut self, text: &str, style: &TextStyle) {
+        let font = style.font();
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.pending_space = !self.fragments.is_empty();
+                continue;
+            }
+            let start_new = self.pending_space
+                || self.pending_line_break
+                || !matches!(self.fragments.last(),
+                    Some(last) if last.font == font && last.link == style.link);
+            if start_new {
+                self.fragments.push(Fragment {
+                    text: Vec::new(),
+                    font,
+                    link: style.link.clone(),
+                    space_before: self.pending_space,
+                    line_break_before: self.pending_line_break && !self.fragments.is_empty(),
+                });
+                self.pending_space = false;
+                self.pending_line_break = false;
+            }
+            let c = encode(c);
+            self.fragments.last_mut().unwrap().text.push(c);
+        }
+    }
+
+    /// Adds text to the current paragraph keeping whitespace and line breaks
+    fn push_preformatted(&mut self, text: &str, style: &TextStyle) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.pending_line_break = true;
+            }
+            if !line.is_empty() {
+                let text = line.chars().map(encode).collect();
+                self.fragments.push(Fragment {
+                    text,
+                    font: Font::Monospace,
+                    link: style.link.clone(),
+                    space_before: false,
+                    line_break_before: self.pending_line_break && !self.fragments.is_empty(),
+                });
+                self.pending_line_break = false;
+            }
+        }
+    }
+
+    /// Wraps the current paragraph into lines and draws them
+    fn flush_paragraph(&mut self) {
+        let fragments = std::mem::take(&mut self.fragments);
+        self.pending_space = false;
+        self.pending_line_break = false;
+        if fragments.is_empty() {
+            return;
+        }
+        let size = self.font_size;
+        let left = MARGIN + self.indent;
+        let max_width = PAGE_WIDTH - MARGIN - self.right_indent - left;
+
+        // Words are fragments that can't be separated by a line break
+        let mut lines: Vec<Vec<&Fragment>> = vec![Vec::new()];
+        let mut line_width = 0.0;
+        let mut index = 0;
+        while index < fragments.len() {
+            let end = fragments[index + 1..]
+                .iter()
+                .position(|f| f.space_before || f.line_break_before)
+                .map_or(fragments.len(), |i| i + index + 1);
+            let word = &fragments[index..end];
+            let space = if word[0].space_before {
+                word[0].font.text_width(b" ", size)
+            } else {
+                0.0
+            };
+            let width = word
+                .iter()
+                .map(|f| f.font.text_width(&f.text, size))
+                .sum::<f32>();
+            let line = lines.last().unwrap();
+            if !line.is_empty()
+                && (word[0].line_break_before || line_width + space + width > max_width)
+            {
+                lines.push(Vec::new());
+                line_width = width;
+            } else {
+                line_width += space + width;
+            }
+            lines.last_mut().unwrap().extend(word);
+            index = end;
+        }
+
+        let line_height = size * LINE_HEIGHT;
+        let marker = self.list_marker.take();
+        for (i, line) in lines.iter().enumerate() {
+            self.ensure_space(line_height);
+            let baseline = self.y - size;
+            let page = self.pages.last_mut().unwrap();
+            if let (0, Some(marker)) = (i, &marker) {
+                write_text(
+                    &mut page.content,
+                    Font::Regular,
+                    size,
+                    left - LIST_INDENT * 0.9,
+                    baseline,
+                    &marker.chars().map(encode).collect::<Vec<_>>(),
+                );
+            }
+            let mut x = left;
+            for (j, fragment) in line.iter().enumerate() {
+                if j > 0 && fragment.space_before {
+                    x += fragment.font.text_width(b" ", size);
+                }
+                let width = fragment.font.text_width(&fragment.text, size);
+                if let Some(link) = &fragment.link {
+                    page.content.set_fill_rgb(0.0, 0.2, 0.7);
+                    write_text(
+                        &mut page.content,
+                        fragment.font,
+                        size,
+                        x,
+                        baseline,
+                        &fragment.text,
+                    );
+                    page.content.set_fill_rgb(0.0, 0.0, 0.0);
+                    match page.links.last_mut() {
+                        // Words of the same link on the line share the area
+                        Some((rect, last))
+                            if j > 0 && line[j - 1].link.as_ref() == Some(link) && last == link =>
+                        {
+                            rect.x2 = x + width;
+                        }
+                        _ => page.links.push((
+                            Rect::new(x, baseline - size * 0.25, x + width, baseline + size * 0.85),
+                            link.clone(),
+                        )),
+                    }
+                } else {
+                    write_text(
+                        &mut page.content,
+                        fragment.font,
+                        size,
+                        x,
+                        baseline,
+                        &fragment.text,
+                    );
+                }
+                x += width;
+            }
+            self.y -= line_height;
+        }
+        self.y -= size * 0.4;
+    }
+
+    /// Gets the index of the image XObject of the resource, decoding it on first use
+    fn image_index(&mut self, name: &str) -> Result<Option<usize>, String> {
+        if let Some(index) = self.image_indices.get(name) {
+            return Ok(*index);
+        }
+        let index = match self.document.resources.get(name) {
+            Some(res) => decode_image(&res.bytes)?.map(|image| {
+                self.images.push(image);
+                self.images.len() - 1
+            }),
+            None => None,
+        };
+        self.image_indices.insert(name.to_string(), index);
+        Ok(index)
+    }
+
+    fn layout_image(&mut self, name: &str) -> Result<(), String> {
+        let Some(index) = self.image_index(name)? else {
+            return Ok(());
+        };
+        let image = &self.images[index];
+        let left = MARGIN + self.indent;
+        // Pixels are 0.75 points at 96 DPI, images are scaled down to fit the page
+        let mut width = image.width as f32 * 0.75;
+        let mut height = image.height as f32 * 0.75;
+        let scale = ((PAGE_WIDTH - MARGIN - left) / width)
+            .min((PAGE_HEIGHT - MARGIN * 2.0) / height)
+            .min(1.0);
+        width *= scale;
+        height *= scale;
+        self.ensure_space(height);
+        let y = self.y - height;
+        self.page()
+            .content
+            .save_state()
+            .transform([width, 0.0, 0.0, height, left, y])
+            .x_object(Name(image_name(index).as_bytes()))
+            .restore_state();
+        self.y = y - FONT_SIZE * 0.4;
+        Ok(())
+    }
+}
+
+fn write_text(content: &mut Content, font: Font, size: f32, x: f32, y: f32, text: &[u8]) {
+    content
+        .begin_text()
+        .set_font(font.resource_name(), size)
+        .next_line(x, y)
+        .show(Str(text))
+        .end_text();
+}
+
+/// Encodes a character in WinAnsiEncoding, others are replaced
+fn encode(c: char) -> u8 {
+    encode_win_ansi(c).unwrap_or(REPLACEMENT_CHARACTER)
+}
+
+/// Encodes a character in WinAnsiEncoding, `None` if it isn't in the encoding. Control
+/// characters (tabs) are spaces
+fn encode_win_ansi(c: char) -> Option<u8> {
+    Some(match c {
+        ' '..='~' | '\u{a0}'..='\u{ff}' => c as u8,
+        '€' => 0x80,
+        '‚' => 0x82,
+        'ƒ' => 0x83,
+        '„' => 0x84,
+        '…' => 0x85,
+        '†' => 0x86,
+        '‡' => 0x87,
+        'ˆ' => 0x88,
+        '‰' => 0x89,
+        'Š' => 0x8A,
+        '‹' => 0x8B,
+        'Œ' => 0x8C,
+        'Ž' => 0x8E,
+        '‘' => 0x91,
+        '’' => 0x92,
+        '“' => 0x93,
+        '”' => 0x94,
+        '•' => 0x95,
+        '–' => 0x96,
+        '—' => 0x97,
+        '˜' => 0x98,
+        '™' => 0x99,
+        'š' => 0x9A,
+        '›' => 0x9B,
+        'œ' => 0x9C,
+        'ž' => 0x9E,
+        'Ÿ' => 0x9F,
+        c if c.is_control() => b' ',
+        _ => return None,
+    })
+}
+
+fn compress(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).map_err(|e| e.to_string())?;
+    encoder.finish().map_err(|e| e.to_string())
+}
+
+/// Converts a PNG or JPEG image to an image XObject, other formats are not supported
+fn decode_image(bytes: &[u8]) -> Result<Option<PdfImage>, String> {
+    match image_type(bytes) {
+        Some(("jpg", _)) => {
+            let (Some((width, height)), Some(components)) =
+                (image_size(bytes), jpeg_components(bytes))
+            else {
+                return Ok(None);
+            };
+            Ok(Some(PdfImage {
+                width,
+                height,
+                data: bytes.to_vec(),
+                filter: Filter::DctDecode,
+                color_space: Name(match components {
+                    1 => b"DeviceGray",
+                    4 => b"DeviceCMYK",
+                    _ => b"DeviceRGB",
+                }),
+                alpha: None,
+            }))
+        }
+        Some(("png", _)) => {
+            let mut decoder = png::Decoder::new(Cursor::new(bytes));
+            decoder.set_transformations(png::Transformations::normalize_to_color8());
+            let Ok(mut reader) = decoder.read_info() else {
+                return Ok(None);
+            };
+            let mut buffer = vec![0; reader.output_buffer_size()];
+            let Ok(info) = reader.next_frame(&mut buffer) else {
+                return Ok(None);
+            };
+            let pixels = &buffer[..info.buffer_size()];
+            let (channels, color_space, has_alpha) = match info.color_type {
+                png::ColorType::Grayscale => (1, Name(b"DeviceGray"), false),
+                png::ColorType::GrayscaleAlpha => (2, Name(b"DeviceGray"), true),
+                png::ColorType::Rgba => (4, Name(b"DeviceRGB"), true),
+                _ => (3, Name(b"DeviceRGB"), false),
+            };
+            let color_channels = if has_alpha { channels - 1 } else { channels };
+            let mut color = Vec::with_capacity(pixels.len());
+            let mut alpha = Vec::new();
+            for pixel in pixels.chunks_exact(channels) {
+                color.extend_from_slice(&pixel[..color_channels]);
+                if has_alpha {
+                    alpha.push(pixel[channels - 1]);
+                }
+            }
+            Ok(Some(PdfImage {
+                width: info.width,
+                height: info.height,
+                data: compress(&color)?,
+                filter: Filter::FlateDecode,
+                color_space,
+                alpha: if has_alpha {
+                    Some(compress(&alpha)?)
+                } else {
+                    None
+                },
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(content: &str) -> Document {
+        let child = Node::new("Child".to_string(), content.to_string(), Vec::new());
+        Document {
+            root_node: Node::new("Root".to_string(), String::new(), vec![child]),
+            resources: BTreeMap::new(),
+        }
+    }
+
+    fn page_count(pdf: &[u8]) -> usize {
+        let pdf = String::from_utf8_lossy(pdf);
+        let count = &pdf[pdf.find("/Count ").unwrap() + 7..];
+        count[..count.find(|c: char| !c.is_ascii_digit()).unwrap()]
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn writes_western_european_text() {
+        let pdf = document_to_pdf(document("<p>Déjà vu – “naïve” €5</p>"), &Default::default());
+        assert!(pdf.unwrap().starts_with(b"%PDF"));
+    }
+
+    #[test]
+    fn replaces_characters_the_fonts_cant_write() {
+        let pdf = document_to_pdf(document("<p>Привет 你好</p>"), &Default::default());
+        assert!(pdf.unwrap().starts_with(b"%PDF"));
+        let encoded = "Пé你".chars().map(encode).collect::<Vec<_>>();
+        assert_eq!(encoded, b"?\xe9?");
+    }
+
+    #[test]
+    fn table_of_contents_is_on_its_own_page() {
+        let options = ExportOptions {
+            table_of_contents: true,
+            ..Default::default()
+        };
+        let without = document_to_pdf(document("<p>text</p>"), &Default::default()).unwrap();
+        let with = document_to_pdf(document("<p>text</p>"), &options).unwrap();
+        assert_eq!(page_count(&without), 1);
+        assert_eq!(page_count(&with), 2);
+    }
+}
@@ -1,7 +1,10 @@
 use crate::types::{self, *};
 use binrw::NullString;
 use flate2::{write::*, Compression};
-use std::io::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::prelude::*,
+};
 
 mod rtd_format {
     use binrw::{binrw, NullString};
@@ -13,6 +16,35 @@ mod rtd_format {
         pub resources_count: i32,
         #[br(count = resources_count - 1)]
         pub resources: Vec<Resource>,
+        /// Missing in documents saved by older versions
+        #[br(try)]
+        pub metadata: Option<Metadata>,
+        /// Missing in documents saved by older versions, their nodes get new ids
+        #[br(try)]
+        pub ids: Option<Ids>,
+    }
+    #[binrw]
+    #[derive(Debug, Clone)]
+    pub struct Ids {
+        pub ids_count: i32,
+        /// Ids of the nodes in depth-first order
+        #[br(count = ids_count - 1)]
+        pub ids: Vec<u32>,
+    }
+    #[binrw]
+    #[derive(Debug, Clone)]
+    pub struct Metadata {
+        pub entries_count: i32,
+        #[br(count = entries_count - 1)]
+        pub entries: Vec<MetadataEntry>,
+    }
+    #[binrw]
+    #[derive(Debug, Clone)]
+    pub struct MetadataEntry {
+        /// Index of the node in depth-first order
+        pub node_index: i32,
+        pub key: NullString,
+        pub value: NullString,
     }
     #[binrw]
     #[derive(Debug, Clone)]
@@ -88,6 +120,10 @@ pub fn deserealize(bytes: Vec<u8>) -> Result<rtd_format::Document, binrw::Error>
 }
 
 pub fn document_to_rtd_document(document: Document) -> rtd_format::Document {
+    let mut entries = Vec::new();
+    add_metadata_entries(&document.root_node, &mut 0, &mut entries);
+    let mut ids = Vec::new();
+    add_ids(&document.root_node, &mut ids);
     rtd_format::Document {
         root_node: node_to_rtd_node(&document.root_node),
         resources_count: (document.resources.len() + 1) as i32,
@@ -100,6 +136,39 @@ pub fn document_to_rtd_document(document: Document) -> rtd_format::Document {
                 bytes: res.bytes.clone(),
             })
             .collect(),
+        metadata: Some(rtd_format::Metadata {
+            entries_count: (entries.len() + 1) as i32,
+            entries,
+        }),
+        ids: Some(rtd_format::Ids {
+            ids_count: (ids.len() + 1) as i32,
+            ids,
+        }),
+    }
+}
+
+fn add_ids(node: &Node, ids: &mut Vec<u32>) {
+    ids.push(node.id);
+    for child in &node.children {
+        add_ids(child, ids);
+    }
+}
+
+fn add_metadata_entries(
+    node: &Node,
+    node_index: &mut i32,
+    entries: &mut Vec<rtd_format::MetadataEntry>,
+) {
+    for (key, value) in &node.metadata {
+        entries.push(rtd_format::MetadataEntry {
+            node_index: *node_index,
+            key: NullString::from(key.clone()),
+            value: NullString::from(value.clone()),
+        });
+    }
+    *node_index += 1;
+    for child in &node.children {
+        add_metadata_entries(child, node_index, entries);
     }
 }
 
@@ -113,8 +182,22 @@ fn node_to_rtd_node(node: &Node) -> rtd_format::Node {
 }
 
 pub fn rtd_document_to_document(document: rtd_format::Document) -> Document {
+    let mut metadata = BTreeMap::<i32, BTreeMap<String, String>>::new();
+    for entry in document.metadata.map(|m| m.entries).unwrap_or_default() {
+        metadata
+            .entry(entry.node_index)
+            .or_default()
+            .insert(entry.key.to_string(), entry.value.to_string());
+    }
+    let mut root_node = rtd_node_to_node(&document.root_node, &mut 0, &mut metadata);
+    let ids = document.ids.map(|ids| ids.ids).unwrap_or_default();
+    // Ids that don't match the nodes or repeat are ignored, the generated ones are kept
+    let unique = ids.iter().collect::<BTreeSet<_>>().len() == ids.len();
+    if unique && ids.len() == count_nodes(&root_node) {
+        set_ids(&mut root_node, &mut ids.into_iter());
+    }
     Document {
-        root_node: rtd_node_to_node(&document.root_node),
+        root_node,
         resources: document
             .resources
             .into_iter()
@@ -123,10 +206,103 @@ pub fn rtd_document_to_document(document: rtd_format::Document) -> Document {
     }
 }
 
-fn rtd_node_to_node(node: &rtd_format::Node) -> Node {
-    Node::new(
-        node.name.to_string(),
-        node.content.to_string(),
-        node.children.iter().map(rtd_node_to_node).collect(),
-    )
+fn count_nodes(node: &Node) -> usize {
+    1 + node.children.iter().map(count_nodes).sum::<usize>()
+}
+
+fn set_ids(node: &mut Node, ids: &mut impl Iterator<Item = u32>) {
+    if let Some(id) = ids.next() {
+        node.id = id;
+    }
+    for child in &mut node.children {
+        set_ids(child, ids);
+    }
+}
+
+fn rtd_node_to_node(
+    node: &rtd_format::Node,
+    node_index: &mut i32,
+    metadata: &mut BTreeMap<i32, BTreeMap<String, String>>,
+) -> Node {
+    let node_metadata = metadata.remove(node_index).unwrap_or_default();
+    *node_index += 1;
+    Node {
+        metadata: node_metadata,
+        ..Node::new(
+            node.name.to_string(),
+            node.content.to_string(),
+            node.children
+                .iter()
+                .map(|n| rtd_node_to_node(n, node_index, metadata))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document() -> Document {
+        let mut child = Node::new("Child".to_string(), "<p>text</p>".to_string(), Vec::new());
+        child.metadata.insert("TAGS".to_string(), ":a:".to_string());
+        let root = Node::new(
+            "Root".to_string(),
+            r#"reference!("id:1")"#.to_string(),
+            vec![
+                child,
+                Node::new("Other".to_string(), String::new(), Vec::new()),
+            ],
+        );
+        Document {
+            root_node: root,
+            resources: BTreeMap::from([("a.png".to_string(), types::Resource::new(vec![1, 2, 3]))]),
+        }
+    }
+
+    fn round_trip(document: rtd_format::Document) -> Document {
+        let bytes = serealize(document).unwrap();
+        rtd_document_to_document(deserealize(bytes).unwrap())
+    }
+
+    #[test]
+    fn keeps_ids_metadata_and_resources() {
+        let document = document();
+        let loaded = round_trip(document_to_rtd_document(document.clone()));
+        assert_eq!(loaded.root_node.id, document.root_node.id);
+        for (loaded, node) in loaded
+            .root_node
+            .children
+            .iter()
+            .zip(&document.root_node.children)
+        {
+            assert_eq!(loaded.id, node.id);
+            assert_eq!(loaded.name, node.name);
+            assert_eq!(loaded.metadata, node.metadata);
+        }
+        assert_eq!(loaded.resources["a.png"].bytes, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn reads_documents_without_ids_and_metadata() {
+        let rtd_document = rtd_format::Document {
+            metadata: None,
+            ids: None,
+            ..document_to_rtd_document(document())
+        };
+        let loaded = round_trip(rtd_document);
+        assert_eq!(loaded.root_node.children[1].name, "Other");
+        assert!(loaded.root_node.children[0].metadata.is_empty());
+    }
+
+    #[test]
+    fn ignores_ids_that_repeat() {
+        let mut rtd_document = document_to_rtd_document(document());
+        rtd_document.ids = Some(rtd_format::Ids {
+            ids_count: 4,
+            ids: vec![7, 7, 8],
+        });
+        let loaded = round_trip(rtd_document);
+        assert_ne!(loaded.root_node.children[0].id, loaded.root_node.id);
+    }
 }
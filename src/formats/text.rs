@@ -0,0 +1,452 @@
+use super::{
+    html::{self, HtmlElement, HtmlNode},
+    node_paths, process_node_content, ExportOptions, NODE_LINK_PREFIX, RESOURCE_LINK_PREFIX,
+};
+use crate::types::{Document, Node};
+use std::collections::BTreeMap;
+
+/// A paragraph of text
+struct TextBlock {
+    text: String,
+    /// Indentation of nested lists and quotes
+    indent: usize,
+    /// List bullet or number written before the first line
+    marker: String,
+    /// Preformatted text is not wrapped
+    preformatted: bool,
+    /// List items and table rows are not separated by blank lines
+    tight: bool,
+    /// The block starts a list or table
+    group_start: bool,
+}
+
+/// Converts the node and its children to plain text. Nodes are `- name` lines indented
+/// by depth or numbered by their position in the outline (`1.2.3 name`).
+/// References are resolved in the document and written as node paths
+pub fn node_to_text(node: &Node, document: &Document, options: &ExportOptions) -> String {
+    let paths = node_paths(&document.root_node);
+    let table_of_contents = if options.table_of_contents && !node.children.is_empty() {
+        format!(
+            "Contents\n\n{}\n",
+            node.children
+                .iter()
+                .enumerate()
+                .map(|(i, child)| contents_lines(child, options, 0, &(i + 1).to_string()))
+                .collect::<String>()
+        )
+    } else {
+        String::new()
+    };
+    table_of_contents + &node_to_outline(node, document, options, &paths, 0, "")
+}
+
+fn contents_lines(node: &Node, options: &ExportOptions, depth: usize, number: &str) -> String {
+    let line = if options.numbering {
+        format!("{number} {}", node.name)
+    } else {
+        format!("- {}", node.name)
+    };
+    let indent = "  ".repeat(depth);
+    wrap(&line, options.wrap_width, &indent, &format!("{indent}  "))
+        + &node
+            .children
+            .iter()
+            .enumerate()
+            .map(|(i, child)| {
+                contents_lines(child, options, depth + 1, &format!("{number}.{}", i + 1))
+            })
+            .collect::<String>()
+}
+
+fn node_to_outline(
+    node: &Node,
+    document: &Document,
+    options: &ExportOptions,
+    paths: &BTreeMap<u32, String>,
+    depth: usize,
+    number: &str,
+) -> String {
+    let (heading, indent, heading_indent) = if !options.numbering {
+        (
+            format!("- {}", node.name),
+            "  ".repeat(depth + 1),
+            "  ".repeat(depth),
+        )
+    } else if number.is_empty() {
+        (node.name.clone(), String::new(), String::new())
+    } else {
+        (
+            format!("{number} {}", node.name),
+            String::new(),
+            String::new(),
+        )
+    };
+    let mut text = wrap(
+        &heading,
+        options.wrap_width,
+        &heading_indent,
+        &format!("{heading_indent}  "),
+    );
+    text += &content_text(node, document, paths, options.wrap_width, &indent);
+    text.push('\n');
+    for (i, child) in node.children.iter().enumerate() {
+        let child_number = if number.is_empty() {
+            (i + 1).to_string()
+        } else {
+            format!("{number}.{}", i + 1)
+        };
+        text += &node_to_outline(child, document, options, paths, depth + 1, &child_number);
+    }
+    text
+}
+
+/// Converts the node content (without the name and children) to plain text
+pub fn content_to_text(node: &Node, document: &Document, wrap_width: usize) -> String {
+    content_text(
+        node,
+        document,
+        &node_paths(&document.root_node),
+        wrap_width,
+        "",
+    )
+}
+
+fn content_text(
+    node: &Node,
+    document: &Document,
+    paths: &BTreeMap<u32, String>,
+    wrap_width: usize,
+    indent: &str,
+) -> String {
+    let content = process_node_content(
+        node,
+        document,
+        |name| format!("{RESOURCE_LINK_PREFIX}{name}"),
+        |id| format!("{NODE_LINK_PREFIX}{id}"),
+    );
+    let mut converter = TextConverter {
+        paths,
+        blocks: Vec::new(),
+        text: String::new(),
+        indent: 0,
+        marker: String::new(),
+        preformatted: false,
+        tight: false,
+        group_start: false,
+        flushes: 0,
+    };
+    converter.convert_nodes(&html::parse_html(&content));
+    converter.flush();
+
+    let mut text = String::new();
+    for (i, block) in converter.blocks.iter().enumerate() {
+        if i > 0 && (block.group_start || !block.tight || !converter.blocks[i - 1].tight) {
+            text.push('\n');
+        }
+        let block_indent = format!("{indent}{}", "  ".repeat(block.indent));
+        if block.preformatted {
+            for line in block.text.lines() {
+                text += &format!("{block_indent}{line}\n");
+            }
+        } else {
+            text += &wrap(
+                &block.text,
+                wrap_width,
+                &format!("{block_indent}{}", block.marker),
+                &format!("{block_indent}{}", " ".repeat(block.marker.chars().count())),
+            );
+        }
+    }
+    text
+}
+
+/// Wraps text at the width (in characters) keeping line breaks.
+/// The first line starts with `first_prefix`, the other ones with `prefix`
+fn wrap(text: &str, width: usize, first_prefix: &str, prefix: &str) -> String {
+    let mut lines = Vec::new();
+    let mut line = first_prefix.to_string();
+    for (i, hard_line) in text.split('\n').enumerate() {
+        if i > 0 {
+            lines.push(line);
+            line = prefix.to_string();
+        }
+        let mut line_is_empty = true;
+        for word in hard_line.split(' ').filter(|w| !w.is_empty()) {
+            if !line_is_empty {
+                if width > 0 && line.chars().count() + 1 + word.chars().count() > width {
+                    lines.push(line);
+                    line = prefix.to_string();
+                } else {
+                    line.push(' ');
+                }
+            }
+            line.push_str(word);
+            line_is_empty = false;
+        }
+    }
+    lines.push(line);
+    lines
+        .iter()
+        .map(|l| l.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+        + "\n"
+}
+
+/// Converts HTML to paragraphs of plain text
+struct TextConverter<'a> {
+    paths: &'a BTreeMap<u32, String>,
+    blocks: Vec<TextBlock>,
+    /// Text of the current paragraph
+    text: String,
+    indent: usize,
+    marker: String,
+    preformatted: bool,
+    tight: bool,
+    /// The next block starts a list or table and is separated from the previous one
+    group_start: bool,
+    /// Number of paragraphs ended, blocks inside of a link end the text before it
+    flushes: usize,
+}
+
+impl TextConverter<'_> {
+    /// Ends the current paragraph
+    fn flush(&mut self) {
+        let text = if self.preformatted {
+            self.text.trim_matches('\n').to_string()
+        } else {
+            self.text
+                .split('\n')
+                .map(|l| l.trim())
+                .collect::<Vec<_>>()
+                .join("\n")
+                .trim()
+                .to_string()
+        };
+        self.text.clear();
+        self.flushes += 1;
+        if !text.is_empty() {
+            self.blocks.push(TextBlock {
+                text,
+                indent: self.indent,
+                marker: std::mem::take(&mut self.marker),
+                preformatted: self.preformatted,
+                tight: self.tight,
+                group_start: std::mem::take(&mut self.group_start),
+            });
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.preformatted {
+            self.text.push_str(text);
+            return;
+        }
+        // Whitespace is collapsed as in HTML
+        for c in text.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                if !self.text.is_empty() && !self.text.ends_with([' ', '\n']) {
+                    self.text.push(' ');
+                }
+            } else {
+                self.text.push(if c == '\u{a0}' { ' ' } else { c });
+            }
+        }
+    }
+
+    fn convert_nodes(&mut self, nodes: &[HtmlNode]) {
+        for node in nodes {
+            match node {
+                HtmlNode::Text(text) => self.push_text(text),
+                HtmlNode::Element(e) => self.convert_element(e),
+            }
+        }
+    }
+
+    fn convert_element(&mut self, e: &HtmlElement) {
+        match e.tag.as_str() {
+            "br" => {
+                if self.text.ends_with(' ') {
+                    self.text.pop();
+                }
+                self.text.push('\n');
+            }
+            "p" | "div" | "center" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "dl" | "dt"
+            | "dd" => {
+                self.flush();
+                self.convert_nodes(&e.children);
+                self.flush();
+            }
+            "blockquote" => {
+                self.flush();
+                self.indent += 1;
+                self.convert_nodes(&e.children);
+                self.flush();
+                self.indent -= 1;
+            }
+            "pre" => {
+                self.flush();
+                self.preformatted = true;
+                self.push_text(&html::nodes_text(&e.children));
+                self.flush();
+                self.preformatted = false;
+            }
+            "hr" => {
+                self.flush();
+                self.text.push_str("----------");
+                self.flush();
+            }
+            "ul" | "ol" => {
+                self.flush();
+                self.group_start = !self.tight;
+                let mut number = 0;
+                for child in &e.children {
+                    match child {
+                        HtmlNode::Element(li) if li.tag == "li" => {
+                            number += 1;
+                            self.marker = if e.tag == "ul" {
+                                "• ".to_string()
+                            } else {
+                                format!("{number}. ")
+                            };
+                            self.indent += 1;
+                            self.tight = true;
+                            self.convert_nodes(&li.children);
+                            self.flush();
+                            self.tight = false;
+                            self.marker.clear();
+                            self.indent -= 1;
+                        }
+                        _ => self.convert_nodes(std::slice::from_ref(child)),
+                    }
+                }
+            }
+            "table" => {
+                self.flush();
+                self.group_start = !self.tight;
+                self.tight = true;
+                for row in html::table_rows(&e.children) {
+                    for (i, cell) in row.iter().enumerate() {
+                        if i > 0 {
+                            self.text.push_str(" | ");
+                        }
+                        self.convert_nodes(&cell.children);
+                    }
+                    self.flush();
+                }
+                self.tight = false;
+            }
+            "img" => {
+                let description = match e.attribute("src") {
+                    Some(src) if src.starts_with(RESOURCE_LINK_PREFIX) => {
+                        &src[RESOURCE_LINK_PREFIX.len()..]
+                    }
+                    _ => e.attribute("alt").unwrap_or_default(),
+                };
+                if description.is_empty() {
+                    self.push_text(" [image] ");
+                } else {
+                    self.push_text(&format!(" [image: {description}] "));
+                }
+            }
+            "a" => {
+                let (start, flushes) = (self.text.len(), self.flushes);
+                self.convert_nodes(&e.children);
+                // After a block in the link the label is the text of the last paragraph
+                let start = if self.flushes == flushes { start } else { 0 };
+                let label = self.text[start..].trim().to_string();
+                let target = match e.attribute("href") {
+                    Some(href) if href.starts_with(NODE_LINK_PREFIX) => Some(
+                        href[NODE_LINK_PREFIX.len()..]
+                            .parse::<u32>()
+                            .ok()
+                            .and_then(|id| self.paths.get(&id))
+                            .cloned()
+                            .unwrap_or("missing node".to_string()),
+                    ),
+                    Some(href) if href.starts_with(RESOURCE_LINK_PREFIX) => {
+                        Some(href[RESOURCE_LINK_PREFIX.len()..].to_string())
+                    }
+                    Some(href) if !href.is_empty() && href != label => Some(href.to_string()),
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    if label.is_empty() {
+                        self.push_text(&target);
+                    } else {
+                        self.push_text(&format!(" ({target})"));
+                    }
+                }
+            }
+            _ => self.convert_nodes(&e.children),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    fn document() -> Document {
+        Document {
+            root_node: node(
+                1,
+                "Root",
+                r#"<p>Fish &amp; chips &lt;b&gt; see <a href="reference!("id:3")">B</a></p>"#,
+                vec![
+                    node(2, "A", "<ul><li>one</li><li>two</li></ul>", Vec::new()),
+                    node(3, "B/C", "", Vec::new()),
+                ],
+            ),
+            resources: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn decodes_entities_and_writes_references_as_paths() {
+        let document = document();
+        let text = node_to_text(&document.root_node, &document, &Default::default());
+        assert_eq!(
+            text,
+            "- Root\n  Fish & chips <b> see B (Root/B\\/C)\n\n  - A\n      • one\n      • two\n\n  - B/C\n\n"
+        );
+    }
+
+    #[test]
+    fn links_with_blocks_inside() {
+        let document = Document {
+            root_node: node(
+                1,
+                "Root",
+                r#"xx<a href="http://x">ab<div>t</div></a><table><tr><td><a href="http://y">é<p>cell</p></a></td></tr></table>"#,
+                Vec::new(),
+            ),
+            resources: BTreeMap::new(),
+        };
+        let text = content_to_text(&document.root_node, &document, 0);
+        assert!(text.starts_with("xxab\n\nt\n\nhttp://x\n"), "{text}");
+        assert!(text.contains("é\ncell\nhttp://y"), "{text}");
+    }
+
+    #[test]
+    fn numbers_wraps_and_lists_contents() {
+        let document = document();
+        let options = ExportOptions {
+            numbering: true,
+            table_of_contents: true,
+            wrap_width: 20,
+            ..Default::default()
+        };
+        let text = node_to_text(&document.root_node, &document, &options);
+        assert!(text
+            .starts_with("Contents\n\n1 A\n2 B/C\n\nRoot\nFish & chips <b> see\nB (Root/B\\/C)\n"));
+        assert!(text.contains("\n1 A\n  • one\n"), "{text}");
+    }
+}
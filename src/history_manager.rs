@@ -1,6 +1,6 @@
 use crate::{
     types::Document,
-    utils::{get_node_parent, modify_document, DocumentModification},
+    utils::{get_node_parent, modify_document, DocumentModification, MoveDirection},
 };
 
 #[derive(Clone, Default, Debug)]
@@ -30,7 +30,33 @@ fn create_undo_modification(
             DocumentModification::PasteNode(node, parent.unwrap_or(root.id))
         }
         DocumentModification::CreateNode(_, new_id) => DocumentModification::DeleteNode(new_id),
-        _ => modification,
+        DocumentModification::PasteNode(node, _) => DocumentModification::DeleteNode(node.id),
+        DocumentModification::MoveNode(id, MoveDirection::Up) => {
+            DocumentModification::MoveNode(id, MoveDirection::Down)
+        }
+        DocumentModification::MoveNode(id, MoveDirection::Down) => {
+            DocumentModification::MoveNode(id, MoveDirection::Up)
+        }
+        DocumentModification::AddResource(name, _) | DocumentModification::DeleteResource(name) => {
+            match document.resources.get(&name) {
+                Some(res) => DocumentModification::AddResource(name, res.clone()),
+                None => DocumentModification::DeleteResource(name),
+            }
+        }
+        DocumentModification::Batch(modifications) => {
+            // Each modification is undone in the document it was applied to, in reverse order
+            let mut document = document;
+            let mut undo = Vec::new();
+            for modification in modifications {
+                undo.push(create_undo_modification(
+                    document.clone(),
+                    modification.clone(),
+                ));
+                document = modify_document(document, modification);
+            }
+            undo.reverse();
+            DocumentModification::Batch(undo)
+        }
     }
 }
 
@@ -40,6 +66,10 @@ impl HistoryManager {
             ..Default::default()
         }
     }
+    /// `true` if there are no actions to undo or redo
+    pub fn is_empty(&self) -> bool {
+        self.undo_stack.is_empty() && self.redo_stack.is_empty()
+    }
     pub fn register_document_modification(
         &mut self,
         document: Document,
@@ -51,18 +81,74 @@ impl HistoryManager {
         });
         modify_document(document, modification)
     }
-    pub fn undo_action(&mut self, document: Document) -> Option<Document> {
+    /// Undoes the last action, returns the document and the modification applied to it
+    pub fn undo_action(&mut self, document: Document) -> Option<(Document, DocumentModification)> {
         if let Some(action) = self.undo_stack.pop() {
             self.redo_stack.push(action.clone());
-            return Some(modify_document(document, action.undo));
+            return Some((modify_document(document, action.undo.clone()), action.undo));
         }
         None
     }
-    pub fn redo_action(&mut self, document: Document) -> Option<Document> {
+    /// Redoes the last undone action, returns the document and the modification applied to it
+    pub fn redo_action(&mut self, document: Document) -> Option<(Document, DocumentModification)> {
         if let Some(action) = self.redo_stack.pop() {
             self.undo_stack.push(action.clone());
-            return Some(modify_document(document, action.redo));
+            return Some((modify_document(document, action.redo.clone()), action.redo));
         }
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        formats::json::document_to_json,
+        types::{Node, Resource},
+    };
+    use std::collections::BTreeMap;
+
+    fn document() -> Document {
+        let child = Node::new("Child".to_string(), "text".to_string(), Vec::new());
+        Document {
+            root_node: Node::new("Root".to_string(), String::new(), vec![child]),
+            resources: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn undoes_and_redoes_a_batch_as_one_action() {
+        let document = document();
+        let child = document.root_node.children[0].clone();
+        let pasted = Node::new("Pasted".to_string(), String::new(), Vec::new());
+        let batch = DocumentModification::Batch(vec![
+            DocumentModification::EditNode(
+                child.id,
+                Node {
+                    name: "Renamed".to_string(),
+                    ..child.clone()
+                },
+            ),
+            DocumentModification::AddResource("a.png".to_string(), Resource::new(vec![1])),
+            DocumentModification::PasteNode(pasted, child.id),
+            DocumentModification::EditNode(
+                child.id,
+                Node {
+                    name: "Renamed twice".to_string(),
+                    ..child
+                },
+            ),
+        ]);
+        let mut history = HistoryManager::new();
+        let modified = history.register_document_modification(document.clone(), batch);
+        assert_eq!(modified.root_node.children[0].name, "Renamed twice");
+        assert_eq!(modified.root_node.children[0].children.len(), 1);
+
+        let (undone, _) = history.undo_action(modified.clone()).unwrap();
+        assert_eq!(document_to_json(undone.clone()), document_to_json(document));
+        assert!(history.undo_action(undone.clone()).is_none());
+
+        let (redone, _) = history.redo_action(undone).unwrap();
+        assert_eq!(document_to_json(redone), document_to_json(modified));
+    }
+}
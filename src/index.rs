@@ -0,0 +1,463 @@
+//! Inverted index of node names and text (without HTML tags) that is updated with
+//! the modifications of the document instead of being rebuilt
+
+use crate::{
+    formats::html::remove_html_tags,
+    search::{field_matches, SearchField, SearchMatch, SearchOptions},
+    types::{Document, Node},
+    utils::{find_node_ref, DocumentModification},
+};
+use regex::Regex;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    hash::{BuildHasherDefault, Hasher},
+};
+
+/// Three lowercase characters packed in a number, queries shorter than that
+/// can't use the index
+type Trigram = u64;
+
+/// Hasher of trigrams and ids, much faster than the default one for numbers
+#[derive(Default)]
+struct NumberHasher(u64);
+
+impl Hasher for NumberHasher {
+    fn finish(&self) -> u64 {
+        self.0 ^ (self.0 >> 29)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(*byte as u64);
+        }
+    }
+    fn write_u32(&mut self, n: u32) {
+        self.write_u64(n as u64);
+    }
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0 ^ n).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+type NumberHasherBuilder = BuildHasherDefault<NumberHasher>;
+
+#[derive(Debug, Clone)]
+struct IndexedNode {
+    name: String,
+    /// Path as in `node_to_tree_vec` (`/` in names is escaped)
+    path: String,
+    text: String,
+    parent: Option<u32>,
+    children: Vec<u32>,
+    /// Sorted without duplicates
+    trigrams: Vec<Trigram>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DocumentIndex {
+    nodes: HashMap<u32, IndexedNode, NumberHasherBuilder>,
+    root_id: u32,
+    /// Nodes with each trigram in the name or the text
+    trigrams: HashMap<Trigram, HashSet<u32, NumberHasherBuilder>, NumberHasherBuilder>,
+    /// Paths with ids of the nodes, for completion of paths. Siblings may have the same path
+    paths: BTreeSet<(String, u32)>,
+}
+
+/// Trigrams of the text ignoring case
+fn trigrams(text: &str) -> impl Iterator<Item = Trigram> + '_ {
+    let mut chars = text.chars().flat_map(char::to_lowercase);
+    let (mut a, mut b) = (chars.next(), chars.next());
+    chars.filter_map(move |c| {
+        let trigram = Some(((a? as u64) << 42) | ((b? as u64) << 21) | c as u64);
+        (a, b) = (b, Some(c));
+        trigram
+    })
+}
+
+fn node_trigrams(name: &str, text: &str) -> Vec<Trigram> {
+    let mut trigrams = trigrams(name).chain(trigrams(text)).collect::<Vec<_>>();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    trigrams
+}
+
+impl DocumentIndex {
+    /// Indexes all nodes of the document
+    pub fn new(document: &Document) -> Self {
+        let mut index = Self {
+            root_id: document.root_node.id,
+            ..Default::default()
+        };
+        index.add_node(&document.root_node, None);
+        index
+    }
+
+    /// Updates the index after the modification was applied to the document
+    pub fn apply(&mut self, document: &Document, modification: &DocumentModification) {
+        let synced = match modification {
+            DocumentModification::EditNode(id, _) => {
+                // The node may be deleted by a later modification of a batch
+                if let Some(node) = find_node_ref(&document.root_node, *id) {
+                    self.edit_node(node);
+                }
+                true
+            }
+            DocumentModification::DeleteNode(id) => {
+                if let Some(parent) = self.nodes.get(id).and_then(|node| node.parent) {
+                    if let Some(parent) = self.nodes.get_mut(&parent) {
+                        parent.children.retain(|child| child != id);
+                    }
+                    self.remove_node(*id);
+                }
+                true
+            }
+            DocumentModification::CreateNode(parent, _)
+            | DocumentModification::PasteNode(_, parent) => self.sync_children(document, *parent),
+            DocumentModification::MoveNode(id, _) => {
+                match self.nodes.get(id).and_then(|node| node.parent) {
+                    Some(parent) => self.sync_children(document, parent),
+                    None => true,
+                }
+            }
+            DocumentModification::AddResource(..) | DocumentModification::DeleteResource(_) => true,
+            DocumentModification::Batch(modifications) => {
+                for modification in modifications {
+                    self.apply(document, modification);
+                }
+                true
+            }
+        };
+        if !synced {
+            *self = Self::new(document);
+        }
+    }
+
+    /// Paths and ids of all nodes in the order of the tree, as `node_to_tree_vec`
+    pub fn paths(&self) -> Vec<(String, u32)> {
+        self.tree_ids()
+            .into_iter()
+            .map(|id| (self.nodes[&id].path.clone(), id))
+            .collect()
+    }
+
+    /// Ids of all nodes in the order of the tree
+    fn tree_ids(&self) -> Vec<u32> {
+        let mut tree_ids = Vec::with_capacity(self.nodes.len());
+        let mut ids = vec![self.root_id];
+        while let Some(id) = ids.pop() {
+            let Some(node) = self.nodes.get(&id) else {
+                continue;
+            };
+            tree_ids.push(id);
+            ids.extend(node.children.iter().rev());
+        }
+        tree_ids
+    }
+
+    /// Paths of nodes that start with the prefix, sorted without duplicates
+    pub fn complete_path(&self, prefix: &str) -> Vec<String> {
+        let mut paths = self
+            .paths
+            .range((prefix.to_string(), 0)..)
+            .take_while(|(path, _)| path.starts_with(prefix))
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        paths.dedup();
+        paths
+    }
+
+    /// Finds matches of the pattern in the order of the tree. Only nodes with all trigrams
+    /// of the query are searched, unless it is a regular expression
+    pub fn search(&self, query: &str, options: SearchOptions, pattern: &Regex) -> Vec<SearchMatch> {
+        let ids = match (!options.regex).then(|| self.candidates(query)).flatten() {
+            Some(candidates) => {
+                let mut candidates = candidates
+                    .into_iter()
+                    .filter(|id| self.nodes.contains_key(id))
+                    .map(|id| (self.tree_position(id), id))
+                    .collect::<Vec<_>>();
+                candidates.sort_unstable();
+                candidates.into_iter().map(|(_, id)| id).collect()
+            }
+            None => self.tree_ids(),
+        };
+        let mut matches = Vec::new();
+        for id in ids {
+            let node = &self.nodes[&id];
+            let name_matches =
+                field_matches(id, &node.path, SearchField::Name, &node.name, pattern);
+            matches.extend(name_matches);
+            let text_matches =
+                field_matches(id, &node.path, SearchField::Content, &node.text, pattern);
+            matches.extend(text_matches);
+        }
+        matches
+    }
+
+    /// Indices of the node and its parents among their siblings from the root, sorting
+    /// by them gives the order of the tree
+    fn tree_position(&self, mut id: u32) -> Vec<usize> {
+        let mut position = Vec::new();
+        while let Some(parent) = self.nodes.get(&id).and_then(|node| node.parent) {
+            let siblings = &self.nodes[&parent].children;
+            position.push(siblings.iter().position(|child| *child == id).unwrap_or(0));
+            id = parent;
+        }
+        position.reverse();
+        position
+    }
+
+    /// Nodes that contain all trigrams of the text, `None` if it is too short to tell
+    fn candidates(&self, text: &str) -> Option<HashSet<u32>> {
+        let mut postings = Vec::new();
+        for trigram in trigrams(text) {
+            match self.trigrams.get(&trigram) {
+                Some(ids) => postings.push(ids),
+                None => return Some(HashSet::new()),
+            }
+        }
+        // Intersecting from the rarest trigram checks the fewest nodes
+        postings.sort_by_key(|ids| ids.len());
+        let (rarest, others) = postings.split_first()?;
+        Some(
+            rarest
+                .iter()
+                .filter(|id| others.iter().all(|ids| ids.contains(id)))
+                .copied()
+                .collect(),
+        )
+    }
+
+    fn add_node(&mut self, node: &Node, parent: Option<u32>) {
+        let path = match parent.and_then(|parent| self.nodes.get(&parent)) {
+            Some(parent) => format!("{}/{}", parent.path, node.name.replace('/', "\\/")),
+            None => node.name.replace('/', "\\/"),
+        };
+        let text = remove_html_tags(&node.content);
+        let trigrams = node_trigrams(&node.name, &text);
+        for trigram in &trigrams {
+            self.trigrams.entry(*trigram).or_default().insert(node.id);
+        }
+        self.paths.insert((path.clone(), node.id));
+        self.nodes.insert(
+            node.id,
+            IndexedNode {
+                name: node.name.clone(),
+                path,
+                text,
+                parent,
+                children: node.children.iter().map(|child| child.id).collect(),
+                trigrams,
+            },
+        );
+        for child in &node.children {
+            self.add_node(child, Some(node.id));
+        }
+    }
+
+    /// Removes the node and its children, but not the node from children of its parent
+    fn remove_node(&mut self, id: u32) {
+        let Some(node) = self.nodes.remove(&id) else {
+            return;
+        };
+        self.remove_trigrams(id, &node.trigrams);
+        self.paths.remove(&(node.path, id));
+        for child in node.children {
+            self.remove_node(child);
+        }
+    }
+
+    fn remove_trigrams(&mut self, id: u32, node_trigrams: &[Trigram]) {
+        for trigram in node_trigrams {
+            if let Some(ids) = self.trigrams.get_mut(trigram) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.trigrams.remove(trigram);
+                }
+            }
+        }
+    }
+
+    /// Reindexes the name and the text of the node, paths of its children change with the name
+    fn edit_node(&mut self, node: &Node) {
+        let Some(indexed) = self.nodes.get(&node.id) else {
+            return;
+        };
+        let old_trigrams = indexed.trigrams.clone();
+        let renamed = indexed.name != node.name;
+        self.remove_trigrams(node.id, &old_trigrams);
+        let text = remove_html_tags(&node.content);
+        let trigrams = node_trigrams(&node.name, &text);
+        for trigram in &trigrams {
+            self.trigrams.entry(*trigram).or_default().insert(node.id);
+        }
+        let indexed = self.nodes.get_mut(&node.id).unwrap();
+        indexed.name = node.name.clone();
+        indexed.text = text;
+        indexed.trigrams = trigrams;
+        if renamed {
+            self.update_paths(node.id);
+        }
+    }
+
+    /// Recomputes paths of the node and its children from the path of its parent
+    fn update_paths(&mut self, id: u32) {
+        let parent_path = self
+            .nodes
+            .get(&id)
+            .and_then(|node| node.parent)
+            .and_then(|parent| self.nodes.get(&parent))
+            .map(|parent| parent.path.clone());
+        let Some(node) = self.nodes.get_mut(&id) else {
+            return;
+        };
+        let name = node.name.replace('/', "\\/");
+        let path = match parent_path {
+            Some(parent_path) => format!("{parent_path}/{name}"),
+            None => name,
+        };
+        let old_path = std::mem::replace(&mut node.path, path.clone());
+        let children = node.children.clone();
+        self.paths.remove(&(old_path, id));
+        self.paths.insert((path, id));
+        for child in children {
+            self.update_paths(child);
+        }
+    }
+
+    /// Indexes new children of the node and takes their order from the document.
+    /// `false` if the node isn't in the document or the index
+    fn sync_children(&mut self, document: &Document, id: u32) -> bool {
+        let Some(node) = find_node_ref(&document.root_node, id) else {
+            return false;
+        };
+        let Some(indexed) = self.nodes.get(&id) else {
+            return false;
+        };
+        let children = node
+            .children
+            .iter()
+            .map(|child| child.id)
+            .collect::<Vec<_>>();
+        let removed = indexed
+            .children
+            .iter()
+            .filter(|child| !children.contains(child))
+            .copied()
+            .collect::<Vec<_>>();
+        for child in removed {
+            self.remove_node(child);
+        }
+        for child in &node.children {
+            if !self.nodes.contains_key(&child.id) {
+                self.add_node(child, Some(id));
+            }
+        }
+        self.nodes.get_mut(&id).unwrap().children = children;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        search::{search_node, search_pattern},
+        utils::{modify_document, MoveDirection},
+    };
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    fn document() -> Document {
+        Document {
+            root_node: node(
+                1,
+                "Root",
+                "<p>root text</p>",
+                vec![
+                    node(
+                        2,
+                        "Alpha",
+                        "<p>apple <b>pie</b></p>",
+                        vec![node(3, "Beta", "<p>pie</p>", vec![])],
+                    ),
+                    node(4, "Gamma", "<p>apple &amp; pear</p>", vec![]),
+                    node(5, "Gamma", "<p>pie</p>", vec![]),
+                ],
+            ),
+            resources: Default::default(),
+        }
+    }
+
+    fn matches(matches: Vec<SearchMatch>) -> Vec<(u32, bool, usize, String)> {
+        matches
+            .into_iter()
+            .map(|m| {
+                (
+                    m.node_id,
+                    m.field == SearchField::Name,
+                    m.occurrence,
+                    m.snippet,
+                )
+            })
+            .collect()
+    }
+
+    fn assert_consistent(index: &DocumentIndex, document: &Document) {
+        let rebuilt = DocumentIndex::new(document);
+        assert_eq!(index.paths(), rebuilt.paths());
+        assert_eq!(index.complete_path(""), rebuilt.complete_path(""));
+        for (query, regex) in [
+            ("pie", false),
+            ("apple", false),
+            ("Gam", false),
+            ("p.e", true),
+        ] {
+            let options = SearchOptions {
+                regex,
+                ..Default::default()
+            };
+            let pattern = search_pattern(query, options).unwrap();
+            let expected = matches(search_node(&document.root_node, &pattern));
+            assert_eq!(matches(index.search(query, options, &pattern)), expected);
+            assert_eq!(matches(rebuilt.search(query, options, &pattern)), expected);
+        }
+    }
+
+    #[test]
+    fn applied_modifications_match_a_rebuilt_index() {
+        let mut document = document();
+        let mut index = DocumentIndex::new(&document);
+        assert_consistent(&index, &document);
+        let modifications = [
+            DocumentModification::EditNode(3, node(3, "Delta", "<p>apple pie</p>", vec![])),
+            DocumentModification::MoveNode(5, MoveDirection::Up),
+            DocumentModification::PasteNode(node(6, "Epsilon", "<p>pie pie</p>", vec![]), 3),
+            DocumentModification::CreateNode(4, 7),
+            DocumentModification::Batch(vec![
+                DocumentModification::EditNode(2, node(2, "Zeta", "<p>pear</p>", vec![])),
+                DocumentModification::DeleteNode(4),
+            ]),
+        ];
+        for modification in modifications {
+            document = modify_document(document, modification.clone());
+            index.apply(&document, &modification);
+            assert_consistent(&index, &document);
+        }
+    }
+
+    #[test]
+    fn completes_paths_of_siblings_with_the_same_name() {
+        let mut document = document();
+        let mut index = DocumentIndex::new(&document);
+        assert_eq!(index.complete_path("Root/G"), vec!["Root/Gamma"]);
+        let modification = DocumentModification::DeleteNode(5);
+        document = modify_document(document, modification.clone());
+        index.apply(&document, &modification);
+        assert_eq!(index.complete_path("Root/G"), vec!["Root/Gamma"]);
+    }
+}
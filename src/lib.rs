@@ -0,0 +1,22 @@
+//! Rustree documents without the GUI: the document model, the `.rtd` reader and writer,
+//! the text processor, document modifications, the search index and exporters.
+//!
+//! The notepad itself is built with the `gui` feature (enabled by default).
+
+#[cfg(feature = "gui")]
+pub mod app;
+pub mod check;
+pub mod cli;
+#[cfg(feature = "gui")]
+pub mod event_loop;
+pub mod formats;
+pub mod history_manager;
+pub mod index;
+pub mod search;
+#[cfg(feature = "gui")]
+pub mod settings;
+pub mod text_processor;
+pub mod types;
+#[cfg(feature = "gui")]
+pub mod ui;
+pub mod utils;
@@ -1,17 +1,42 @@
-#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+#![cfg_attr(
+    all(feature = "gui", not(debug_assertions)),
+    windows_subsystem = "windows"
+)]
 
-mod app;
-mod event_loop;
-mod formats;
-mod history_manager;
-mod settings;
-mod types;
-mod ui;
-mod utils;
+use rustree::cli;
 
-use app::Application;
+/// The GUI subsystem has no console, so output of the commands would be lost. The console
+/// of the command prompt the program was started from is used instead
+#[cfg(all(windows, feature = "gui", not(debug_assertions)))]
+fn attach_console() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    // It fails when there is no parent console (started from Explorer), that's fine
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
 
 fn main() {
-    let mut app = Application::new();
-    app.run();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    #[cfg(all(windows, feature = "gui", not(debug_assertions)))]
+    if cli::is_command(&args) {
+        attach_console();
+    }
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+    #[cfg(feature = "gui")]
+    {
+        let mut app = rustree::app::Application::new();
+        app.run(&args);
+    }
+    #[cfg(not(feature = "gui"))]
+    {
+        eprintln!("rustree: built without the GUI, see `rustree help`");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,614 @@
+//! Full-text search of node names and content (without HTML tags)
+
+use crate::{
+    formats::{
+        escape_xml,
+        html::{html_text, remove_html_tags, HtmlText},
+    },
+    types::{Document, Node},
+    utils::DocumentModification,
+};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, ops::Range};
+
+/// Characters of the text shown around a match in snippets
+const SNIPPET_CONTEXT: usize = 40;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    /// The query is a regular expression, otherwise it is plain text
+    pub regex: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchField {
+    Name,
+    Content,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchMatch {
+    pub node_id: u32,
+    /// Path of the node (Root/Node)
+    pub path: String,
+    pub field: SearchField,
+    /// Index of the match among the matches in the field
+    pub occurrence: usize,
+    /// The match with the text around it on one line
+    pub snippet: String,
+}
+
+/// Creates the regular expression that finds the query
+pub fn search_pattern(query: &str, options: SearchOptions) -> Result<Regex, String> {
+    if query.is_empty() {
+        return Err("The search query is empty".to_string());
+    }
+    let pattern = if options.regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+    let pattern = if options.whole_word {
+        format!(r"\b(?:{pattern})\b")
+    } else {
+        pattern
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!options.case_sensitive)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Finds matches in names and content of the node and its children
+pub fn search_node(node: &Node, pattern: &Regex) -> Vec<SearchMatch> {
+    let mut matches = Vec::new();
+    search_node_recursive(node, "", pattern, &mut matches);
+    matches
+}
+
+fn search_node_recursive(
+    node: &Node,
+    parent_path: &str,
+    pattern: &Regex,
+    matches: &mut Vec<SearchMatch>,
+) {
+    // '/' in names is escaped like in paths of references
+    let name = node.name.replace('/', "\\/");
+    let path = if parent_path.is_empty() {
+        name
+    } else {
+        format!("{parent_path}/{name}")
+    };
+    matches.extend(field_matches(
+        node.id,
+        &path,
+        SearchField::Name,
+        &node.name,
+        pattern,
+    ));
+    let text = remove_html_tags(&node.content);
+    matches.extend(field_matches(
+        node.id,
+        &path,
+        SearchField::Content,
+        &text,
+        pattern,
+    ));
+    for child in &node.children {
+        search_node_recursive(child, &path, pattern, matches);
+    }
+}
+
+/// Matches of the pattern in the name or the text (without HTML tags) of a node
+pub(crate) fn field_matches<'a>(
+    node_id: u32,
+    path: &'a str,
+    field: SearchField,
+    text: &'a str,
+    pattern: &'a Regex,
+) -> impl Iterator<Item = SearchMatch> + 'a {
+    pattern
+        .find_iter(text)
+        .filter(|m| !m.is_empty())
+        .enumerate()
+        .map(move |(occurrence, m)| SearchMatch {
+            node_id,
+            path: path.to_string(),
+            field,
+            occurrence,
+            snippet: snippet(text, m.start(), m.end()),
+        })
+}
+
+/// Text around the match, whitespace (including line breaks) is collapsed
+pub fn snippet(text: &str, start: usize, end: usize) -> String {
+    let mut from = start.saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(from) {
+        from -= 1;
+    }
+    let mut to = (end + SNIPPET_CONTEXT).min(text.len());
+    while !text.is_char_boundary(to) {
+        to += 1;
+    }
+    format!(
+        "{}{}{}",
+        if from > 0 { "…" } else { "" },
+        text[from..to]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+        if to < text.len() { "…" } else { "" }
+    )
+}
+
+/// Tags (`true`) and texts between them (`false`) of a part of HTML
+fn html_parts(html: &str) -> Vec<(bool, &str)> {
+    let mut parts = Vec::new();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let is_tag = rest.starts_with('<')
+            && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || "/!?".contains(c));
+        let end = if is_tag && rest.starts_with("<!--") {
+            rest.find("-->").map_or(rest.len(), |i| i + 3)
+        } else if is_tag {
+            rest.find('>').map_or(rest.len(), |i| i + 1)
+        } else {
+            let first = rest.chars().next().unwrap().len_utf8();
+            rest[first..].find('<').map_or(rest.len(), |i| i + first)
+        };
+        parts.push((is_tag, &rest[..end]));
+        rest = &rest[end..];
+    }
+    parts
+}
+
+/// Highlights a match in HTML and puts an anchor before it. Matches are counted in the
+/// text of the HTML like in search, texts of a match that spans several elements are
+/// highlighted separately
+pub fn highlight_html(html: &str, pattern: &Regex, occurrence: usize, anchor: &str) -> String {
+    let text = html_text(html);
+    let Some(m) = pattern
+        .find_iter(&text.text)
+        .filter(|m| !m.is_empty())
+        .nth(occurrence)
+    else {
+        return html.to_string();
+    };
+    let range = text.html_range(m.range());
+    let highlighted = html_parts(&html[range.clone()])
+        .into_iter()
+        .map(|(is_tag, part)| {
+            if is_tag {
+                part.to_string()
+            } else {
+                format!("<font color=\"#d00000\"><b>{part}</b></font>")
+            }
+        })
+        .collect::<String>();
+    format!(
+        "{}<a name=\"{anchor}\"></a>{highlighted}{}",
+        &html[..range.start],
+        &html[range.end..]
+    )
+}
+
+/// Change of a match made by find and replace
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pub node_id: u32,
+    /// Path of the node (Root/Node)
+    pub path: String,
+    pub field: SearchField,
+    /// Byte range of the match in the name or in the HTML content
+    pub range: Range<usize>,
+    /// The name or the HTML in the range
+    pub old: String,
+    /// What replaces the range, tags of HTML in it are kept
+    pub new: String,
+    /// The change in the text with the text around it: `…before [old → new] after…`
+    pub preview: String,
+}
+
+/// Finds matches to replace in names and text of the content of the node and its
+/// children, the same matches as search finds. `$1` and `$name` in the replacement are
+/// capture groups if `expand` is set
+pub fn find_replacements(
+    node: &Node,
+    pattern: &Regex,
+    replacement: &str,
+    expand: bool,
+    fields: &[SearchField],
+) -> Vec<Replacement> {
+    let mut replacements = Vec::new();
+    let mut find = |node: &Node, path: &str, field, text: &str, html: Option<&HtmlText>| {
+        for captures in pattern.captures_iter(text) {
+            let m = captures.get(0).unwrap();
+            if m.is_empty() {
+                continue;
+            }
+            let mut new_text = String::new();
+            if expand {
+                captures.expand(replacement, &mut new_text);
+            } else {
+                new_text.push_str(replacement);
+            }
+            let (range, old, new) = match html {
+                Some(html) => {
+                    let range = html.html_range(m.range());
+                    let old = &node.content[range.clone()];
+                    (range, old, replaced_html(old, &escape_xml(&new_text)))
+                }
+                None => (m.range(), m.as_str(), new_text.clone()),
+            };
+            let (before, after) = (&text[..m.start()], &text[m.end()..]);
+            let mut from = before.len().saturating_sub(SNIPPET_CONTEXT);
+            while !before.is_char_boundary(from) {
+                from -= 1;
+            }
+            let mut to = SNIPPET_CONTEXT.min(after.len());
+            while !after.is_char_boundary(to) {
+                to += 1;
+            }
+            let collapse = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+            replacements.push(Replacement {
+                node_id: node.id,
+                path: path.to_string(),
+                field,
+                range,
+                old: old.to_string(),
+                new,
+                preview: format!(
+                    "{}{} [{} → {new_text}] {}{}",
+                    if from > 0 { "…" } else { "" },
+                    collapse(&before[from..]),
+                    m.as_str(),
+                    collapse(&after[..to]),
+                    if to < after.len() { "…" } else { "" }
+                ),
+            });
+        }
+    };
+    let mut nodes = vec![(node, node.name.replace('/', "\\/"))];
+    while let Some((node, path)) = nodes.pop() {
+        if fields.contains(&SearchField::Name) {
+            find(node, &path, SearchField::Name, &node.name, None);
+        }
+        if fields.contains(&SearchField::Content) {
+            let html = html_text(&node.content);
+            find(node, &path, SearchField::Content, &html.text, Some(&html));
+        }
+        // Children are pushed in reverse to be visited in the order of the tree
+        for child in node.children.iter().rev() {
+            nodes.push((child, format!("{path}/{}", child.name.replace('/', "\\/"))));
+        }
+    }
+    replacements
+}
+
+/// The HTML of a match with its first text replaced and the other texts removed, so
+/// elements the match spans stay balanced
+fn replaced_html(old: &str, new: &str) -> String {
+    let mut replaced = false;
+    let mut html = String::new();
+    for (is_tag, part) in html_parts(old) {
+        if is_tag {
+            html.push_str(part);
+        } else if !replaced {
+            html.push_str(new);
+            replaced = true;
+        }
+    }
+    if !replaced {
+        html.insert_str(0, new);
+    }
+    html
+}
+
+/// Modification that applies the replacements, `None` if there are none.
+/// Replacements of a field must not overlap
+pub fn replace_modification(
+    document: &Document,
+    replacements: &[Replacement],
+) -> Option<DocumentModification> {
+    let mut by_node = BTreeMap::<u32, Vec<&Replacement>>::new();
+    for replacement in replacements {
+        by_node
+            .entry(replacement.node_id)
+            .or_default()
+            .push(replacement);
+    }
+    let modifications = by_node
+        .into_iter()
+        .filter_map(|(id, mut replacements)| {
+            let mut node = document.clone().get_node(id)?;
+            // Replacing from the end keeps ranges of the other matches valid
+            replacements.sort_by_key(|r| std::cmp::Reverse(r.range.start));
+            for replacement in replacements {
+                let text = match replacement.field {
+                    SearchField::Name => &mut node.name,
+                    SearchField::Content => &mut node.content,
+                };
+                if text.get(replacement.range.clone()) == Some(replacement.old.as_str()) {
+                    text.replace_range(replacement.range.clone(), &replacement.new);
+                }
+            }
+            Some(DocumentModification::EditNode(id, node))
+        })
+        .collect::<Vec<_>>();
+    (!modifications.is_empty()).then_some(DocumentModification::Batch(modifications))
+}
+
+/// Score of a matched character, with bonuses for matches at the start of a word, that
+/// follow the previous match or are in the node name (after the last `/`)
+const FUZZY_MATCH: i64 = 16;
+const FUZZY_WORD_START: i64 = 24;
+const FUZZY_CONSECUTIVE: i64 = 16;
+const FUZZY_IN_NAME: i64 = 8;
+/// Bonus of the most recently visited node, each older one gets less
+const FUZZY_RECENT: i64 = 64;
+
+/// Score of the query matched as a subsequence of the text ignoring case, `None` if the
+/// text doesn't contain all characters of the query in order. Skipped characters and
+/// long texts lower the score
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let query = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let lower = text
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        return Some(0);
+    }
+    let name_start = text.iter().rposition(|c| *c == '/').map_or(0, |i| i + 1);
+    let bonus = |j: usize| {
+        let word_start = j == 0
+            || matches!(text[j - 1], '/' | ' ' | '_' | '-' | '.')
+            || (text[j].is_uppercase() && text[j - 1].is_lowercase());
+        FUZZY_MATCH
+            + if word_start { FUZZY_WORD_START } else { 0 }
+            + if j >= name_start { FUZZY_IN_NAME } else { 0 }
+    };
+    // Best score of the query so far with its last character matched at each position
+    let mut previous = vec![None::<i64>; text.len()];
+    for (i, q) in query.iter().enumerate() {
+        let mut current = vec![None; text.len()];
+        // Best score of the previous row plus its position, a gap costs 1 per character
+        let mut best_before = None::<i64>;
+        for j in 0..text.len() {
+            if j > 0 && i > 0 {
+                if let Some(score) = previous[j - 1] {
+                    best_before = best_before.max(Some(score + j as i64 - 1));
+                }
+            }
+            if lower[j] != *q {
+                continue;
+            }
+            current[j] = if i == 0 {
+                Some(bonus(j) - j as i64)
+            } else {
+                let consecutive = (j > 0)
+                    .then(|| previous[j - 1])
+                    .flatten()
+                    .map(|score| score + FUZZY_CONSECUTIVE);
+                let gap = best_before.map(|score| score - j as i64 + 1);
+                consecutive.max(gap).map(|score| score + bonus(j))
+            };
+        }
+        previous = current;
+    }
+    previous
+        .into_iter()
+        .flatten()
+        .max()
+        .map(|score| score - text.len() as i64 / 4)
+}
+
+/// Paths of nodes (as in `node_to_tree_vec`) that match the query, best first.
+/// Recently visited nodes (most recent first) rank higher, with an empty query they are
+/// listed first and the other nodes follow in the order of the tree
+pub fn fuzzy_find_nodes(
+    paths: &[(String, u32)],
+    query: &str,
+    recent_nodes: &[u32],
+) -> Vec<(String, u32)> {
+    let mut scored = paths
+        .iter()
+        .enumerate()
+        .filter_map(|(index, (path, id))| {
+            let score = fuzzy_score(query, path)?;
+            let recency = recent_nodes
+                .iter()
+                .position(|recent| recent == id)
+                .map_or(0, |position| FUZZY_RECENT / (position as i64 + 1));
+            Some((score + recency, index))
+        })
+        .collect::<Vec<_>>();
+    scored.sort_by_key(|(score, index)| (std::cmp::Reverse(*score), *index));
+    scored
+        .into_iter()
+        .map(|(_, index)| paths[index].clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(query: &str) -> Regex {
+        search_pattern(query, SearchOptions::default()).unwrap()
+    }
+
+    fn node(content: &str) -> Node {
+        Node::new("Node".to_string(), content.to_string(), Vec::new())
+    }
+
+    fn highlighted(html: &str) -> String {
+        html.replace("<a name=\"hit\"></a>", "[")
+            .replace("<font color=\"#d00000\"><b>", "{")
+            .replace("</b></font>", "}")
+    }
+
+    #[test]
+    fn finds_matches_in_text_without_tags() {
+        let node = node("<p>foo<b>bar</b> and foobar</p><p>FOOBAR</p>");
+        let matches = search_node(&node, &pattern("foobar"));
+        assert_eq!(matches.len(), 3);
+        assert!(matches.iter().all(|m| m.field == SearchField::Content));
+        assert_eq!(
+            matches.iter().map(|m| m.occurrence).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        assert_eq!(search_node(&node, &pattern("node")).len(), 1);
+    }
+
+    #[test]
+    fn paths_escape_slashes_in_names() {
+        let child = Node::new("b/c".to_string(), "<p>text</p>".to_string(), Vec::new());
+        let root = Node::new("a/".to_string(), String::new(), vec![child]);
+        let matches = search_node(&root, &pattern("text"));
+        assert_eq!(matches[0].path, "a\\//b\\/c");
+        let replacements =
+            find_replacements(&root, &pattern("text"), "x", false, &[SearchField::Content]);
+        assert_eq!(replacements[0].path, "a\\//b\\/c");
+    }
+
+    #[test]
+    fn highlights_matches_that_span_tags() {
+        let html = "<p>foo<b>bar</b> and foobar</p>";
+        let pattern = pattern("foobar");
+        assert_eq!(search_node(&node(html), &pattern).len(), 2);
+        assert_eq!(
+            highlighted(&highlight_html(html, &pattern, 0, "hit")),
+            "<p>[{foo}<b>{bar}</b> and foobar</p>"
+        );
+        assert_eq!(
+            highlighted(&highlight_html(html, &pattern, 1, "hit")),
+            "<p>foo<b>bar</b> and [{foobar}</p>"
+        );
+        assert_eq!(highlight_html(html, &pattern, 2, "hit"), html);
+    }
+
+    #[test]
+    fn highlights_matches_with_entities() {
+        let html = "<p>AT&amp;T rocks</p>";
+        let pattern = pattern("AT&T");
+        assert_eq!(search_node(&node(html), &pattern).len(), 1);
+        assert_eq!(
+            highlighted(&highlight_html(html, &pattern, 0, "hit")),
+            "<p>[{AT&amp;T} rocks</p>"
+        );
+    }
+
+    #[test]
+    fn whole_word_and_case_options() {
+        let options = SearchOptions {
+            case_sensitive: true,
+            whole_word: true,
+            regex: false,
+        };
+        let pattern = search_pattern("Bar", options).unwrap();
+        assert_eq!(search_node(&node("Bar bar Barn Bar."), &pattern).len(), 2);
+        assert!(search_pattern("", options).is_err());
+    }
+
+    #[test]
+    fn replaces_the_matches_search_finds() {
+        let mut root = node("<p>foo<b>bar</b> and foobar, AT&amp;T</p>");
+        root.children.push(node("<p>FooBar</p>"));
+        let document = Document {
+            root_node: root,
+            resources: BTreeMap::new(),
+        };
+        let pattern = pattern("foobar");
+        let fields = [SearchField::Name, SearchField::Content];
+        let replacements = find_replacements(&document.root_node, &pattern, "x<y", false, &fields);
+        assert_eq!(
+            replacements.len(),
+            search_node(&document.root_node, &pattern).len()
+        );
+        assert_eq!(replacements.len(), 3);
+        assert!(replacements[0].preview.contains("[foobar → x<y]"));
+
+        let modification = replace_modification(&document, &replacements).unwrap();
+        let replaced = crate::utils::modify_document(document, modification);
+        assert_eq!(
+            replaced.root_node.content,
+            "<p>x&lt;y<b></b> and x&lt;y, AT&amp;T</p>"
+        );
+        assert_eq!(replaced.root_node.children[0].content, "<p>x&lt;y</p>");
+        assert!(search_node(&replaced.root_node, &pattern).is_empty());
+    }
+
+    #[test]
+    fn replaces_matches_with_entities_and_groups() {
+        let document = Document {
+            root_node: node("<p>AT&amp;T and AT&amp;T</p>"),
+            resources: BTreeMap::new(),
+        };
+        let options = SearchOptions {
+            regex: true,
+            ..Default::default()
+        };
+        let pattern = search_pattern("(AT)&(T)", options).unwrap();
+        let replacements = find_replacements(
+            &document.root_node,
+            &pattern,
+            "$2&$1",
+            true,
+            &[SearchField::Content],
+        );
+        assert_eq!(replacements.len(), 2);
+        let modification = replace_modification(&document, &replacements).unwrap();
+        let replaced = crate::utils::modify_document(document, modification);
+        assert_eq!(replaced.root_node.content, "<p>T&amp;AT and T&amp;AT</p>");
+    }
+
+    #[test]
+    fn fuzzy_score_matches_subsequences_ignoring_case() {
+        assert_eq!(fuzzy_score("", "Root"), Some(0));
+        assert_eq!(fuzzy_score(" ", "Root"), Some(0));
+        assert!(fuzzy_score("rt", "ROOT").is_some());
+        assert!(fuzzy_score("r o", "Root").is_some());
+        assert_eq!(fuzzy_score("tr", "Root"), None);
+        assert_eq!(fuzzy_score("rootx", "Root"), None);
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_consecutive_word_starts_in_names() {
+        let score = |query, text| fuzzy_score(query, text).unwrap();
+        assert!(score("abc", "xabcx") > score("abc", "axbxc"));
+        assert!(score("qo", "Quick open") > score("qo", "aquarium oboe"));
+        assert!(score("qo", "QuickOpen") > score("qo", "Quickopen"));
+        assert!(score("note", "Other/Notes") > score("note", "Notes/Other"));
+        assert!(score("ab", "ab") > score("ab", "ab cdefghijklmnop"));
+    }
+
+    #[test]
+    fn fuzzy_find_ranks_recent_nodes_higher() {
+        let paths = [
+            ("Root".to_string(), 1),
+            ("Root/Notes".to_string(), 2),
+            ("Root/Other".to_string(), 3),
+            ("Root/Other/Notes".to_string(), 4),
+        ];
+        let ids = |query, recent: &[u32]| {
+            fuzzy_find_nodes(&paths, query, recent)
+                .into_iter()
+                .map(|(_, id)| id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids("", &[3]), [3, 1, 2, 4]);
+        assert_eq!(ids("notes", &[]), [2, 4]);
+        assert_eq!(ids("notes", &[4]), [4, 2]);
+        assert_eq!(ids("xyz", &[1]), Vec::<u32>::new());
+    }
+}
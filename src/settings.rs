@@ -1,5 +1,6 @@
 use crate::{
     app::Application,
+    formats::{ExportFormat, ExportOptions},
     types::{Message, Theme},
     ui::SettingsInterface,
 };
@@ -14,20 +15,27 @@ use std::{collections::BTreeMap, fs, path::PathBuf};
 const SETTINGS_PATH: &str = "./Rustree.ron";
 const DEFAULT_EDITOR_TEXT_SIZE: i32 = 20;
 const DEFAULT_THEME: Theme = Theme::Light;
+const DEFAULT_RECENT_DOCUMENTS_LENGTH: usize = 10;
 
 /// UI element type
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Deserialize, Serialize)]
 pub enum UIElementType {
+    NewFile,
     OpenFile,
     SaveFile,
     SaveAsFile,
     QuitApp,
+    CloseTab,
 
     Copy,
     Paste,
     Cut,
     Undo,
     Redo,
+    Find,
+    Replace,
+    QuickOpen,
+    CompleteReference,
 
     DeleteNodeBtn,
     CreateNodeBtn,
@@ -56,6 +64,55 @@ struct SettingsDocument {
     shortcuts: BTreeMap<UIElementType, i32>,
     theme: Theme,
     editor_text_size: i32,
+    /// The last export options of formats
+    #[serde(default)]
+    export_options: BTreeMap<ExportFormat, ExportOptions>,
+    /// Recently opened documents, the last one first
+    #[serde(default)]
+    recent_documents: Vec<PathBuf>,
+    #[serde(default = "default_recent_documents_length")]
+    recent_documents_length: usize,
+    #[serde(default = "default_restore_session")]
+    restore_session: bool,
+    /// The session saved when Rustree was closed
+    #[serde(default)]
+    session: Option<Session>,
+}
+
+fn default_recent_documents_length() -> usize {
+    DEFAULT_RECENT_DOCUMENTS_LENGTH
+}
+
+fn default_restore_session() -> bool {
+    true
+}
+
+/// Open documents and the layout of the window restored at start
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Session {
+    /// Documents of tabs, documents that aren't saved to files are not restored
+    pub documents: Vec<SessionDocument>,
+    /// Index of the selected document
+    pub current_document: usize,
+    /// Index of the selected tab of the window (Document or Resources)
+    pub main_tab: i32,
+    /// Position and size of the window
+    pub window: Option<(i32, i32, i32, i32)>,
+    /// Width of the document tree, the node view takes the rest of the window
+    pub tree_width: Option<i32>,
+}
+
+/// Document open in a tab
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SessionDocument {
+    pub path: PathBuf,
+    /// Path of the selected node (Root/Node)
+    #[serde(default)]
+    pub selected_node: Option<String>,
+    /// Paths of nodes with hidden children
+    #[serde(default)]
+    pub collapsed_nodes: Vec<String>,
 }
 
 impl SettingsDocument {
@@ -64,6 +121,11 @@ impl SettingsDocument {
             shortcuts: BTreeMap::new(),
             theme: DEFAULT_THEME,
             editor_text_size: DEFAULT_EDITOR_TEXT_SIZE,
+            export_options: BTreeMap::new(),
+            recent_documents: Vec::new(),
+            recent_documents_length: DEFAULT_RECENT_DOCUMENTS_LENGTH,
+            restore_session: default_restore_session(),
+            session: None,
         }
     }
 
@@ -88,6 +150,11 @@ fn settings_document_to_settings(doc: SettingsDocument) -> MainSettings {
         elements_shortcuts_browser_indexes: Vec::new(),
         current_shortcut_ui_element_type: None,
         editor_text_size: doc.editor_text_size,
+        export_options: doc.export_options,
+        recent_documents: doc.recent_documents,
+        recent_documents_length: doc.recent_documents_length,
+        restore_session: doc.restore_session,
+        session: doc.session,
         shortcuts_manager: ShortcutsManager {
             shortcuts: doc
                 .shortcuts
@@ -109,6 +176,11 @@ fn settings_to_document_settings(settings: MainSettings) -> SettingsDocument {
             .collect::<BTreeMap<_, _>>(),
         theme: settings.theme,
         editor_text_size: settings.editor_text_size,
+        export_options: settings.export_options,
+        recent_documents: settings.recent_documents,
+        recent_documents_length: settings.recent_documents_length,
+        restore_session: settings.restore_session,
+        session: settings.session,
     }
 }
 
@@ -120,6 +192,17 @@ pub struct MainSettings {
     pub elements_shortcuts_browser_indexes: Vec<(i32, UIElementType)>,
     pub current_shortcut_ui_element_type: Option<UIElementType>,
     pub editor_text_size: i32,
+    pub export_options: BTreeMap<ExportFormat, ExportOptions>,
+    pub recent_documents: Vec<PathBuf>,
+    pub recent_documents_length: usize,
+    pub restore_session: bool,
+    pub session: Option<Session>,
+}
+
+impl Default for MainSettings {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MainSettings {
@@ -152,11 +235,30 @@ impl MainSettings {
             elements_shortcuts_browser_indexes: Vec::new(),
             current_shortcut_ui_element_type: None,
             editor_text_size: DEFAULT_EDITOR_TEXT_SIZE,
+            export_options: BTreeMap::new(),
+            recent_documents: Vec::new(),
+            recent_documents_length: DEFAULT_RECENT_DOCUMENTS_LENGTH,
+            restore_session: default_restore_session(),
+            session: None,
         }
     }
     pub fn write(self) {
         settings_to_document_settings(self).write().unwrap();
     }
+
+    /// Moves the document to the top of recent documents
+    pub fn add_recent_document(&mut self, path: PathBuf) {
+        let path = path.canonicalize().unwrap_or(path);
+        self.recent_documents.retain(|p| *p != path);
+        self.recent_documents.insert(0, path);
+        self.prune_recent_documents();
+    }
+
+    /// Removes recent documents that don't exist anymore and the ones over the length
+    pub fn prune_recent_documents(&mut self) {
+        self.recent_documents.retain(|path| path.exists());
+        self.recent_documents.truncate(self.recent_documents_length);
+    }
 }
 
 pub fn update_shortcuts_browser(
@@ -195,6 +297,14 @@ pub fn show(app: &mut Application) {
             .set_value(settings.editor_text_size.into());
         ui.editor_text_size
             .emit(main_sender, Message::UpdateSettings);
+        ui.recent_documents_length.set_range(0., 50.);
+        ui.recent_documents_length
+            .set_value(settings.recent_documents_length as f64);
+        ui.recent_documents_length
+            .emit(main_sender, Message::UpdateSettings);
+        ui.restore_session.set_checked(settings.restore_session);
+        ui.restore_session
+            .emit(main_sender, Message::UpdateSettings);
         ui.reset_shortcuts
             .emit(main_sender, Message::ResetShortcuts);
 
@@ -255,15 +365,21 @@ impl ShortcutsManager {
     /// Get default shortcuts
     pub fn default_shortcuts() -> BTreeMap<UIElementType, Shortcut> {
         [
+            (UIElementType::NewFile, Shortcut::Command | 'n'),
             (UIElementType::OpenFile, Shortcut::Command | 'o'),
             (UIElementType::SaveFile, Shortcut::Command | 's'),
             (UIElementType::SaveAsFile, Shortcut::Command | 'S'),
             (UIElementType::QuitApp, Shortcut::Command | 'q'),
+            (UIElementType::CloseTab, Shortcut::Command | 'w'),
             (UIElementType::Copy, Shortcut::Command | 'c'),
             (UIElementType::Paste, Shortcut::Command | 'v'),
             (UIElementType::Cut, Shortcut::Command | 'x'),
             (UIElementType::Undo, Shortcut::Command | 'z'),
             (UIElementType::Redo, Shortcut::Command | 'y'),
+            (UIElementType::Find, Shortcut::Command | 'f'),
+            (UIElementType::Replace, Shortcut::Command | 'h'),
+            (UIElementType::QuickOpen, Shortcut::Command | 'p'),
+            (UIElementType::CompleteReference, Shortcut::Ctrl | ' '),
             (UIElementType::DeleteNodeBtn, Shortcut::None | Key::Delete),
             (UIElementType::CreateNodeBtn, Shortcut::None | Key::Insert),
             (UIElementType::DelResBtn, Shortcut::None | Key::Delete),
@@ -0,0 +1,558 @@
+use crate::{
+    search::snippet,
+    types::{Document, Node},
+    utils::{
+        find_node_ref, find_parent_ref, modify_document, node_to_tree_vec, DocumentModification,
+    },
+};
+use regex::{Captures, Regex};
+use std::{collections::HashMap, ops::Range};
+
+pub fn process_node_path(path: String, document: Document, current_node: Node) -> u32 {
+    resolve_node_path(&path, &document, current_node.id)
+}
+
+/// Finds the node a reference in the node with `current_id` points to, `0` if there is no
+/// such node. References are:
+/// - `path:Root/Node`, names from the root separated by `/` (escaped as `\/` in names)
+/// - `path:../Node` and `path:./Child`, paths relative to the current node
+/// - `sibling:Name`, `child:Name` and `parent:`
+/// - `id:1053420121`, which doesn't break when nodes are renamed or moved
+pub fn resolve_node_path(path: &str, document: &Document, current_id: u32) -> u32 {
+    let Some((kind, arg)) = path.split_once(':') else {
+        return 0;
+    };
+    let arg = arg.trim();
+    let root = &document.root_node;
+    let node = match kind.trim().to_lowercase().as_str() {
+        "id" => arg.parse().ok().and_then(|id| find_node_ref(root, id)),
+        "parent" => find_parent_ref(root, current_id),
+        "sibling" => find_parent_ref(root, current_id)
+            .and_then(|parent| parent.children.iter().find(|n| n.name == arg)),
+        "child" => find_node_ref(root, current_id)
+            .and_then(|node| node.children.iter().find(|n| n.name == arg)),
+        "path" if is_relative_path(arg) => find_node_ref(root, current_id)
+            .and_then(|node| follow_path(root, node, &split_path(arg))),
+        "path" => match split_path(arg).split_first() {
+            Some((name, names)) if *name == root.name => follow_path(root, root, names),
+            _ => None,
+        },
+        _ => None,
+    };
+    node.map_or(0, |node| node.id)
+}
+
+/// Splits the path at `/` that isn't escaped as `\/` and unescapes the names
+fn split_path(path: &str) -> Vec<String> {
+    let mut names = vec![String::new()];
+    let mut chars = path.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'/') => {
+                chars.next();
+                names.last_mut().unwrap().push('/');
+            }
+            '/' => names.push(String::new()),
+            c => names.last_mut().unwrap().push(c),
+        }
+    }
+    names
+}
+
+/// A path starting with `.` or `..` is relative to the node with the reference
+fn is_relative_path(path: &str) -> bool {
+    matches!(
+        split_path(path).first().map(String::as_str),
+        Some(".") | Some("..")
+    )
+}
+
+/// Follows the names from the node, `.` is the node and `..` its parent. Siblings
+/// with the same name are tried in order, like paths of `node_to_tree_vec`
+fn follow_path<'a>(root: &'a Node, node: &'a Node, names: &[String]) -> Option<&'a Node> {
+    let Some((name, names)) = names.split_first() else {
+        return Some(node);
+    };
+    match name.as_str() {
+        "." => follow_path(root, node, names),
+        ".." => follow_path(root, find_parent_ref(root, node.id)?, names),
+        _ => node
+            .children
+            .iter()
+            .filter(|child| child.name == *name)
+            .find_map(|child| follow_path(root, child, names)),
+    }
+}
+
+/// Nodes from the root to the node with id
+fn node_chain(node: &Node, id: u32) -> Option<Vec<&Node>> {
+    if node.id == id {
+        return Some(vec![node]);
+    }
+    node.children.iter().find_map(|child| {
+        let mut chain = node_chain(child, id)?;
+        chain.insert(0, node);
+        Some(chain)
+    })
+}
+
+/// Path from the node with id `from` to the node with id `to` through their closest
+/// common parent, like `../Node/Child`
+fn relative_path(root: &Node, from: u32, to: u32) -> Option<String> {
+    let (from, to) = (node_chain(root, from)?, node_chain(root, to)?);
+    let common = from
+        .iter()
+        .zip(&to)
+        .take_while(|(a, b)| a.id == b.id)
+        .count();
+    let mut names = vec!["..".to_string(); from.len() - common];
+    if names.is_empty() {
+        names.push(".".to_string());
+    }
+    names.extend(
+        to[common..]
+            .iter()
+            .map(|node| node.name.replace('/', "\\/")),
+    );
+    Some(names.join("/"))
+}
+
+/// Node that references another node
+#[derive(Debug, Clone)]
+pub struct Backlink {
+    pub node_id: u32,
+    /// Path of the node as in `node_to_tree_vec`
+    pub path: String,
+    /// The reference with the text around it on one line
+    pub snippet: String,
+}
+
+/// Finds references in the text: their byte ranges and arguments
+pub fn find_references(text: &str) -> Vec<(Range<usize>, String)> {
+    Regex::new(r#"reference!\("([^"]*)"\)"#)
+        .unwrap()
+        .captures_iter(text)
+        .map(|captures| (captures.get(0).unwrap().range(), captures[1].to_string()))
+        .collect()
+}
+
+/// Creates the reference macro with the argument. A `"` in it would end the macro, so
+/// such references point to the node by its id instead
+pub fn reference_macro(reference: &str, id: u32) -> String {
+    if reference.contains('"') {
+        format!("reference!(\"id:{id}\")")
+    } else {
+        format!("reference!(\"{reference}\")")
+    }
+}
+
+/// Text of the HTML without tags, references in attributes (`href`) are kept in place
+/// of their tags
+fn references_text(html: &str) -> String {
+    Regex::new(r"<[^>]*>")
+        .unwrap()
+        .replace_all(html, |captures: &Captures| {
+            let tag = &captures[0];
+            let references = find_references(tag)
+                .into_iter()
+                .map(|(range, _)| &tag[range])
+                .collect::<Vec<_>>();
+            format!(" {} ", references.join(" "))
+        })
+        .to_string()
+}
+
+/// Finds nodes whose content references the node with id, in the order of the tree.
+/// A node is listed for each of its references
+pub fn find_backlinks(document: &Document, id: u32) -> Vec<Backlink> {
+    let mut backlinks = Vec::new();
+    let root = &document.root_node;
+    let mut nodes = vec![(root, root.name.replace('/', "\\/"))];
+    while let Some((node, path)) = nodes.pop() {
+        // Children are pushed in reverse to be visited in the order of the tree
+        for child in node.children.iter().rev() {
+            nodes.push((child, format!("{path}/{}", child.name.replace('/', "\\/"))));
+        }
+        if !node.content.contains("reference!(") {
+            continue;
+        }
+        let text = references_text(&node.content);
+        for (range, reference) in find_references(&text) {
+            if resolve_node_path(&reference, document, node.id) == id {
+                backlinks.push(Backlink {
+                    node_id: node.id,
+                    path: path.clone(),
+                    snippet: snippet(&text, range.start, range.end),
+                });
+            }
+        }
+    }
+    backlinks
+}
+
+/// Edits of nodes of `after` whose references point to other nodes than in `before`,
+/// because the nodes were renamed or moved. The references are rewritten to point to the
+/// same nodes again. `ids` maps ids of nodes of `before` to ids in `after` (pasted nodes
+/// get new ids), nodes that aren't in it keep their ids
+pub fn rewrite_references(
+    before: &Document,
+    after: &Document,
+    ids: &HashMap<u32, u32>,
+) -> Vec<DocumentModification> {
+    rewrite_subtree_references(before, after, ids, &after.root_node, true)
+}
+
+/// Edits of the node with `pasted_id` in `after` and its children, copied from `before`
+/// with new ids (`ids` maps the old ones to them), whose references point to other nodes
+/// than in `before`. References to the copied nodes are rewritten to point to the copies
+pub fn rewrite_pasted_references(
+    before: &Document,
+    after: &Document,
+    ids: &HashMap<u32, u32>,
+    pasted_id: u32,
+) -> Vec<DocumentModification> {
+    match find_node_ref(&after.root_node, pasted_id) {
+        Some(pasted) => rewrite_subtree_references(before, after, ids, pasted, false),
+        None => Vec::new(),
+    }
+}
+
+/// The node with id as a document (see `Document::node_document`), references between its
+/// nodes are rewritten to point to them in the new document
+pub fn extract_document(document: &Document, id: u32) -> Option<Document> {
+    let node = document.clone().get_node(id)?;
+    let extracted = document.node_document(node);
+    let modifications = rewrite_references(document, &extracted, &HashMap::new());
+    Some(modify_document(
+        extracted,
+        DocumentModification::Batch(modifications),
+    ))
+}
+
+/// Rewrites references of the node and its children. Nodes that aren't in `ids` keep
+/// their ids if `same_ids` is set, otherwise they aren't in `after`
+fn rewrite_subtree_references(
+    before: &Document,
+    after: &Document,
+    ids: &HashMap<u32, u32>,
+    root: &Node,
+    same_ids: bool,
+) -> Vec<DocumentModification> {
+    let paths = node_to_tree_vec(after.root_node.clone(), Vec::new(), None)
+        .into_iter()
+        .map(|(path, id)| (id, path))
+        .collect::<HashMap<_, _>>();
+    let before_ids = ids
+        .iter()
+        .map(|(before_id, after_id)| (*after_id, *before_id))
+        .collect::<HashMap<_, _>>();
+    let mut modifications = Vec::new();
+    let mut nodes = vec![root];
+    while let Some(node) = nodes.pop() {
+        nodes.extend(node.children.iter().rev());
+        if !node.content.contains("reference!(") {
+            continue;
+        }
+        let Some(before_id) = before_ids
+            .get(&node.id)
+            .copied()
+            .or(same_ids.then_some(node.id))
+        else {
+            continue;
+        };
+        // References of new nodes didn't point anywhere before
+        if find_node_ref(&before.root_node, before_id).is_none() {
+            continue;
+        }
+        let mut content = node.content.clone();
+        // Replaced from the end, so ranges of earlier references stay valid
+        for (range, reference) in find_references(&node.content).into_iter().rev() {
+            let target = resolve_node_path(&reference, before, before_id);
+            if target == 0 {
+                continue;
+            }
+            let Some(target) = ids.get(&target).copied().or(same_ids.then_some(target)) else {
+                continue;
+            };
+            let (Some(path), Some(target_node)) =
+                (paths.get(&target), find_node_ref(&after.root_node, target))
+            else {
+                continue;
+            };
+            if resolve_node_path(&reference, after, node.id) == target {
+                continue;
+            }
+            // The reference keeps its kind if it can still point to the node
+            let parent = |id| find_parent_ref(&after.root_node, id).map(|parent| parent.id);
+            let (kind, arg) = reference.split_once(':').unwrap_or_default();
+            let reference = match kind.trim().to_lowercase().as_str() {
+                "id" => format!("id:{target}"),
+                "sibling" if parent(node.id) == parent(target) => {
+                    format!("sibling:{}", target_node.name)
+                }
+                "child" if parent(target) == Some(node.id) => {
+                    format!("child:{}", target_node.name)
+                }
+                "path" if is_relative_path(arg.trim()) => {
+                    match relative_path(&after.root_node, node.id, target) {
+                        Some(path) => format!("path:{path}"),
+                        None => continue,
+                    }
+                }
+                _ => format!("path:{path}"),
+            };
+            content.replace_range(range, &reference_macro(&reference, target));
+        }
+        if content != node.content {
+            modifications.push(DocumentModification::EditNode(
+                node.id,
+                Node {
+                    content,
+                    ..node.clone()
+                },
+            ));
+        }
+    }
+    modifications
+}
+
+/// The modification with the edits of references it would break (see
+/// `rewrite_references`), as one batch
+pub fn with_rewritten_references(
+    document: &Document,
+    modification: DocumentModification,
+) -> DocumentModification {
+    let after = modify_document(document.clone(), modification.clone());
+    let rewrites = rewrite_references(document, &after, &HashMap::new());
+    if rewrites.is_empty() {
+        return modification;
+    }
+    let mut modifications = match modification {
+        DocumentModification::Batch(modifications) => modifications,
+        modification => vec![modification],
+    };
+    modifications.extend(rewrites);
+    DocumentModification::Batch(modifications)
+}
+
+/// Finds names of the resources used in the text
+pub fn find_resources(text: &str) -> Vec<String> {
+    Regex::new(r#"resource!\("([^"]*)"\)"#)
+        .unwrap()
+        .captures_iter(text)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+pub fn process_text(
+    text: String,
+    handle_res: impl Fn(&str) -> String,
+    handle_ref: impl Fn(&str) -> String,
+) -> String {
+    let res_regex = Regex::new(r#"resource!\("([^"]*)"\)"#).unwrap();
+    let ref_regex = Regex::new(r#"reference!\("([^"]*)"\)"#).unwrap();
+
+    ref_regex
+        .replace_all(
+            &res_regex.replace_all(&text, |captures: &Captures| handle_res(&captures[1])),
+            |captures: &Captures| handle_ref(&captures[1]),
+        )
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{map_node_ids, randomize_node};
+    use std::collections::BTreeMap;
+
+    fn node(id: u32, name: &str, content: &str, children: Vec<Node>) -> Node {
+        Node {
+            id,
+            ..Node::new(name.to_string(), content.to_string(), children)
+        }
+    }
+
+    fn reference(argument: &str) -> String {
+        format!("reference!(\"{argument}\")")
+    }
+
+    /// Root(1) with A(2) > A1(3) > A1x(4), B(5) and C(6), each with a reference
+    fn document() -> Document {
+        let a1 = node(
+            3,
+            "A1",
+            &reference("path:Root/A/A1/A1x"),
+            vec![node(4, "A1x", &reference("id:3"), Vec::new())],
+        );
+        Document {
+            root_node: node(
+                1,
+                "Root",
+                "",
+                vec![
+                    node(2, "A", &reference("path:Root/A/A1"), vec![a1]),
+                    node(5, "B", &reference("sibling:A"), Vec::new()),
+                    node(6, "C", &reference("id:4"), Vec::new()),
+                ],
+            ),
+            resources: BTreeMap::new(),
+        }
+    }
+
+    fn content(document: &Document, id: u32) -> String {
+        document.clone().get_node(id).unwrap().content
+    }
+
+    #[test]
+    fn splits_paths_at_unescaped_slashes() {
+        assert_eq!(split_path(r"Root/A\/x/B"), ["Root", "A/x", "B"]);
+        assert_eq!(split_path("Root"), ["Root"]);
+        assert!(is_relative_path("../B") && is_relative_path("./A1"));
+        assert!(!is_relative_path("Root/A") && !is_relative_path(r".\/A"));
+    }
+
+    #[test]
+    fn resolves_each_kind_of_reference() {
+        let document = document();
+        let resolve = |reference, current_id| resolve_node_path(reference, &document, current_id);
+        assert_eq!(resolve("id:3", 1), 3);
+        assert_eq!(resolve("path:Root/A/A1", 1), 3);
+        assert_eq!(resolve(" Path: Root/A/A1 ", 1), 3);
+        assert_eq!(resolve("path:../B", 2), 5);
+        assert_eq!(resolve("path:./A1/A1x", 2), 4);
+        assert_eq!(resolve("path:../../..", 4), 1);
+        assert_eq!(resolve("sibling:B", 2), 5);
+        assert_eq!(resolve("child:A1", 2), 3);
+        assert_eq!(resolve("parent:", 3), 2);
+        assert_eq!(resolve("parent:", 1), 0);
+        for missing in [
+            "id:99",
+            "path:Other/A",
+            "path:Root/X",
+            "sibling:A1",
+            "name:A",
+            "A",
+        ] {
+            assert_eq!(resolve(missing, 2), 0, "{missing}");
+        }
+    }
+
+    #[test]
+    fn resolves_escaped_names_and_same_named_siblings() {
+        let document = Document {
+            root_node: node(
+                1,
+                "Root",
+                "",
+                vec![
+                    node(2, "X", "", Vec::new()),
+                    node(3, "X", "", vec![node(4, "Y", "", Vec::new())]),
+                    node(5, "a/b", "", Vec::new()),
+                ],
+            ),
+            resources: BTreeMap::new(),
+        };
+        assert_eq!(resolve_node_path("path:Root/X/Y", &document, 1), 4);
+        assert_eq!(resolve_node_path(r"path:Root/a\/b", &document, 1), 5);
+        assert_eq!(resolve_node_path("path:Root/a/b", &document, 1), 0);
+        assert_eq!(
+            relative_path(&document.root_node, 4, 5).unwrap(),
+            r"../../a\/b"
+        );
+        assert_eq!(relative_path(&document.root_node, 1, 4).unwrap(), "./X/Y");
+    }
+
+    #[test]
+    fn renaming_rewrites_references_that_break() {
+        let document = document();
+        let renamed =
+            DocumentModification::EditNode(2, node(2, "Z", &content(&document, 2), Vec::new()));
+        let renamed = modify_document(
+            document.clone(),
+            with_rewritten_references(&document, renamed),
+        );
+        assert_eq!(content(&renamed, 2), reference("path:Root/Z/A1"));
+        assert_eq!(content(&renamed, 3), reference("path:Root/Z/A1/A1x"));
+        assert_eq!(content(&renamed, 5), reference("sibling:Z"));
+        // References by id don't break
+        assert_eq!(content(&renamed, 4), content(&document, 4));
+        assert_eq!(content(&renamed, 6), content(&document, 6));
+    }
+
+    #[test]
+    fn moved_nodes_keep_relative_references_relative() {
+        let before = Document {
+            root_node: node(
+                1,
+                "Root",
+                "",
+                vec![
+                    node(2, "A", &reference("path:../B"), Vec::new()),
+                    node(
+                        3,
+                        "B",
+                        &reference("child:C"),
+                        vec![node(4, "C", "", Vec::new())],
+                    ),
+                ],
+            ),
+            resources: BTreeMap::new(),
+        };
+        // A is moved into C, B is renamed
+        let mut after = before.clone();
+        let a = after.root_node.children.remove(0);
+        after.root_node.children[0].name = "D".to_string();
+        after.root_node.children[0].children[0].children.push(a);
+        let after = modify_document(
+            after.clone(),
+            DocumentModification::Batch(rewrite_references(&before, &after, &HashMap::new())),
+        );
+        assert_eq!(content(&after, 2), reference("path:../.."));
+        assert_eq!(content(&after, 3), reference("child:C"));
+    }
+
+    #[test]
+    fn extracted_documents_keep_references_between_their_nodes() {
+        let extracted = extract_document(&document(), 2).unwrap();
+        assert_eq!(extracted.root_node.name, "A");
+        assert_eq!(content(&extracted, 2), reference("path:A/A1"));
+        assert_eq!(content(&extracted, 3), reference("path:A/A1/A1x"));
+        assert!(extract_document(&document(), 99).is_none());
+    }
+
+    #[test]
+    fn pasted_references_point_to_the_copies() {
+        let document = document();
+        let copied = extract_document(&document, 2).unwrap();
+        let copy = randomize_node(&copied.root_node);
+        let pasted = modify_document(
+            document.clone(),
+            DocumentModification::PasteNode(copy.clone(), 6),
+        );
+        let ids = map_node_ids(&copied.root_node, &copy);
+        let modifications = rewrite_pasted_references(&copied, &pasted, &ids, copy.id);
+        let pasted = modify_document(pasted, DocumentModification::Batch(modifications));
+        let copy_a1 = ids[&3];
+        assert_eq!(content(&pasted, copy.id), reference("path:Root/C/A/A1"));
+        assert_eq!(
+            content(&pasted, ids[&4]),
+            reference(&format!("id:{copy_a1}"))
+        );
+        // Nodes outside of the pasted one are not changed, even with the same ids
+        assert_eq!(content(&pasted, 2), content(&document, 2));
+        assert_eq!(content(&pasted, 6), content(&document, 6));
+    }
+
+    #[test]
+    fn references_to_names_with_quotes_use_ids() {
+        let document = document();
+        let renamed = DocumentModification::EditNode(3, node(3, "\"A1\"", "", Vec::new()));
+        let renamed = modify_document(
+            document.clone(),
+            with_rewritten_references(&document, renamed),
+        );
+        assert_eq!(content(&renamed, 2), reference("id:3"));
+        assert_eq!(resolve_node_path("id:3", &renamed, 2), 3);
+    }
+}
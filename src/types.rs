@@ -1,11 +1,14 @@
 use std::{collections::BTreeMap, path::PathBuf};
 
-use crate::utils::*;
+use crate::{formats::ExportFormat, utils::*};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 pub const RESOURCES_FILTER: &str = "Images\t*.{png,jpg,jpeg,svg,gif}";
 pub const RTD_FILTER: &str = "Rustree document\t*.rtd";
+pub const JSON_FILTER: &str = "JSON document\t*.json";
+pub const OPML_FILTER: &str = "OPML outline\t*.{opml,xml}";
+pub const ORG_FILTER: &str = "Org document\t*.org";
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum Theme {
@@ -111,15 +114,34 @@ impl Document {
     pub fn get_node(self, id: u32) -> Option<Node> {
         get_node_by_id(self.root_node, id)
     }
+
+    /// Creates a document from the node with only the resources it uses
+    pub fn node_document(&self, node: Node) -> Document {
+        let names = get_node_resources_names(&node);
+        Document {
+            resources: self
+                .resources
+                .iter()
+                .filter(|(name, _)| names.contains(*name))
+                .map(|(name, res)| (name.clone(), res.clone()))
+                .collect(),
+            root_node: node,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Node {
     pub name: String,
+    #[serde(default)]
     pub content: String,
+    #[serde(default)]
     pub children: Vec<Node>,
+    /// Properties of the node imported from other formats (e.g. Org-mode properties drawers)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
 
-    #[serde(skip_serializing)]
+    #[serde(default = "Node::generate_id")]
     pub id: u32,
 }
 
@@ -129,6 +151,7 @@ impl Node {
             name,
             content,
             children,
+            metadata: BTreeMap::new(),
             id: Node::generate_id(),
         }
     }
@@ -152,6 +175,73 @@ pub enum TreeExportType {
 
     JsonNode,
     JsonDoc,
+
+    OpmlNode,
+    OpmlDoc,
+
+    EpubNode,
+    EpubDoc,
+
+    LatexNode,
+    LatexDoc,
+
+    DocxNode,
+    DocxDoc,
+
+    PdfNode,
+    PdfDoc,
+
+    OrgNode,
+    OrgDoc,
+}
+
+impl TreeExportType {
+    pub fn format(self) -> ExportFormat {
+        match self {
+            TreeExportType::RtdNode => ExportFormat::Rtd,
+            TreeExportType::TextNode | TreeExportType::TextDoc => ExportFormat::Text,
+            TreeExportType::HtmlNode | TreeExportType::HtmlDoc => ExportFormat::Html,
+            TreeExportType::MdNode | TreeExportType::MdDoc => ExportFormat::Markdown,
+            TreeExportType::JsonNode | TreeExportType::JsonDoc => ExportFormat::Json,
+            TreeExportType::OpmlNode | TreeExportType::OpmlDoc => ExportFormat::Opml,
+            TreeExportType::EpubNode | TreeExportType::EpubDoc => ExportFormat::Epub,
+            TreeExportType::LatexNode | TreeExportType::LatexDoc => ExportFormat::Latex,
+            TreeExportType::DocxNode | TreeExportType::DocxDoc => ExportFormat::Docx,
+            TreeExportType::PdfNode | TreeExportType::PdfDoc => ExportFormat::Pdf,
+            TreeExportType::OrgNode | TreeExportType::OrgDoc => ExportFormat::Org,
+        }
+    }
+
+    /// Whether the whole document is exported instead of the selected node
+    pub fn is_document(self) -> bool {
+        matches!(
+            self,
+            TreeExportType::TextDoc
+                | TreeExportType::HtmlDoc
+                | TreeExportType::MdDoc
+                | TreeExportType::JsonDoc
+                | TreeExportType::OpmlDoc
+                | TreeExportType::EpubDoc
+                | TreeExportType::LatexDoc
+                | TreeExportType::DocxDoc
+                | TreeExportType::PdfDoc
+                | TreeExportType::OrgDoc
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum TreeImportType {
+    /// Open as a new document
+    OpmlDoc,
+    /// Import into the selected node
+    OpmlNode,
+
+    JsonDoc,
+    JsonNode,
+
+    OrgDoc,
+    OrgNode,
 }
 
 #[derive(Clone, Copy)]
@@ -177,14 +267,30 @@ pub enum Message {
     EditResource,
 
     ExportNode(TreeExportType),
+    ImportNode(TreeImportType),
     SaveAs,
     SaveDocument,
+    NewDocument,
     OpenDocument,
+    CloseTab,
+    SelectTab(usize),
+    OpenRecentDocument(usize),
+    ClearRecentDocuments,
 
     CopyNode,
     PasteNode,
     CutNode,
 
+    ShowSearch,
+    Search,
+    OpenSearchResult,
+    ShowReplace,
+    QuickOpen,
+    CompleteReference,
+    OpenBacklink,
+    CheckDocument,
+    OpenProblem,
+
     OpenSettings,
     UpdateSettings,
     ResetShortcuts,
@@ -1,8 +1,9 @@
-use crate::types::{Document, Node};
-use fltk::{
-    app, button::ShortcutButton, dialog, enums::*, prelude::*, tree::TreeItem, window::Window,
+use crate::{
+    text_processor::find_resources,
+    types::{Document, Node, Resource},
 };
 use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 /// Creates an array of paths of all nodes
 pub fn node_to_tree_vec(
@@ -33,18 +34,6 @@ pub fn node_to_tree_vec(
     new_vec
 }
 
-/// Creates an array of paths of children nodes
-pub fn get_tree_item_path(tree_item: TreeItem, last_vec: Vec<String>) -> Vec<String> {
-    let mut result = last_vec.clone();
-    if !tree_item.is_root() {
-        result.push(tree_item.label().unwrap());
-        if let Some(parent) = tree_item.parent() {
-            result.extend(get_tree_item_path(parent, last_vec.clone()));
-        }
-    }
-    result
-}
-
 /// Finds node by id
 pub fn get_node_by_id(node: Node, id: u32) -> Option<Node> {
     if node.id == id {
@@ -60,24 +49,78 @@ pub fn get_node_by_id(node: Node, id: u32) -> Option<Node> {
     None
 }
 
-pub enum SaveDialogAnswer {
-    Yes,
-    No,
-    Cancel,
+/// Finds node by id without cloning it
+pub fn find_node_ref(node: &Node, id: u32) -> Option<&Node> {
+    if node.id == id {
+        return Some(node);
+    }
+    node.children
+        .iter()
+        .find_map(|child| find_node_ref(child, id))
+}
+
+/// Finds the parent of the node with id without cloning it
+pub fn find_parent_ref(node: &Node, id: u32) -> Option<&Node> {
+    if node.children.iter().any(|child| child.id == id) {
+        return Some(node);
+    }
+    node.children
+        .iter()
+        .find_map(|child| find_parent_ref(child, id))
+}
+
+/// Gets names of the resources used by the node and its children
+pub fn get_node_resources_names(node: &Node) -> BTreeSet<String> {
+    let mut names = find_resources(&node.content)
+        .into_iter()
+        .collect::<BTreeSet<_>>();
+    for child in &node.children {
+        names.extend(get_node_resources_names(child));
+    }
+    names
+}
+
+/// Renames resource in resource!() macros of the node and its children
+pub fn rename_resource_in_node(node: Node, old_name: &str, new_name: &str) -> Node {
+    Node {
+        content: node.content.replace(
+            &format!("resource!(\"{old_name}\")"),
+            &format!("resource!(\"{new_name}\")"),
+        ),
+        children: node
+            .children
+            .into_iter()
+            .map(|n| rename_resource_in_node(n, old_name, new_name))
+            .collect(),
+        ..node
+    }
 }
 
-pub fn ask_save_dialog(text: &str) -> SaveDialogAnswer {
-    if let Some(answer) = dialog::choice2_default(text, "Yes", "No", "Cancel") {
-        if answer == 2 {
-            SaveDialogAnswer::Cancel
-        } else if answer == 0 {
-            SaveDialogAnswer::Yes
-        } else {
-            SaveDialogAnswer::No
+/// Adds resources of another document to the document and returns its root node.
+/// Resources with the same name but different bytes are renamed
+pub fn merge_resources(document: &mut Document, other: Document) -> Node {
+    let mut node = other.root_node;
+    for (name, res) in other.resources {
+        match document.resources.get(&name) {
+            Some(existing) if existing.bytes == res.bytes => {}
+            Some(_) => {
+                let mut index = 2;
+                while document
+                    .resources
+                    .contains_key(&format!("{name} ({index})"))
+                {
+                    index += 1;
+                }
+                let new_name = format!("{name} ({index})");
+                node = rename_resource_in_node(node, &name, &new_name);
+                document.resources.insert(new_name, res);
+            }
+            None => {
+                document.resources.insert(name, res);
+            }
         }
-    } else {
-        SaveDialogAnswer::Cancel
     }
+    node
 }
 
 #[derive(Clone, Debug)]
@@ -97,8 +140,15 @@ pub enum DocumentModification {
     CreateNode(u32, u32),
     /// Move node with it up or down
     MoveNode(u32, MoveDirection),
-    /// Paste node (copied node, where paste node)
+    /// Paste node (copied node, where paste node). Ids of the node are kept,
+    /// so a copy needs new ones (`randomize_node`)
     PasteNode(Node, u32),
+    /// Add resource with name or replace the existing one
+    AddResource(String, Resource),
+    /// Delete resource with name
+    DeleteResource(String),
+    /// Modifications applied one after another as a single action
+    Batch(Vec<DocumentModification>),
 }
 
 /// Checks if there is a node with the same name
@@ -164,6 +214,7 @@ fn modify_node(node: Node, modification: DocumentModification, root: Node) -> No
                             name: format!("New node ({})", rand::thread_rng().gen::<u16>()),
                             children: Vec::new(),
                             content: String::new(),
+                            metadata: BTreeMap::new(),
                             id: new_id,
                         }])
                         .cloned()
@@ -223,18 +274,12 @@ fn modify_node(node: Node, modification: DocumentModification, root: Node) -> No
                 let rand_num = &rand::thread_rng().gen::<u16>().to_string();
                 children.push(Node {
                     name: name.clone()
-                        + if find_node_with_same_name_in_same_parent_node(
-                            node.clone(),
-                            copied.id,
-                            name.clone(),
-                        )
-                        .is_some()
-                        {
+                        + if node.children.iter().any(|n| n.name == name) {
                             rand_num
                         } else {
                             ""
                         },
-                    ..randomize_node(&copied)
+                    ..copied
                 });
                 Node { children, ..node }
             } else {
@@ -248,14 +293,38 @@ fn modify_node(node: Node, modification: DocumentModification, root: Node) -> No
                 }
             }
         }
+        DocumentModification::AddResource(..)
+        | DocumentModification::DeleteResource(_)
+        | DocumentModification::Batch(_) => node,
     }
 }
 
 /// Modify document
 pub fn modify_document(document: Document, modification: DocumentModification) -> Document {
-    Document {
-        root_node: modify_node(document.root_node.clone(), modification, document.root_node),
-        ..document
+    match modification {
+        DocumentModification::AddResource(name, res) => {
+            let mut resources = document.resources;
+            resources.insert(name, res);
+            Document {
+                resources,
+                ..document
+            }
+        }
+        DocumentModification::DeleteResource(name) => {
+            let mut resources = document.resources;
+            resources.remove(&name);
+            Document {
+                resources,
+                ..document
+            }
+        }
+        DocumentModification::Batch(modifications) => {
+            modifications.into_iter().fold(document, modify_document)
+        }
+        _ => Document {
+            root_node: modify_node(document.root_node.clone(), modification, document.root_node),
+            ..document
+        },
     }
 }
 
@@ -284,34 +353,29 @@ pub fn randomize_node(node: &Node) -> Node {
     }
 }
 
-pub fn ask_shortcut(start_shortcut: Shortcut) -> Shortcut {
-    let (s, r) = app::channel::<bool>();
-    let mut clicks = 0;
-    let mut win = Window::new(100, 100, 300, 200, "Shortcut");
-    let mut btn = ShortcutButton::default().with_size(win.w(), win.h());
-    win.make_modal(true);
-    btn.set_label_size(30);
-    btn.set_down_frame(fltk::enums::FrameType::NoBox);
-    btn.set_label("Click to start");
-    win.end();
-    win.show();
-    btn.handle(move |e, ev| {
-        if ev == Event::Push {
-            clicks += 1;
-            if clicks == 2 {
-                s.send(true);
-            } else {
-                e.window().unwrap().set_label("Click to end");
-                e.set_value(start_shortcut);
-            }
-        }
-        false
-    });
-    while app::wait() && win.visible() {
-        if r.recv().is_some() {
-            win.hide();
-            return btn.value();
-        }
+/// Maps ids of the node and its children to ids of their copy from `randomize_node`
+pub fn map_node_ids(node: &Node, copy: &Node) -> HashMap<u32, u32> {
+    let mut ids = HashMap::from([(node.id, copy.id)]);
+    for (child, child_copy) in node.children.iter().zip(&copy.children) {
+        ids.extend(map_node_ids(child, child_copy));
     }
-    start_shortcut
+    ids
+}
+
+/// Renames children with the same name (a document can't have them)
+pub fn make_children_names_unique(children: Vec<Node>) -> Vec<Node> {
+    let mut names: Vec<String> = Vec::new();
+    children
+        .into_iter()
+        .map(|child| {
+            let mut name = child.name.clone();
+            let mut index = 2;
+            while names.contains(&name) {
+                name = format!("{} ({index})", child.name);
+                index += 1;
+            }
+            names.push(name.clone());
+            Node { name, ..child }
+        })
+        .collect()
 }